@@ -0,0 +1,12 @@
+#![no_main]
+
+use dns_starter_rust::Message;
+use libfuzzer_sys::fuzz_target;
+
+// Whatever arrives on the wire, parsing may fail but must never panic. A
+// message that does parse must also survive being written back out.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = Message::parse(data) {
+        let _ = msg.to_bytes();
+    }
+});
@@ -0,0 +1,36 @@
+use std::env;
+use std::net::UdpSocket;
+
+// systemd hands over sockets starting at this descriptor
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// The socket passed in by systemd socket activation, if we were started
+/// that way: `LISTEN_PID` names this process and `LISTEN_FDS` counts at
+/// least one descriptor. Only the first one is used. The variables are
+/// cleared so they don't leak into anything we might spawn.
+#[cfg(unix)]
+pub fn listen_socket() -> Option<UdpSocket> {
+    use std::os::unix::io::FromRawFd;
+
+    let pid: u32 = env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: u32 = env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if pid != std::process::id() || fds == 0 {
+        return None;
+    }
+    if fds > 1 {
+        warn!("systemd passed {} sockets, only the first is used", fds);
+    }
+
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    // systemd guarantees the descriptor is open and ours to own
+    Some(unsafe { UdpSocket::from_raw_fd(SD_LISTEN_FDS_START) })
+}
+
+#[cfg(not(unix))]
+pub fn listen_socket() -> Option<UdpSocket> {
+    None
+}
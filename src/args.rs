@@ -0,0 +1,266 @@
+use crate::blocklist::Blocklist;
+use crate::cache::{Cache, DEFAULT_CAPACITY};
+use crate::cookie::Cookies;
+use crate::doh::DohResolver;
+use crate::hosts::Hosts;
+use crate::ratelimit::parse_rate_limit;
+use crate::recursor::Recursor;
+use crate::rewrite::parse_rewrite;
+use crate::rpz::Policy;
+use crate::server::Config;
+use crate::upstream::{parse_resolver_spec_with_port, Protocol, Upstreams, DEFAULT_PORT};
+use crate::zone::Zone;
+use crate::{config_file, logging, preload};
+
+const DEFAULT_CNAME_CHASE_DEPTH: usize = 8;
+// options that add up when repeated rather than replace each other
+const REPEATABLE: [&str; 3] = ["--resolver", "--root", "--rewrite"];
+
+/// Builds the server's configuration from its command-line options, program
+/// name excluded. Files named by options (zone, hosts, ...) are loaded on
+/// the way, and any problem comes back as a message for the usage text.
+///
+/// With `--config path` the settings in that file apply first, wherever the
+/// option appears, and options on the command line override them. For the
+/// repeatable options (`--resolver`, `--root`, `--rewrite`) that means any given on
+/// the command line replace all of the file's.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Config, String> {
+    let args: Vec<String> = args.into_iter().collect();
+
+    let mut config_files = args.iter().enumerate().filter(|(_, arg)| *arg == "--config");
+    let file_args = match (config_files.next(), config_files.next()) {
+        (None, _) => Vec::new(),
+        (Some(_), Some(_)) => return Err("--config can only be given once".to_string()),
+        (Some((i, _)), None) => {
+            let path = args.get(i + 1).ok_or("Missing value for --config")?;
+            let settings = config_file::load(path).map_err(|e| format!("Failed to load config file: {}", e))?;
+            settings.into_iter()
+                .filter(|(flag, _)| !(REPEATABLE.contains(&&flag[..]) && args.contains(flag)))
+                .flat_map(|(flag, value)| std::iter::once(flag).chain(value))
+                .collect()
+        }
+    };
+
+    parse_options(file_args.into_iter().chain(args))
+}
+
+fn parse_options<I: Iterator<Item = String>>(mut args: I) -> Result<Config, String> {
+    let mut config = Config::default();
+    // parsed once all options are in, as --upstream-port may come later
+    let mut resolvers = Vec::new();
+    let mut upstream_port = DEFAULT_PORT;
+    let mut cache = false;
+    let mut cache_size = None;
+    let mut retries = None;
+    let mut protocol = Protocol::Udp;
+    let mut roots = Vec::new();
+    let mut minimize = false;
+    let mut randomize_case = false;
+
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--resolver" => match args.next() {
+                Some(resolver) => resolvers.push(resolver),
+                None => return Err("Missing value for --resolver".to_string()),
+            },
+            "--root" => match args.next().map(|ip| ip.parse()) {
+                Some(Ok(ip)) => roots.push(ip),
+                Some(Err(_)) => return Err("Invalid IP address for --root".to_string()),
+                None => return Err("Missing value for --root".to_string()),
+            },
+            "--qname-minimization" => minimize = true,
+            "--randomize-case" => randomize_case = true,
+            "--upstream-port" => match args.next().map(|port| port.parse()) {
+                Some(Ok(port)) => upstream_port = port,
+                Some(Err(_)) => return Err("Invalid value for --upstream-port".to_string()),
+                None => return Err("Missing value for --upstream-port".to_string()),
+            },
+            "--cache" => cache = true,
+            "--cache-size" => match args.next().map(|size| size.parse()) {
+                Some(Ok(size)) if size > 0 => cache_size = Some(size),
+                Some(_) => return Err("Invalid value for --cache-size".to_string()),
+                None => return Err("Missing value for --cache-size".to_string()),
+            },
+            // already read by parse()
+            "--config" => {
+                args.next();
+            }
+            "--cookies" => config.cookies = Some(Cookies::default()),
+            "--stdio" => config.stdio = true,
+            "--strict" => config.strict = true,
+            "--deny-recursion" => config.deny_recursion = true,
+            "--strip-edns" => config.strip_edns = true,
+            "--answer-section-only" => config.answer_section_only = true,
+            "--no-compression" => config.no_compression = true,
+            "--shuffle-answers" => config.shuffle_answers = true,
+            "--chase-cnames" => config.cname_chase_depth = config.cname_chase_depth.or(Some(DEFAULT_CNAME_CHASE_DEPTH)),
+            "--max-cname-depth" => match args.next().map(|depth| depth.parse()) {
+                Some(Ok(depth)) => config.cname_chase_depth = Some(depth),
+                Some(Err(_)) => return Err("Invalid value for --max-cname-depth".to_string()),
+                None => return Err("Missing value for --max-cname-depth".to_string()),
+            },
+            "--corrupt-ancount" => match args.next().map(|delta| delta.parse()) {
+                Some(Ok(delta)) => config.corrupt_ancount = Some(delta),
+                Some(Err(_)) => return Err("Invalid value for --corrupt-ancount".to_string()),
+                None => return Err("Missing value for --corrupt-ancount".to_string()),
+            },
+            "--blocklist" => match args.next() {
+                Some(path) => match Blocklist::load(&path) {
+                    Ok(blocklist) => config.blocklist = Some(blocklist),
+                    Err(e) => return Err(format!("Failed to load blocklist: {}", e)),
+                },
+                None => return Err("Missing value for --blocklist".to_string()),
+            },
+            "--rate-limit" => match args.next().map(|spec| parse_rate_limit(&spec)) {
+                Some(Ok(limiter)) => config.rate_limiter = Some(limiter),
+                Some(Err(e)) => return Err(e),
+                None => return Err("Missing value for --rate-limit".to_string()),
+            },
+            "--rewrite" => match args.next().map(|spec| parse_rewrite(&spec)) {
+                Some(Ok((from, to))) => config.rewrites.add(from, to),
+                Some(Err(e)) => return Err(e),
+                None => return Err("Missing value for --rewrite".to_string()),
+            },
+            "--ttl" => match args.next().map(|ttl| ttl.parse()) {
+                Some(Ok(ttl)) => config.ttl = ttl,
+                Some(Err(_)) => return Err("Invalid value for --ttl, expected 0 to 4294967295 seconds".to_string()),
+                None => return Err("Missing value for --ttl".to_string()),
+            },
+            "--default-a" => match args.next().map(|ip| ip.parse()) {
+                Some(Ok(ip)) => config.default_a = ip,
+                Some(Err(_)) => return Err("Invalid IPv4 address for --default-a".to_string()),
+                None => return Err("Missing value for --default-a".to_string()),
+            },
+            "--default-aaaa" => match args.next().map(|ip| ip.parse()) {
+                Some(Ok(ip)) => config.default_aaaa = ip,
+                Some(Err(_)) => return Err("Invalid IPv6 address for --default-aaaa".to_string()),
+                None => return Err("Missing value for --default-aaaa".to_string()),
+            },
+            "--listen" => match args.next().map(|ip| ip.parse()) {
+                Some(Ok(ip)) => config.listen = ip,
+                Some(Err(_)) => return Err("Invalid IP address for --listen".to_string()),
+                None => return Err("Missing value for --listen".to_string()),
+            },
+            "--port" => match args.next().map(|port| port.parse()) {
+                Some(Ok(port)) => config.port = port,
+                Some(Err(_)) => return Err("Invalid value for --port".to_string()),
+                None => return Err("Missing value for --port".to_string()),
+            },
+            "--buffer-size" => match args.next().map(|size| size.parse()) {
+                Some(Ok(size)) if size > 0 => config.buffer_size = size,
+                Some(_) => return Err("Invalid value for --buffer-size".to_string()),
+                None => return Err("Missing value for --buffer-size".to_string()),
+            },
+            "--max-records" => match args.next().map(|n| n.parse()) {
+                Some(Ok(n)) => config.max_records = n,
+                Some(Err(_)) => return Err("Invalid value for --max-records".to_string()),
+                None => return Err("Missing value for --max-records".to_string()),
+            },
+            "--metrics-port" => match args.next().map(|port| port.parse()) {
+                Some(Ok(port)) => config.metrics_port = Some(port),
+                Some(Err(_)) => return Err("Invalid value for --metrics-port".to_string()),
+                None => return Err("Missing value for --metrics-port".to_string()),
+            },
+            "--retries" => match args.next().map(|n| n.parse()) {
+                Some(Ok(n)) => retries = Some(n),
+                Some(Err(_)) => return Err("Invalid value for --retries".to_string()),
+                None => return Err("Missing value for --retries".to_string()),
+            },
+            "--forward-protocol" => match args.next().map(|protocol| protocol.parse()) {
+                Some(Ok(value)) => protocol = value,
+                Some(Err(e)) => return Err(e),
+                None => return Err("Missing value for --forward-protocol".to_string()),
+            },
+            "--zone" => match args.next() {
+                Some(path) => match Zone::load(&path) {
+                    Ok(zone) => config.zone = Some(zone),
+                    Err(e) => return Err(format!("Failed to load zone file: {}", e)),
+                },
+                None => return Err("Missing value for --zone".to_string()),
+            },
+            "--log-format" => match args.next().map(|format| format.parse()) {
+                Some(Ok(format)) => config.log_format = format,
+                Some(Err(e)) => return Err(e),
+                None => return Err("Missing value for --log-format".to_string()),
+            },
+            "--rpz" => match args.next() {
+                Some(path) => match Policy::load(&path) {
+                    Ok(policy) => config.policy = Some(policy),
+                    Err(e) => return Err(format!("Failed to load policy file: {}", e)),
+                },
+                None => return Err("Missing value for --rpz".to_string()),
+            },
+            "--preload" => match args.next() {
+                Some(path) => match preload::load(&path) {
+                    Ok(questions) => config.preload = questions,
+                    Err(e) => return Err(format!("Failed to load preload file: {}", e)),
+                },
+                None => return Err("Missing value for --preload".to_string()),
+            },
+            "--unix-socket" => match args.next() {
+                Some(path) => config.unix_socket = Some(path),
+                None => return Err("Missing value for --unix-socket".to_string()),
+            },
+            "--hosts" => match args.next() {
+                Some(path) => match Hosts::load(&path) {
+                    Ok(hosts) => config.hosts = Some(hosts),
+                    Err(e) => return Err(format!("Failed to load hosts file: {}", e)),
+                },
+                None => return Err("Missing value for --hosts".to_string()),
+            },
+            "--health-name" => match args.next() {
+                Some(name) => config.health_name = name,
+                None => return Err("Missing value for --health-name".to_string()),
+            },
+            "--chaos-txt" => match args.next() {
+                Some(text) => config.chaos_txt = Some(text),
+                None => return Err("Missing value for --chaos-txt".to_string()),
+            },
+            "--refuse-chaos" => config.chaos_txt = None,
+            "-v" | "--verbose" => config.log_level = Some(logging::Level::Debug),
+            "-vv" => config.log_level = Some(logging::Level::Trace),
+            _ => return Err(format!("Unrecognized option {}", arg)),
+        }
+    }
+
+    // --cache-size alone turns the cache on too
+    if cache || cache_size.is_some() {
+        config.cache = Some(Cache::new().with_capacity(cache_size.unwrap_or(DEFAULT_CAPACITY)));
+    }
+
+    if !roots.is_empty() {
+        if !resolvers.is_empty() {
+            return Err("--root and --resolver can't be combined".to_string());
+        }
+        let recursor = Recursor::new(roots).with_port(upstream_port).with_qname_minimization(minimize);
+        config.upstreams = Some(Box::new(recursor));
+    } else if minimize {
+        return Err("--qname-minimization needs --root".to_string());
+    } else if resolvers.iter().any(|resolver| DohResolver::is_url(resolver)) {
+        let [url] = &resolvers[..] else {
+            return Err("A DoH resolver must be the only --resolver".to_string());
+        };
+        config.upstreams = Some(Box::new(DohResolver::new(url)?));
+    } else if !resolvers.is_empty() {
+        let resolvers = resolvers.iter()
+            .map(|resolver| parse_resolver_spec_with_port(resolver, upstream_port))
+            .collect::<Result<_, _>>()?;
+        let mut upstreams = Upstreams::weighted(resolvers)
+            .with_protocol(protocol)
+            .with_case_randomization(randomize_case);
+        if let Some(retries) = retries {
+            upstreams = upstreams.with_retries(retries);
+        }
+        config.upstreams = Some(Box::new(upstreams));
+    }
+
+    if randomize_case && resolvers.iter().all(|resolver| DohResolver::is_url(resolver)) {
+        return Err("--randomize-case needs a --resolver address".to_string());
+    }
+
+    if !config.preload.is_empty() && (config.cache.is_none() || config.upstreams.is_none()) {
+        return Err("--preload needs --cache and a --resolver".to_string());
+    }
+
+    Ok(config)
+}
@@ -0,0 +1,44 @@
+use std::collections::HashSet;
+use std::fs;
+
+use crate::Name;
+
+/// Names to refuse resolving, loaded from a file with one name per line
+/// (`#` starts a comment). Blocking a name also blocks everything below it,
+/// so `example.com` covers `www.example.com` too. Matching ignores case.
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    names: HashSet<String>,
+}
+
+impl Blocklist {
+    pub fn load(path: &str) -> std::io::Result<Blocklist> {
+        let contents = fs::read_to_string(path)?;
+        let names = contents.lines()
+            .map(|line| line.split('#').next().unwrap().trim())
+            .filter(|line| !line.is_empty());
+        Ok(Blocklist::new(names))
+    }
+
+    pub fn new<'a, I: IntoIterator<Item = &'a str>>(names: I) -> Blocklist {
+        let names = names.into_iter()
+            .map(Name::key)
+            .collect();
+        Blocklist { names }
+    }
+
+    pub fn is_blocked(&self, name: &Name) -> bool {
+        let name = name.canonical();
+        let mut suffix = &name[..];
+
+        loop {
+            if self.names.contains(suffix) {
+                return true;
+            }
+            match suffix.split_once('.') {
+                Some((_, parent)) => suffix = parent,
+                None => return false,
+            }
+        }
+    }
+}
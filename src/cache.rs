@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{dump, Answer, Message, MessageType, QClass, QType, ResourceType, ResponseCode};
+
+/// Forwarded responses, kept for as long as their records say they may be.
+/// Positive answers live for the smallest TTL among them. Negative ones
+/// (NXDOMAIN, or NOERROR without answers) live for the SOA's TTL capped by
+/// its MINIMUM field, as RFC 2308 specifies; without an SOA there is no
+/// way to tell, so they aren't cached. Safe to share between threads.
+///
+/// At most `capacity` entries are held; inserting into a full cache evicts
+/// the least recently used one.
+#[derive(Debug)]
+pub struct Cache {
+    entries: Mutex<Lru>,
+    capacity: usize,
+}
+
+/// How many entries a cache holds unless told otherwise.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+// name, type and class, plus whether DNSSEC records were asked for
+type Key = (String, u16, u16, bool);
+
+#[derive(Debug)]
+struct Entry {
+    rcode: u8,
+    answers: Vec<Answer>,
+    name_servers: Vec<Answer>,
+    stored: Instant,
+    expires: Instant,
+}
+
+/// The entries in order of use, as a doubly linked list threaded through
+/// a slab so that finding, touching and evicting an entry are all O(1).
+#[derive(Debug, Default)]
+struct Lru {
+    index: HashMap<Key, usize>,
+    slots: Vec<Option<Slot>>,
+    // slots emptied by removals, reused before the slab grows
+    free: Vec<usize>,
+    // most and least recently used
+    head: Option<usize>,
+    tail: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Slot {
+    key: Key,
+    entry: Entry,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache { entries: Mutex::new(Lru::default()), capacity: DEFAULT_CAPACITY }
+    }
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::default()
+    }
+
+    /// Caps the cache at `capacity` entries instead of DEFAULT_CAPACITY.
+    pub fn with_capacity(mut self, capacity: usize) -> Cache {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Builds the reply to a single-question `query` from the cache, with
+    /// every TTL reduced by the time the entry has been held. The additional
+    /// section is left empty; the server adds its own OPT, as it does to
+    /// forwarded replies.
+    pub fn get(&self, query: &Message) -> Option<Message> {
+        let key = Cache::key(query)?;
+        let now = Instant::now();
+
+        let mut entries = self.entries.lock().unwrap();
+        let expired = entries.get(&key)?.expires <= now;
+        if expired {
+            entries.remove(&key);
+            return None;
+        }
+        let entry = entries.get(&key)?;
+
+        let age = now.duration_since(entry.stored).as_secs() as u32;
+        let aged = |records: &[Answer]| -> Vec<Answer> {
+            records.iter()
+                .map(|record| Answer { ttl: record.ttl.saturating_sub(age), ..record.clone() })
+                .collect()
+        };
+
+        let mut response = query.clone();
+        response.header.flags.qr = MessageType::Reply;
+        response.header.flags.rcode = entry.rcode;
+        response.answers = aged(&entry.answers);
+        response.name_servers = aged(&entry.name_servers);
+        response.additional.clear();
+        response.header.ancount = response.answers.len() as u16;
+        response.header.nscount = response.name_servers.len() as u16;
+        response.header.arcount = 0;
+        Some(response)
+    }
+
+    /// Remembers `response` to the single-question `query`, if it can be
+    /// cached. The entry is keyed on the query, so it is only served to
+    /// queries asking for the same DO bit.
+    pub fn insert(&self, query: &Message, response: &Message) {
+        let Some(key) = Cache::key(query) else {
+            return;
+        };
+        let Some(ttl) = Cache::ttl(response) else {
+            return;
+        };
+        if ttl == 0 {
+            return;
+        }
+
+        // no record may outlive the entry, e.g. a negative answer's SOA
+        // is served with the capped TTL
+        let capped = |records: &[Answer]| -> Vec<Answer> {
+            records.iter()
+                .map(|record| Answer { ttl: record.ttl.min(ttl), ..record.clone() })
+                .collect()
+        };
+
+        let now = Instant::now();
+        let entry = Entry {
+            rcode: response.header.flags.rcode,
+            answers: capped(&response.answers),
+            name_servers: capped(&response.name_servers),
+            stored: now,
+            expires: now + Duration::from_secs(ttl.into()),
+        };
+        self.entries.lock().unwrap().insert(key, entry, self.capacity);
+    }
+
+    /// How many entries the cache holds before evicting the least recently used.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The live entries, most recently used first, each as a line with the
+    /// question, RCODE and time left, followed by its records with the TTLs
+    /// they would be served with now.
+    pub fn dump(&self) -> String {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        let mut out = String::new();
+
+        let mut next = entries.head;
+        while let Some(index) = next {
+            let slot = entries.slots[index].as_ref().unwrap();
+            next = slot.next;
+            let ((name, rtype, class, _), entry) = (&slot.key, &slot.entry);
+            if entry.expires <= now {
+                continue;
+            }
+
+            let rtype = QType::try_from(*rtype).map_or(format!("TYPE{}", rtype), dump::type_name);
+            let class = QClass::try_from(*class).map_or(format!("CLASS{}", class), dump::class_name);
+            let left = entry.expires.duration_since(now).as_secs();
+            let _ = writeln!(out, ";; {}. {} {}, rcode {}, {}s left", name, class, rtype, entry.rcode, left);
+
+            let age = now.duration_since(entry.stored).as_secs() as u32;
+            for record in entry.answers.iter().chain(&entry.name_servers) {
+                let aged = Answer { ttl: record.ttl.saturating_sub(age), ..record.clone() };
+                let _ = writeln!(out, "{}", dump::describe_record(&aged));
+            }
+        }
+        out
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn key(msg: &Message) -> Option<Key> {
+        let [question] = &msg.questions[..] else {
+            return None;
+        };
+        Some((question.name.canonical(), question.rtype.into(), question.class.into(), msg.dnssec_ok()))
+    }
+
+    /// How long `response` may be cached, or `None` if it mustn't be.
+    fn ttl(response: &Message) -> Option<u32> {
+        let rcode = response.extended_rcode();
+        let negative = rcode == ResponseCode::NXDomain as u16
+            || (rcode == ResponseCode::NoError as u16 && response.answers.is_empty());
+
+        if negative {
+            let soa = response.name_servers.iter().find(|record| record.rtype == ResourceType::SOA)?;
+            return Some(soa.ttl.min(soa_minimum(soa)?));
+        }
+        if rcode != ResponseCode::NoError as u16 {
+            return None;
+        }
+        response.answers.iter().map(|answer| answer.ttl).min()
+    }
+}
+
+impl Lru {
+    /// The entry for `key`, marked as the most recently used.
+    fn get(&mut self, key: &Key) -> Option<&Entry> {
+        let slot = *self.index.get(key)?;
+        self.unlink(slot);
+        self.push_front(slot);
+        self.slots[slot].as_ref().map(|slot| &slot.entry)
+    }
+
+    /// Stores `entry` as the most recently used, first evicting the least
+    /// recently used entry if `key` is new and there are `capacity` already.
+    fn insert(&mut self, key: Key, entry: Entry, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        if self.index.len() >= capacity {
+            if let Some(oldest) = self.tail.and_then(|tail| self.slots[tail].as_ref()) {
+                let oldest = oldest.key.clone();
+                self.remove(&oldest);
+            }
+        }
+
+        let slot = Slot { key: key.clone(), entry, prev: None, next: None };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = Some(slot);
+                index
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() - 1
+            }
+        };
+        self.index.insert(key, index);
+        self.push_front(index);
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(slot) = self.index.remove(key) {
+            self.unlink(slot);
+            self.slots[slot] = None;
+            self.free.push(slot);
+        }
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let slot = self.slots[index].as_mut().unwrap();
+        let (prev, next) = (slot.prev.take(), slot.next.take());
+        match prev {
+            Some(prev) => self.slots[prev].as_mut().unwrap().next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.slots[next].as_mut().unwrap().prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    fn push_front(&mut self, index: usize) {
+        let old_head = self.head.replace(index);
+        self.slots[index].as_mut().unwrap().next = old_head;
+        match old_head {
+            Some(old_head) => self.slots[old_head].as_mut().unwrap().prev = Some(index),
+            None => self.tail = Some(index),
+        }
+    }
+}
+
+/// The MINIMUM field, the last of the SOA's RDATA. The fixed-size fields
+/// sit after the names, so they can be read from the end whatever the
+/// names look like.
+fn soa_minimum(soa: &Answer) -> Option<u32> {
+    let minimum = soa.rdata.last_chunk::<4>()?;
+    Some(u32::from_be_bytes(*minimum))
+}
@@ -0,0 +1,77 @@
+use std::net::TcpStream;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+use crate::server::forward_query;
+use crate::{tcp, Answer, Message, Name, QType, Question, ResourceClass, ResourceType, ResponseCode};
+
+const MAX_CNAME_CHAIN: usize = 8;
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Looks up `name` through `resolver` (an `ip:port` address) and returns
+/// every record in the answer sections. When the resolver only hands back
+/// a CNAME chain, the last target is queried in turn until a record of the
+/// requested type shows up.
+pub fn resolve(name: &str, rtype: ResourceType, resolver: &str) -> Result<Vec<Answer>> {
+    let mut answers = Vec::new();
+    let mut target = Name::new(name);
+
+    for _ in 0..MAX_CNAME_CHAIN {
+        let query = Message::query(rand::random(), Question { name: target.clone(), rtype: rtype.into(), class: ResourceClass::IN.into() });
+
+        let response = forward_query(&query, resolver)?;
+        if response.header.flags.rcode != ResponseCode::NoError as u8 {
+            bail!("resolver {} answered {} with rcode {}", resolver, target.name, response.header.flags.rcode);
+        }
+
+        let found = response.answers.iter().any(|answer| answer.rtype == rtype);
+        let next = response.answers.iter().rev().find_map(Answer::cname);
+        answers.extend(response.answers);
+
+        match next {
+            Some(next) if !found => target = next,
+            _ => return Ok(answers),
+        }
+    }
+
+    bail!("CNAME chain for {} is longer than {} hops", name, MAX_CNAME_CHAIN)
+}
+
+/// Transfers `zone` from `primary` (an `ip:port` address) with AXFR over
+/// TCP. The records arrive spread over any number of messages, starting
+/// with the zone's SOA and ending when that SOA comes around again; all of
+/// them, both SOAs included, are returned in order.
+pub fn transfer_zone(zone: &str, primary: &str) -> Result<Vec<Answer>> {
+    let mut stream = TcpStream::connect(primary)?;
+    stream.set_read_timeout(Some(TRANSFER_TIMEOUT))?;
+
+    let mut query = Message::query(rand::random(), Question { name: Name::new(zone), rtype: QType::AXFR, class: ResourceClass::IN.into() });
+    // a transfer is never recursive
+    query.header.flags.rd = 0;
+    tcp::send(&mut stream, &query)?;
+
+    let mut records: Vec<Answer> = Vec::new();
+    loop {
+        let response = tcp::recv(&mut stream)?;
+        if response.header.id != query.header.id {
+            bail!("primary {} answered with id {} instead of {}", primary, response.header.id, query.header.id);
+        }
+        if response.header.flags.rcode != ResponseCode::NoError as u8 {
+            bail!("primary {} refused to transfer {} with rcode {}", primary, zone, response.header.flags.rcode);
+        }
+
+        // SOA names arrive expanded, so the closing SOA matches the opening
+        // one however the primary chose to compress either
+        for answer in response.answers {
+            match records.first() {
+                None if answer.rtype != ResourceType::SOA => bail!("transfer of {} doesn't start with its SOA", zone),
+                Some(first) if answer.rtype == ResourceType::SOA && answer.rdata == first.rdata => {
+                    records.push(answer);
+                    return Ok(records);
+                }
+                _ => records.push(answer),
+            }
+        }
+    }
+}
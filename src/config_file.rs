@@ -0,0 +1,196 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+/// How a key in the file maps onto its command-line option.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Kind {
+    /// `--key value`
+    Value,
+    /// `--key value`, repeatable, so the file value may also be an array
+    List,
+    /// `--key` alone, given when the value is `true`
+    Switch,
+}
+
+// every option that makes sense in a file, named as on the command line
+const KEYS: [(&str, Kind); 40] = [
+    ("listen", Kind::Value),
+    ("port", Kind::Value),
+    ("unix-socket", Kind::Value),
+    ("buffer-size", Kind::Value),
+    ("max-records", Kind::Value),
+    ("metrics-port", Kind::Value),
+    ("resolver", Kind::List),
+    ("root", Kind::List),
+    ("qname-minimization", Kind::Switch),
+    ("upstream-port", Kind::Value),
+    ("retries", Kind::Value),
+    ("randomize-case", Kind::Switch),
+    ("forward-protocol", Kind::Value),
+    ("zone", Kind::Value),
+    ("hosts", Kind::Value),
+    ("health-name", Kind::Value),
+    ("chaos-txt", Kind::Value),
+    ("refuse-chaos", Kind::Switch),
+    ("blocklist", Kind::Value),
+    ("rpz", Kind::Value),
+    ("rate-limit", Kind::Value),
+    ("ttl", Kind::Value),
+    ("default-a", Kind::Value),
+    ("default-aaaa", Kind::Value),
+    ("shuffle-answers", Kind::Switch),
+    ("rewrite", Kind::List),
+    ("cache", Kind::Switch),
+    ("cache-size", Kind::Value),
+    ("preload", Kind::Value),
+    ("cookies", Kind::Switch),
+    ("strict", Kind::Switch),
+    ("deny-recursion", Kind::Switch),
+    ("strip-edns", Kind::Switch),
+    ("answer-section-only", Kind::Switch),
+    ("no-compression", Kind::Switch),
+    ("chase-cnames", Kind::Switch),
+    ("max-cname-depth", Kind::Value),
+    ("corrupt-ancount", Kind::Value),
+    ("log-format", Kind::Value),
+    ("verbose", Kind::Switch),
+];
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+/// Loads a `--config` file: a flat TOML document whose keys are the
+/// command-line options without their dashes, e.g.
+///
+/// ```text
+/// # forward to two resolvers, caching what they say
+/// port = 5353
+/// resolver = ["192.0.2.1", "192.0.2.2:53:3"]
+/// cache = true
+/// log-format = "json"
+/// ```
+///
+/// Switches take `true` or `false`, repeatable options a single value or
+/// an array. Tables and multi-line values aren't supported. The settings
+/// come back as the options they stand for, each with its value if it has
+/// one, so they are validated exactly like the command line.
+pub fn load(path: &str) -> std::io::Result<Vec<(String, Option<String>)>> {
+    let mut options = Vec::new();
+
+    for (lineno, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let invalid = |reason: String| Error::new(
+            ErrorKind::InvalidData,
+            format!("{}:{}: {}", path, lineno + 1, reason),
+        );
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(invalid("tables aren't supported".to_string()));
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(invalid("expected: key = value".to_string()));
+        };
+        let key = key.trim().trim_matches('"');
+        let Some(&(_, kind)) = KEYS.iter().find(|(name, _)| *name == key) else {
+            return Err(invalid(format!("unknown key {}", key)));
+        };
+        let value = parse_value(value).map_err(invalid)?;
+
+        let flag = format!("--{}", key);
+        match (kind, value) {
+            (Kind::Switch, Value::Boolean(true)) => options.push((flag, None)),
+            (Kind::Switch, Value::Boolean(false)) => {}
+            (Kind::Switch, _) => return Err(invalid(format!("{} takes true or false", key))),
+            (Kind::List, Value::Array(values)) => {
+                for value in values {
+                    options.push((flag.clone(), Some(scalar(value).map_err(&invalid)?)));
+                }
+            }
+            (_, value) => options.push((flag, Some(scalar(value).map_err(invalid)?))),
+        }
+    }
+
+    Ok(options)
+}
+
+/// A string or number as a command-line value.
+fn scalar(value: Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s),
+        Value::Integer(n) => Ok(n.to_string()),
+        Value::Boolean(_) | Value::Array(_) => Err("expected a string or a number".to_string()),
+    }
+}
+
+/// Parses the value after `=`, up to an optional trailing comment.
+fn parse_value(input: &str) -> Result<Value, String> {
+    let (value, rest) = parse_item(input.trim_start())?;
+    let rest = rest.trim_start();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected {} after the value", rest));
+    }
+    Ok(value)
+}
+
+fn parse_item(input: &str) -> Result<(Value, &str), String> {
+    if let Some(rest) = input.strip_prefix('"') {
+        return parse_string(rest);
+    }
+    if let Some(mut rest) = input.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(rest) = rest.strip_prefix(']') {
+                return Ok((Value::Array(items), rest));
+            }
+            let (item, after) = parse_item(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            match rest.strip_prefix(',') {
+                Some(after) => rest = after,
+                None if rest.starts_with(']') => {}
+                None => return Err("expected , or ] in array".to_string()),
+            }
+        }
+    }
+
+    let end = input.find(|c: char| c.is_whitespace() || matches!(c, ',' | ']' | '#')).unwrap_or(input.len());
+    let value = match &input[..end] {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        "" => return Err("missing value".to_string()),
+        token => match token.replace('_', "").parse() {
+            Ok(n) => Value::Integer(n),
+            Err(_) => return Err(format!("invalid value {}, strings need quotes", token)),
+        },
+    };
+    Ok((value, &input[end..]))
+}
+
+/// A basic string, its opening quote already consumed.
+fn parse_string(input: &str) -> Result<(Value, &str), String> {
+    let mut s = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((Value::String(s), &input[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('"') => s.push('"'),
+                Some('\\') => s.push('\\'),
+                Some('n') => s.push('\n'),
+                Some('t') => s.push('\t'),
+                _ => return Err("unsupported escape in string".to_string()),
+            },
+            c => s.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
@@ -0,0 +1,118 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::IpAddr;
+
+use crate::{Answer, Name, ResourceClass, ResourceType, ResponseCode};
+
+// the EDNS option code of a cookie
+const COOKIE: u16 = 10;
+/// The extended RCODE telling a client its server cookie is no good.
+pub const BADCOOKIE: u16 = 23;
+
+/// DNS Cookies (RFC 7873). A client puts a random client cookie in an EDNS
+/// option; we answer with it followed by a server cookie derived from it,
+/// the client's address and a secret only we know. Clients send the server
+/// cookie back from then on, so a query carrying a wrong one is refused
+/// with BADCOOKIE and a fresh cookie to use instead.
+///
+/// The server cookie is a SipHash (std's hasher) over the secret, which
+/// stops off-path guessing but isn't meant as a cryptographic MAC.
+#[derive(Debug)]
+pub struct Cookies {
+    secret: [u8; 16],
+}
+
+/// The cookie option of a query: the client part, plus the server part if
+/// the client had one from us already.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub client: [u8; 8],
+    pub server: Option<Vec<u8>>,
+}
+
+impl Default for Cookies {
+    fn default() -> Cookies {
+        Cookies { secret: rand::random() }
+    }
+}
+
+impl Cookies {
+    /// The server cookie `client` gets when asking from `ip`.
+    pub fn server_cookie(&self, client: &[u8; 8], ip: IpAddr) -> [u8; 8] {
+        let mut hasher = DefaultHasher::new();
+        self.secret.hash(&mut hasher);
+        client.hash(&mut hasher);
+        ip.hash(&mut hasher);
+        hasher.finish().to_be_bytes()
+    }
+
+    /// Whether `cookie` can be trusted: either it's a first contact without
+    /// a server cookie, or the server cookie is the one we'd hand out.
+    pub fn accepts(&self, cookie: &Cookie, ip: IpAddr) -> bool {
+        match &cookie.server {
+            Some(server) => server[..] == self.server_cookie(&cookie.client, ip),
+            None => true,
+        }
+    }
+
+    /// Our OPT record for a reply to `cookie`, carrying the client cookie
+    /// and a valid server cookie. `payload_size` is the largest UDP message
+    /// we take, and the upper 8 bits of `rcode` go in the extended RCODE.
+    pub fn opt_record(&self, cookie: &Cookie, ip: IpAddr, payload_size: u16, rcode: u16) -> Answer {
+        let server = self.server_cookie(&cookie.client, ip);
+        let rdata = Cookie { client: cookie.client, server: Some(server.to_vec()) }.to_option();
+
+        Answer {
+            name: Name::new(""),
+            rtype: ResourceType::OPT,
+            class: ResourceClass::try_from(payload_size).unwrap_or(ResourceClass::Unknown(payload_size)),
+            ttl: ((rcode >> 4) as u32) << 24,
+            rdlength: rdata.len() as u16,
+            rdata,
+        }
+    }
+}
+
+impl Cookie {
+    /// The cookie option in the OPT record `opt`, if there is one. Options
+    /// that don't add up, or a cookie of the wrong size, are FORMERR.
+    pub fn find(opt: &Answer) -> Result<Option<Cookie>, ResponseCode> {
+        let mut options = &opt.rdata[..];
+
+        while !options.is_empty() {
+            let Some((header, rest)) = options.split_first_chunk::<4>() else {
+                return Err(ResponseCode::FormErr);
+            };
+            let code = u16::from_be_bytes([header[0], header[1]]);
+            let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+            if rest.len() < len {
+                return Err(ResponseCode::FormErr);
+            }
+            let (data, rest) = rest.split_at(len);
+            options = rest;
+
+            if code != COOKIE {
+                continue;
+            }
+            // the server part is 8 to 32 bytes
+            let (client, server) = data.split_first_chunk::<8>().ok_or(ResponseCode::FormErr)?;
+            return match server.len() {
+                0 => Ok(Some(Cookie { client: *client, server: None })),
+                8..=32 => Ok(Some(Cookie { client: *client, server: Some(server.to_vec()) })),
+                _ => Err(ResponseCode::FormErr),
+            };
+        }
+
+        Ok(None)
+    }
+
+    /// The option bytes for this cookie, for building OPT records.
+    pub fn to_option(&self) -> Vec<u8> {
+        let server = self.server.as_deref().unwrap_or_default();
+        let mut option = Vec::new();
+        option.extend_from_slice(&COOKIE.to_be_bytes());
+        option.extend_from_slice(&((8 + server.len()) as u16).to_be_bytes());
+        option.extend_from_slice(&self.client);
+        option.extend_from_slice(server);
+        option
+    }
+}
@@ -0,0 +1,198 @@
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::server::{resolve_addr, same_questions};
+use crate::upstream::Upstream;
+use crate::Message;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const CONTENT_TYPE: &str = "application/dns-message";
+// no DNS message is longer, whatever the server claims
+const MAX_BODY: usize = u16::MAX as usize;
+
+/// A resolver speaking the DoH protocol (RFC 8484) over plain HTTP: every
+/// query is POSTed in wire format to the resolver's URL, and the response
+/// body is the answer.
+///
+/// This is not DNS-over-HTTPS. There is no TLS implementation among our
+/// dependencies, so the query crosses the wire unencrypted and only
+/// `http://` URLs can be used. To reach a public DoH service, point this at
+/// a local proxy that adds the TLS, such as a TLS-terminating reverse
+/// proxy or a DoH stub on the same host. `https://` URLs are recognized,
+/// but only to refuse them up front with that advice.
+#[derive(Debug)]
+pub struct DohResolver {
+    // host:port to connect to, also sent as the Host header
+    authority: String,
+    path: String,
+    timeout: Duration,
+}
+
+impl DohResolver {
+    /// Parses a resolver URL such as `http://127.0.0.1:8053/dns-query`.
+    /// The port defaults to 80 and the path to `/dns-query`.
+    pub fn new(url: &str) -> Result<DohResolver, String> {
+        if url.starts_with("https://") {
+            return Err(format!("{}: https needs TLS, which this build doesn't have; use an http:// URL of a local proxy that adds it", url));
+        }
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(format!("{}: not an http:// URL", url));
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/dns-query"),
+        };
+        if authority.is_empty() {
+            return Err(format!("{}: missing host", url));
+        }
+        // a bracketed IPv6 address has colons of its own
+        let has_port = authority.rsplit_once(':').is_some_and(|(_, port)| !port.contains(']'));
+        let authority = if has_port { authority.to_string() } else { format!("{}:80", authority) };
+
+        Ok(DohResolver { authority, path: path.to_string(), timeout: TIMEOUT })
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> DohResolver {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Whether `spec` names a DoH resolver rather than an address.
+    pub fn is_url(spec: &str) -> bool {
+        spec.starts_with("http://") || spec.starts_with("https://")
+    }
+}
+
+impl Upstream for DohResolver {
+    fn query(&self, msg: &Message) -> std::io::Result<Message> {
+        // RFC 8484 asks for ID 0, so identical queries can be cached by HTTP
+        let mut query = msg.clone();
+        query.header.id = 0;
+        let body = query.to_bytes();
+
+        debug!("forwarding query {} to http://{}{}", msg.header.id, self.authority, self.path);
+
+        let mut stream = TcpStream::connect_timeout(&resolve_addr(&self.authority)?, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nAccept: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path, self.authority, CONTENT_TYPE, CONTENT_TYPE, body.len(),
+        );
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(&body)?;
+
+        let mut response = read_response(BufReader::new(stream))?;
+        if !same_questions(&query, &response) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("DoH response from {} doesn't match the question", self.authority)));
+        }
+        response.header.id = msg.header.id;
+        Ok(response)
+    }
+
+    fn describe(&self) -> String {
+        format!("http://{}{}", self.authority, self.path)
+    }
+}
+
+/// Reads an HTTP/1.1 response and parses its body as a DNS message. The
+/// body may be chunked, and is never taken to be longer than a DNS message
+/// can be.
+fn read_response<R: BufRead>(mut reader: R) -> std::io::Result<Message> {
+    let invalid = |reason: String| Error::new(ErrorKind::InvalidData, reason);
+
+    let mut status = String::new();
+    reader.read_line(&mut status)?;
+    match status.split_whitespace().nth(1) {
+        Some("200") => {}
+        _ => return Err(invalid(format!("DoH resolver answered {}", status.trim()))),
+    }
+
+    let mut content_length = None;
+    let mut content_type = None;
+    let mut chunked = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "DoH response ended in its headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            match &name.trim().to_ascii_lowercase()[..] {
+                "content-length" => content_length = value.trim().parse::<usize>().ok(),
+                "content-type" => content_type = Some(value.trim().to_ascii_lowercase()),
+                "transfer-encoding" => chunked = value.trim().eq_ignore_ascii_case("chunked"),
+                _ => {}
+            }
+        }
+    }
+    if content_type.as_deref() != Some(CONTENT_TYPE) {
+        return Err(invalid(format!("DoH response isn't {}", CONTENT_TYPE)));
+    }
+
+    let too_long = || invalid(format!("DoH response is longer than {} bytes", MAX_BODY));
+    let mut body = Vec::new();
+    match content_length {
+        // chunking takes precedence over any length given (RFC 9112 6.3)
+        _ if chunked => body = read_chunks(&mut reader)?,
+        Some(len) if len > MAX_BODY => return Err(too_long()),
+        Some(len) => {
+            body.resize(len, 0);
+            reader.read_exact(&mut body)?;
+        }
+        // the server closes the connection after the body
+        None => {
+            reader.take(MAX_BODY as u64 + 1).read_to_end(&mut body)?;
+            if body.len() > MAX_BODY {
+                return Err(too_long());
+            }
+        }
+    }
+    Message::parse_reply(&body).map_err(|e| invalid(format!("malformed DoH response: {}", e)))
+}
+
+/// Reads a chunked body: chunks each led by their size in hex, up to one
+/// of size 0 and the trailer fields after it.
+fn read_chunks<R: BufRead>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let invalid = |reason: &str| Error::new(ErrorKind::InvalidData, format!("DoH response {}", reason));
+    let mut body = Vec::new();
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "DoH response ended in its body"));
+        }
+        // a chunk size may be followed by extensions, which we don't use
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid("has a bad chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        if body.len() + size > MAX_BODY {
+            return Err(invalid(&format!("is longer than {} bytes", MAX_BODY)));
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        let mut end = [0; 2];
+        reader.read_exact(&mut end)?;
+        if end != *b"\r\n" {
+            return Err(invalid("has a chunk running past its size"));
+        }
+    }
+
+    // trailer fields, up to an empty line or the end of the connection
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            return Ok(body);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt::Write;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::{Answer, Message, QClass, QType, ResourceClass, ResourceType};
+
+/// A human-readable rendering of `msg` in the spirit of dig's output: the
+/// header first, then every section with one record per line.
+pub fn describe(msg: &Message) -> String {
+    let header = &msg.header;
+    let flags = &header.flags;
+    let mut out = String::new();
+
+    let set: Vec<&str> = [("aa", flags.aa), ("tc", flags.tc), ("rd", flags.rd), ("ra", flags.ra)]
+        .into_iter()
+        .filter(|(_, bit)| *bit == 1)
+        .map(|(name, _)| name)
+        .collect();
+    let _ = writeln!(out, ";; id {}, {:?}, opcode {}, rcode {}, flags: {}", header.id, flags.qr, flags.opcode, flags.rcode, set.join(" "));
+    let _ = writeln!(out, ";; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}", header.qdcount, header.ancount, header.nscount, header.arcount);
+
+    let _ = writeln!(out, "\n;; QUESTION SECTION:");
+    for question in &msg.questions {
+        let _ = writeln!(out, "{}.\t{}\t{}", question.name.name, class_name(question.class), type_name(question.rtype));
+    }
+
+    for (title, records) in [("ANSWER", &msg.answers), ("AUTHORITY", &msg.name_servers), ("ADDITIONAL", &msg.additional)] {
+        if records.is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "\n;; {} SECTION:", title);
+        for record in records {
+            let _ = writeln!(out, "{}", describe_record(record));
+        }
+    }
+
+    out
+}
+
+pub(crate) fn describe_record(record: &Answer) -> String {
+    let class = match record.class {
+        ResourceClass::Unknown(value) => format!("CLASS{}", value),
+        class => format!("{:?}", class),
+    };
+    let data = match (record.rtype, &record.rdata[..]) {
+        (ResourceType::A, &[a, b, c, d]) => Ipv4Addr::new(a, b, c, d).to_string(),
+        (ResourceType::AAAA, rdata) if rdata.len() == 16 => {
+            let octets: [u8; 16] = rdata.try_into().unwrap();
+            Ipv6Addr::from(octets).to_string()
+        }
+        (ResourceType::CNAME, _) => format!("{}.", record.cname().map(|name| name.name).unwrap_or_default()),
+        _ => match record.data() {
+            Some(data) => format!("{:?}", data),
+            None => record.rdata.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        },
+    };
+    format!("{}.\t{}\t{}\t{:?}\t{}", record.name.name, record.ttl, class, record.rtype, data)
+}
+
+pub(crate) fn type_name(rtype: QType) -> String {
+    match rtype {
+        QType::Record(rtype) => format!("{:?}", rtype),
+        other => format!("{:?}", other),
+    }
+}
+
+pub(crate) fn class_name(class: QClass) -> String {
+    match class {
+        QClass::Record(ResourceClass::Unknown(value)) => format!("CLASS{}", value),
+        QClass::Record(class) => format!("{:?}", class),
+        other => format!("{:?}", other),
+    }
+}
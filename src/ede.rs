@@ -0,0 +1,63 @@
+use crate::{Answer, Message, ResourceType};
+
+// the EDNS option code of an extended DNS error
+const EDE: u16 = 15;
+
+/// Extended DNS Errors (RFC 8914): an EDNS option telling the client why
+/// it got the RCODE it got, as an INFO-CODE and some text for humans. It
+/// only ever rides along in the OPT record of a reply to an EDNS query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedError {
+    pub code: u16,
+    pub text: String,
+}
+
+impl ExtendedError {
+    pub const OTHER: u16 = 0;
+    pub const BLOCKED: u16 = 15;
+    pub const PROHIBITED: u16 = 18;
+    pub const NOT_AUTHORITATIVE: u16 = 20;
+    pub const NO_REACHABLE_AUTHORITY: u16 = 22;
+
+    pub fn new(code: u16, text: &str) -> ExtendedError {
+        ExtendedError { code, text: text.to_string() }
+    }
+
+    /// The extended error in `msg`'s OPT record, if it has one. Options
+    /// that don't add up are taken as no error at all.
+    pub fn find(msg: &Message) -> Option<ExtendedError> {
+        let opt = msg.additional.iter().find(|record| record.rtype == ResourceType::OPT)?;
+        let mut options = &opt.rdata[..];
+
+        while let Some((header, rest)) = options.split_first_chunk::<4>() {
+            let code = u16::from_be_bytes([header[0], header[1]]);
+            let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+            let data = rest.get(..len)?;
+            options = &rest[len..];
+
+            if code == EDE {
+                let (info, text) = data.split_first_chunk::<2>()?;
+                return Some(ExtendedError { code: u16::from_be_bytes(*info), text: String::from_utf8_lossy(text).into_owned() });
+            }
+        }
+
+        None
+    }
+
+    /// Adds this error to the options of `opt`, an OPT record we built for
+    /// a reply. Only a client that asked for EDNS gets one of those.
+    pub fn attach(&self, opt: &mut Answer) {
+        opt.rdata.extend_from_slice(&self.to_option());
+        opt.rdlength = opt.rdata.len() as u16;
+    }
+
+    /// The option bytes for this error, for building OPT records.
+    pub fn to_option(&self) -> Vec<u8> {
+        let mut option = Vec::new();
+        option.extend_from_slice(&EDE.to_be_bytes());
+        option.extend_from_slice(&((2 + self.text.len()) as u16).to_be_bytes());
+        option.extend_from_slice(&self.code.to_be_bytes());
+        option.extend_from_slice(self.text.as_bytes());
+        option
+    }
+}
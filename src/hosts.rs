@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+
+use crate::server::{ipv4_to_bytes, ipv6_to_bytes};
+use crate::{Answer, Name, Question, ResourceClass, ResourceType};
+
+const TTL: u32 = 60;
+
+/// Addresses from an /etc/hosts-style file: an address followed by the
+/// names it belongs to, canonical name first and aliases after.
+///
+/// ```text
+/// # comment
+/// 192.0.2.1   router.lan router
+/// 2001:db8::1 router.lan
+/// ```
+///
+/// Only A and AAAA questions (and ANY) are answered from it; anything else
+/// about those names goes wherever it would have gone without the file.
+#[derive(Debug, Default)]
+pub struct Hosts {
+    addresses: HashMap<String, Vec<IpAddr>>,
+}
+
+impl Hosts {
+    pub fn load(path: &str) -> std::io::Result<Hosts> {
+        let mut hosts = Hosts::default();
+
+        for (lineno, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let mut fields = line.split('#').next().unwrap().split_whitespace();
+            let Some(ip) = fields.next() else {
+                continue;
+            };
+            let ip: IpAddr = ip.parse().map_err(|_| Error::new(
+                ErrorKind::InvalidData,
+                format!("{}:{}: invalid address {}", path, lineno + 1, ip),
+            ))?;
+
+            for name in fields {
+                hosts.addresses.entry(Name::key(name)).or_default().push(ip);
+            }
+        }
+
+        Ok(hosts)
+    }
+
+    /// Returns the addresses of the type asked for, named as the question
+    /// was asked, or `None` if the question isn't one for the hosts file.
+    pub fn lookup(&self, question: &Question) -> Option<Vec<Answer>> {
+        let wants_address = question.rtype.matches(ResourceType::A) || question.rtype.matches(ResourceType::AAAA);
+        if !wants_address || !question.class.matches(ResourceClass::IN) {
+            return None;
+        }
+
+        let addresses = self.addresses.get(&Name::key(&question.name.name))?;
+        Some(address_answers(question, addresses))
+    }
+}
+
+/// The records for those of `addresses` whose type `question` asks for,
+/// named as the question was asked.
+pub(crate) fn address_answers(question: &Question, addresses: &[IpAddr]) -> Vec<Answer> {
+    addresses.iter()
+        .map(|ip| match ip {
+            IpAddr::V4(ip) => (ResourceType::A, ipv4_to_bytes(*ip)),
+            IpAddr::V6(ip) => (ResourceType::AAAA, ipv6_to_bytes(*ip)),
+        })
+        .filter(|(rtype, _)| question.rtype.matches(*rtype))
+        .map(|(rtype, rdata)| Answer {
+            name: question.name.clone(),
+            rtype,
+            class: ResourceClass::IN,
+            ttl: TTL,
+            rdlength: rdata.len() as u16,
+            rdata,
+        })
+        .collect()
+}
@@ -0,0 +1,1039 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use thiserror::Error;
+
+#[macro_use]
+pub mod logging;
+pub mod activation;
+pub mod args;
+pub mod blocklist;
+pub mod cache;
+pub mod client;
+pub mod config_file;
+pub mod cookie;
+pub mod doh;
+pub mod ede;
+pub mod dump;
+pub mod hosts;
+pub mod metrics;
+pub mod pool;
+pub mod preload;
+pub mod ratelimit;
+pub mod recursor;
+pub mod rdata;
+pub mod rewrite;
+pub mod rpz;
+pub mod server;
+pub mod signals;
+pub mod singleflight;
+pub mod tcp;
+#[cfg(unix)]
+pub mod unix;
+pub mod upstream;
+pub mod zone;
+
+pub const HEADER_LEN: u16 = 12;
+/// The most records, questions included, a query may claim to hold before
+/// parsing it is refused. Generous for anything legitimate; replies to our
+/// own queries aren't held to it.
+pub const MAX_RECORDS: usize = 1000;
+// more compression pointers than any legitimate name needs (a name is at
+// most 255 bytes, so it can't have more labels than that either)
+const MAX_POINTERS: usize = 128;
+
+/// Why a message or one of its fields couldn't be decoded. Each variant
+/// carries the offending value, so it can be logged as received.
+#[derive(Debug, Copy, Clone, PartialEq, Error)]
+pub enum ParseError {
+    #[error("unknown resource type {0}")]
+    UnknownResourceType(u16),
+    #[error("unknown resource class {0}")]
+    UnknownResourceClass(u16),
+    #[error("unknown message type {0}")]
+    UnknownMessageType(u8),
+    #[error("type {0} is only valid in a question")]
+    QueryOnlyType(u16),
+    #[error("class {0} is only valid in a question")]
+    QueryOnlyClass(u16),
+    #[error("message shorter than its header")]
+    TruncatedHeader,
+    #[error("compression pointers in a name form a loop")]
+    PointerLoop,
+    #[error("compression pointer to offset {0} doesn't point backwards")]
+    BadPointer(u16),
+    #[error("message claims {0} records, more than allowed")]
+    TooManyRecords(usize),
+    #[error("message ends in the middle of a record")]
+    Truncated,
+}
+
+// reading from an in-memory message only fails when it runs out of bytes
+impl From<std::io::Error> for ParseError {
+    fn from(_: std::io::Error) -> ParseError {
+        ParseError::Truncated
+    }
+}
+
+impl ParseError {
+    /// The RCODE to answer a query that failed to parse with this error:
+    /// things we merely don't know are NOTIMP, anything else is malformed.
+    pub fn rcode(&self) -> ResponseCode {
+        match self {
+            ParseError::UnknownResourceType(_) | ParseError::UnknownResourceClass(_) => ResponseCode::NotImp,
+            _ => ResponseCode::FormErr,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub header: Header,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Answer>,
+    pub name_servers: Vec<Answer>,
+    pub additional: Vec<Answer>
+}
+
+impl Message {
+    pub fn new(header: Header) -> Message {
+        Message {
+            header,
+            questions: Vec::new(),
+            answers: Vec::new(),
+            name_servers: Vec::new(),
+            additional: Vec::new(),
+        }
+    }
+
+    /// A recursive query for a single question.
+    pub fn query(id: u16, question: Question) -> Message {
+        let mut header = Header::new(id, MessageType::Query);
+        header.flags.rd = 1;
+
+        Message::new(header).with_question(question)
+    }
+
+    // The add_* methods keep the matching header count in step with the
+    // section, so callers never touch qdcount & co. themselves.
+
+    pub fn add_question(&mut self, question: Question) {
+        self.questions.push(question);
+        self.header.qdcount += 1;
+    }
+
+    pub fn add_answer(&mut self, answer: Answer) {
+        self.answers.push(answer);
+        self.header.ancount += 1;
+    }
+
+    pub fn add_name_server(&mut self, answer: Answer) {
+        self.name_servers.push(answer);
+        self.header.nscount += 1;
+    }
+
+    pub fn add_additional(&mut self, answer: Answer) {
+        self.additional.push(answer);
+        self.header.arcount += 1;
+    }
+
+    pub fn with_question(mut self, question: Question) -> Message {
+        self.add_question(question);
+        self
+    }
+
+    pub fn with_answer(mut self, answer: Answer) -> Message {
+        self.add_answer(answer);
+        self
+    }
+
+    pub fn with_name_server(mut self, answer: Answer) -> Message {
+        self.add_name_server(answer);
+        self
+    }
+
+    pub fn with_additional(mut self, answer: Answer) -> Message {
+        self.add_additional(answer);
+        self
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Message, ParseError> {
+        Message::parse_limited(buffer, MAX_RECORDS)
+    }
+
+    /// Like `parse`, without a cap on the records: for replies to our own
+    /// queries, which can legitimately hold far more records than a query
+    /// (a zone transfer above all). The message's length still bounds the
+    /// work, as every record takes up some of it.
+    pub fn parse_reply(buffer: &[u8]) -> Result<Message, ParseError> {
+        Message::parse_limited(buffer, usize::MAX)
+    }
+
+    /// Like `parse`, refusing up front a message whose header claims more
+    /// than `max_records` records in all, so a crafted header can't make us
+    /// churn through (and allocate for) tens of thousands of them.
+    pub fn parse_limited(buffer: &[u8], max_records: usize) -> Result<Message, ParseError> {
+        let header = Header::parse(buffer)?;
+        let records = [header.qdcount, header.ancount, header.nscount, header.arcount]
+            .into_iter()
+            .map(usize::from)
+            .sum();
+        if records > max_records {
+            return Err(ParseError::TooManyRecords(records));
+        }
+        let mut msg = Message::new(header);
+
+        let mut reader = Cursor::new(buffer);
+        reader.seek(std::io::SeekFrom::Start(HEADER_LEN.into()))?;
+
+        for _ in 0..msg.header.qdcount {
+            msg.questions.push(Question::parse(&mut reader)?);
+        }
+
+        for _ in 0..msg.header.ancount {
+            msg.answers.push(Answer::parse(&mut reader)?);
+        }
+
+        for _ in 0..msg.header.nscount {
+            msg.name_servers.push(Answer::parse(&mut reader)?);
+        }
+
+        for _ in 0..msg.header.arcount {
+            msg.additional.push(Answer::parse(&mut reader)?);
+        }
+
+        Ok(msg)
+    }
+
+    /// Locates the EDNS OPT pseudo-record. It belongs in the additional
+    /// section, but some clients misplace it; those are only tolerated when
+    /// `strict` is off. More than one OPT record is always a format error.
+    pub fn find_opt(&self, strict: bool) -> Result<Option<&Answer>, ResponseCode> {
+        let is_opt = |answer: &&Answer| matches!(answer.rtype, ResourceType::OPT);
+
+        let misplaced: Vec<&Answer> = self.answers.iter()
+            .chain(self.name_servers.iter())
+            .filter(is_opt)
+            .collect();
+        let placed: Vec<&Answer> = self.additional.iter().filter(is_opt).collect();
+
+        if misplaced.len() + placed.len() > 1 {
+            return Err(ResponseCode::FormErr);
+        }
+        if strict && !misplaced.is_empty() {
+            return Err(ResponseCode::FormErr);
+        }
+
+        Ok(placed.into_iter().chain(misplaced).next())
+    }
+
+    /// The full 12-bit RCODE (RFC 6891 section 6.1.3): the header's 4 bits,
+    /// below the 8 an OPT record carries in the top byte of its TTL.
+    /// Without an OPT record that's just the header's.
+    pub fn extended_rcode(&self) -> u16 {
+        let upper = self.additional.iter()
+            .find(|record| record.rtype == ResourceType::OPT)
+            .map_or(0, |opt| (opt.ttl >> 24) as u16);
+        upper << 4 | self.header.flags.rcode as u16
+    }
+
+    /// Whether the DO bit (RFC 3225) is set in the OPT record, asking for
+    /// DNSSEC records along with the answer.
+    pub fn dnssec_ok(&self) -> bool {
+        self.additional.iter()
+            .find(|record| record.rtype == ResourceType::OPT)
+            .is_some_and(|opt| opt.ttl & 0x8000 != 0)
+    }
+
+    /// Sets the RCODE, its upper 8 bits in the OPT record. A message
+    /// without one can only carry the lower 4, the rest is dropped.
+    pub fn set_extended_rcode(&mut self, rcode: u16) {
+        self.header.flags.rcode = (rcode & 0xf) as u8;
+        if let Some(opt) = self.additional.iter_mut().find(|record| record.rtype == ResourceType::OPT) {
+            opt.ttl = (opt.ttl & 0x00ff_ffff) | ((rcode >> 4) as u32) << 24;
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.write_to(&mut buffer);
+        buffer
+    }
+
+    /// Like `to_bytes`, but a question or record name whose tail was
+    /// already written becomes a pointer to it (RFC 1035 section 4.1.4).
+    /// Names inside RDATA are left as they are, so RDLENGTH stays right.
+    /// Suffixes are matched byte for byte, so a name keeps its case.
+    pub fn to_bytes_compressed(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        let mut written = Vec::new();
+        self.header_with_counts().write_to(&mut buffer);
+        for question in &self.questions {
+            question.name.write_compressed(&mut buffer, &mut written);
+            question.write_fields(&mut buffer);
+        }
+        for answer in self.answers.iter().chain(&self.name_servers).chain(&self.additional) {
+            answer.name.write_compressed(&mut buffer, &mut written);
+            answer.write_fields(&mut buffer);
+        }
+        buffer
+    }
+
+    fn header_with_counts(&self) -> Header {
+        Header {
+            qdcount: self.questions.len() as u16,
+            ancount: self.answers.len() as u16,
+            nscount: self.name_servers.len() as u16,
+            arcount: self.additional.len() as u16,
+            ..self.header.clone()
+        }
+    }
+
+    /// Serializes the message into `buffer`, discarding whatever it held before.
+    /// Lets callers reuse one allocation across many responses. The header
+    /// counts are taken from the sections, not from `header`, so what goes
+    /// on the wire always describes the records that follow.
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
+        buffer.clear();
+        self.header_with_counts().write_to(buffer);
+        for question in &self.questions {
+            question.write_to(buffer);
+        }
+        for answer in &self.answers {
+            answer.write_to(buffer);
+        }
+        for answer in &self.name_servers {
+            answer.write_to(buffer);
+        }
+        for answer in &self.additional {
+            answer.write_to(buffer);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub id: u16,
+    pub flags: Flags,
+    pub qdcount: u16,
+    pub ancount: u16,
+    pub nscount: u16,
+    pub arcount: u16,
+}
+
+impl Header {
+    pub fn new(id: u16, msg_type: MessageType) -> Header {
+        Header{
+            id,
+            flags: Flags::new(msg_type),
+            qdcount: 0,
+            ancount: 0,
+            nscount: 0,
+            arcount: 0
+        }
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Header, ParseError> {
+        if buffer.len() < HEADER_LEN as usize {
+            return Err(ParseError::TruncatedHeader);
+        }
+
+        let flags = Flags {
+            qr: (buffer[2] >> 7).try_into()?,
+            opcode: buffer[2] >> 3 & 0xf,
+            aa: buffer[2] >> 2 & 0x1,
+            tc: buffer[2] >> 1 & 0x1,
+            rd: buffer[2] & 0x1,
+            ra: buffer[3] >> 7,
+            z: buffer[3] >> 4 & 0x7,
+            rcode: buffer[3] & 0xf,
+        };
+
+        Ok(Header {
+            id: u16::from_be_bytes(buffer[0..2].try_into().unwrap()),
+            flags,
+            qdcount: u16::from_be_bytes(buffer[4..6].try_into().unwrap()),
+            ancount: u16::from_be_bytes(buffer[6..8].try_into().unwrap()),
+            nscount: u16::from_be_bytes(buffer[8..10].try_into().unwrap()),
+            arcount: u16::from_be_bytes(buffer[10..12].try_into().unwrap()),
+        })
+    }
+
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.id.to_be_bytes());
+        buffer.extend_from_slice(&self.flags.to_bytes());
+        buffer.extend_from_slice(&self.qdcount.to_be_bytes());
+        buffer.extend_from_slice(&self.ancount.to_be_bytes());
+        buffer.extend_from_slice(&self.nscount.to_be_bytes());
+        buffer.extend_from_slice(&self.arcount.to_be_bytes());
+    }
+}
+
+/// A domain name in presentation form, labels joined by dots. Labels are
+/// arbitrary octets on the wire, so a dot or backslash inside a label is
+/// escaped with a backslash and bytes that aren't valid UTF-8 (or are
+/// control characters) are written as `\DDD` in decimal, as in zone files.
+/// This keeps every label intact through parse and write_to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Name {
+    pub name: String
+}
+
+impl Name {
+    pub fn new(name: &str) -> Name {
+        Name { name: String::from(name) }
+    }
+
+    pub fn parse<T: Read + Seek>(reader: &mut T) -> Result<Name, ParseError> {
+        Name::parse_within(reader, MAX_POINTERS)
+    }
+
+    // `pointers` is how many more compression pointers may be followed, so
+    // a pointer loop ends in an error rather than a stack overflow
+    fn parse_within<T: Read + Seek>(reader: &mut T, pointers: usize) -> Result<Name, ParseError> {
+        let mut names: Vec<String> = Vec::new();
+
+        loop {
+            let mut len = [0];
+            reader.read_exact(&mut len)?;
+            let len = u8::from_be_bytes(len) as usize;
+
+            if len >> 6 == 0b11 { // compressed
+                let mut ptr_bottom = [0];
+                reader.read_exact(&mut ptr_bottom)?;
+                let ptr = (((len as u16) & 0x3f) << 8) | u8::from_be_bytes(ptr_bottom) as u16;
+
+                // a pointer may only refer to a prior occurrence of a name,
+                // which also keeps it inside the part of the message we've read
+                let at = reader.stream_position()?.saturating_sub(2);
+                if u64::from(ptr) >= at {
+                    return Err(ParseError::BadPointer(ptr));
+                }
+                if pointers == 0 {
+                    return Err(ParseError::PointerLoop);
+                }
+                let label = Name::resolve(ptr, reader, pointers - 1)?;
+                names.push(label);
+                break;
+            } else if len == 0 {
+                break;
+            }
+
+            let mut label = vec![0; len];
+            reader.read_exact(&mut label)?;
+
+            names.push(Name::escape_label(&label));
+        }
+
+        let name = names.join(".");
+        Ok(Name { name })
+    }
+
+    /// The form used when comparing names or keying maps by name. DNS names
+    /// compare case-insensitively, but only for ASCII letters, so anything
+    /// else is left untouched. `self` keeps the original case for echoing.
+    pub fn canonical(&self) -> String {
+        self.name.to_ascii_lowercase()
+    }
+
+    /// The canonical form of a name as written in a file or asked about,
+    /// with or without the trailing dot, for keying maps of local data.
+    pub fn key(name: &str) -> String {
+        Name::new(name.trim_end_matches('.')).canonical()
+    }
+
+    fn resolve<T: Read + Seek>(ptr: u16, reader: &mut T, pointers: usize) -> Result<String, ParseError> {
+        let pos = reader.stream_position()?;
+        reader.seek(std::io::SeekFrom::Start(ptr.into()))?;
+        let name = Name::parse_within(reader, pointers)?.name;
+        reader.seek(SeekFrom::Start(pos))?;
+        Ok(name)
+    }
+
+    fn escape_label(label: &[u8]) -> String {
+        let mut escaped = String::new();
+        for chunk in label.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                match c {
+                    '.' | '\\' => {
+                        escaped.push('\\');
+                        escaped.push(c);
+                    }
+                    c if c.is_ascii_control() => escaped.push_str(&format!("\\{:03}", c as u8)),
+                    c => escaped.push(c),
+                }
+            }
+            for byte in chunk.invalid() {
+                escaped.push_str(&format!("\\{:03}", byte));
+            }
+        }
+        escaped
+    }
+
+    /// Splits the name into its raw labels, undoing the escapes.
+    fn labels(&self) -> Vec<Vec<u8>> {
+        let mut labels = Vec::new();
+        let mut label = Vec::new();
+        let mut chars = self.name.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => labels.push(std::mem::take(&mut label)),
+                '\\' => {
+                    let digits: String = std::iter::from_fn(|| chars.next_if(char::is_ascii_digit)).take(3).collect();
+                    match digits.parse::<u8>() {
+                        Ok(byte) if digits.len() == 3 => label.push(byte),
+                        // not a \DDD escape, so the next character stands for itself
+                        _ => {
+                            label.extend_from_slice(digits.as_bytes());
+                            if digits.is_empty() {
+                                if let Some(c) = chars.next() {
+                                    label.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                                }
+                            }
+                        }
+                    }
+                }
+                c => label.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+        labels.push(label);
+        labels
+    }
+
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
+        // the root name (e.g. the owner of an OPT record) is just the terminator
+        if self.name.is_empty() {
+            buffer.push(0);
+            return;
+        }
+
+        for label in self.labels() {
+            buffer.push(label.len().try_into().expect("domain name component larger than 255 characters"));
+            buffer.extend_from_slice(&label);
+        }
+        buffer.push(0);
+    }
+
+    /// Writes the name, ending in a pointer to the longest of its suffixes
+    /// in `written`, and records where its own suffixes start.
+    fn write_compressed(&self, buffer: &mut Vec<u8>, written: &mut Vec<(Vec<Vec<u8>>, u16)>) {
+        let labels = if self.name.is_empty() { Vec::new() } else { self.labels() };
+
+        for i in 0..labels.len() {
+            if let Some((_, offset)) = written.iter().find(|(suffix, _)| *suffix == labels[i..]) {
+                buffer.extend_from_slice(&(0xc000 | offset).to_be_bytes());
+                return;
+            }
+            // a pointer has 14 bits for the offset
+            if buffer.len() < 0x4000 {
+                written.push((labels[i..].to_vec(), buffer.len() as u16));
+            }
+            buffer.push(labels[i].len().try_into().expect("domain name component larger than 255 characters"));
+            buffer.extend_from_slice(&labels[i]);
+        }
+        buffer.push(0);
+    }
+}
+
+/// Presentation format (RFC 1035 section 5.1): literal dots and backslashes
+/// in a label are escaped with a backslash, and bytes outside printable
+/// ASCII are written as `\DDD`. The alternate form (`{:#}`) adds the
+/// trailing dot of a fully qualified name. The root name is always ".".
+impl std::fmt::Display for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.name.is_empty() {
+            return f.write_str(".");
+        }
+
+        for (i, label) in self.labels().iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            for &byte in label {
+                match byte {
+                    b'.' | b'\\' => write!(f, "\\{}", byte as char)?,
+                    0x21..=0x7e => write!(f, "{}", byte as char)?,
+                    _ => write!(f, "\\{:03}", byte)?,
+                }
+            }
+        }
+        if f.alternate() {
+            f.write_str(".")?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ResourceType {
+    A = 1,
+    NS,
+    MD,
+    MF,
+    CNAME,
+    SOA,
+    MB,
+    MG,
+    MR,
+    NULL,
+    WKS,
+    PTR,
+    HINFO,
+    MINFO,
+    MX,
+    TXT,
+    AAAA = 28,
+    SRV = 33,
+    OPT = 41,
+    // DNSSEC records are passed along as they come, without decoding
+    RRSIG = 46,
+    NSEC,
+    DNSKEY,
+    CAA = 257,
+}
+
+impl TryFrom<u16> for ResourceType {
+    type Error = ParseError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            x if x == ResourceType::A as u16  => Ok(ResourceType::A),
+            x if x == ResourceType::NS as u16  => Ok(ResourceType::NS),
+            x if x == ResourceType::MD as u16  => Ok(ResourceType::MD),
+            x if x == ResourceType::MF as u16  => Ok(ResourceType::MF),
+            x if x == ResourceType::CNAME as u16  => Ok(ResourceType::CNAME),
+            x if x == ResourceType::SOA as u16  => Ok(ResourceType::SOA),
+            x if x == ResourceType::MB as u16  => Ok(ResourceType::MB),
+            x if x == ResourceType::MG as u16  => Ok(ResourceType::MG),
+            x if x == ResourceType::MR as u16  => Ok(ResourceType::MR),
+            x if x == ResourceType::NULL as u16  => Ok(ResourceType::NULL),
+            x if x == ResourceType::WKS as u16  => Ok(ResourceType::WKS),
+            x if x == ResourceType::PTR as u16  => Ok(ResourceType::PTR),
+            x if x == ResourceType::HINFO as u16  => Ok(ResourceType::HINFO),
+            x if x == ResourceType::MINFO as u16  => Ok(ResourceType::MINFO),
+            x if x == ResourceType::MX as u16  => Ok(ResourceType::MX),
+            x if x == ResourceType::TXT as u16  => Ok(ResourceType::TXT),
+            x if x == ResourceType::AAAA as u16  => Ok(ResourceType::AAAA),
+            x if x == ResourceType::SRV as u16  => Ok(ResourceType::SRV),
+            x if x == ResourceType::OPT as u16  => Ok(ResourceType::OPT),
+            x if x == ResourceType::RRSIG as u16  => Ok(ResourceType::RRSIG),
+            x if x == ResourceType::NSEC as u16  => Ok(ResourceType::NSEC),
+            x if x == ResourceType::DNSKEY as u16  => Ok(ResourceType::DNSKEY),
+            x if x == ResourceType::CAA as u16  => Ok(ResourceType::CAA),
+            _ => Err(ParseError::UnknownResourceType(value)),
+        }
+    }
+}
+
+/// Parses a type by its mnemonic, e.g. "AAAA", as written in zone files.
+impl std::str::FromStr for ResourceType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ResourceType, String> {
+        match &s.to_ascii_uppercase()[..] {
+            "A" => Ok(ResourceType::A),
+            "NS" => Ok(ResourceType::NS),
+            "MD" => Ok(ResourceType::MD),
+            "MF" => Ok(ResourceType::MF),
+            "CNAME" => Ok(ResourceType::CNAME),
+            "SOA" => Ok(ResourceType::SOA),
+            "MB" => Ok(ResourceType::MB),
+            "MG" => Ok(ResourceType::MG),
+            "MR" => Ok(ResourceType::MR),
+            "NULL" => Ok(ResourceType::NULL),
+            "WKS" => Ok(ResourceType::WKS),
+            "PTR" => Ok(ResourceType::PTR),
+            "HINFO" => Ok(ResourceType::HINFO),
+            "MINFO" => Ok(ResourceType::MINFO),
+            "MX" => Ok(ResourceType::MX),
+            "TXT" => Ok(ResourceType::TXT),
+            "AAAA" => Ok(ResourceType::AAAA),
+            "SRV" => Ok(ResourceType::SRV),
+            "OPT" => Ok(ResourceType::OPT),
+            "RRSIG" => Ok(ResourceType::RRSIG),
+            "NSEC" => Ok(ResourceType::NSEC),
+            "DNSKEY" => Ok(ResourceType::DNSKEY),
+            "CAA" => Ok(ResourceType::CAA),
+            _ => Err(format!("unknown record type {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ResourceClass {
+    IN,
+    CS,
+    CH,
+    HS,
+    // records such as OPT reuse the class field for something else
+    // (the requestor's UDP payload size), so keep the raw value around
+    Unknown(u16),
+}
+
+impl TryFrom<u16> for ResourceClass {
+    type Error = ParseError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(ResourceClass::IN),
+            2 => Ok(ResourceClass::CS),
+            3 => Ok(ResourceClass::CH),
+            4 => Ok(ResourceClass::HS),
+            _ => Err(ParseError::UnknownResourceClass(value)),
+        }
+    }
+}
+
+impl From<ResourceClass> for u16 {
+    fn from(class: ResourceClass) -> u16 {
+        match class {
+            ResourceClass::IN => 1,
+            ResourceClass::CS => 2,
+            ResourceClass::CH => 3,
+            ResourceClass::HS => 4,
+            ResourceClass::Unknown(value) => value,
+        }
+    }
+}
+
+/// The type asked for in a question: any record type, or one of the
+/// query-only codes that select several record types at once.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum QType {
+    Record(ResourceType),
+    AXFR,
+    MAILB,
+    MAILA,
+    ANY,
+}
+
+impl QType {
+    /// Whether a record of type `rtype` answers a question of this type.
+    pub fn matches(&self, rtype: ResourceType) -> bool {
+        match self {
+            QType::Record(wanted) => *wanted == rtype,
+            QType::MAILB => matches!(rtype, ResourceType::MB | ResourceType::MG | ResourceType::MR),
+            QType::MAILA => matches!(rtype, ResourceType::MD | ResourceType::MF),
+            QType::ANY => true,
+            QType::AXFR => false,
+        }
+    }
+}
+
+impl TryFrom<u16> for QType {
+    type Error = ParseError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            252 => Ok(QType::AXFR),
+            253 => Ok(QType::MAILB),
+            254 => Ok(QType::MAILA),
+            255 => Ok(QType::ANY),
+            _ => ResourceType::try_from(value).map(QType::Record),
+        }
+    }
+}
+
+impl From<QType> for u16 {
+    fn from(qtype: QType) -> u16 {
+        match qtype {
+            QType::Record(rtype) => rtype as u16,
+            QType::AXFR => 252,
+            QType::MAILB => 253,
+            QType::MAILA => 254,
+            QType::ANY => 255,
+        }
+    }
+}
+
+impl From<ResourceType> for QType {
+    fn from(rtype: ResourceType) -> QType {
+        QType::Record(rtype)
+    }
+}
+
+impl TryFrom<QType> for ResourceType {
+    type Error = ParseError;
+
+    fn try_from(qtype: QType) -> Result<Self, Self::Error> {
+        match qtype {
+            QType::Record(rtype) => Ok(rtype),
+            _ => Err(ParseError::QueryOnlyType(qtype.into())),
+        }
+    }
+}
+
+/// The class asked for in a question: any record class, or one of the
+/// query-only codes NONE and ANY.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum QClass {
+    Record(ResourceClass),
+    NONE,
+    ANY,
+}
+
+impl QClass {
+    /// Whether a record of class `class` answers a question of this class.
+    pub fn matches(&self, class: ResourceClass) -> bool {
+        match self {
+            QClass::Record(wanted) => *wanted == class,
+            QClass::ANY => true,
+            QClass::NONE => false,
+        }
+    }
+}
+
+impl TryFrom<u16> for QClass {
+    type Error = ParseError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            254 => Ok(QClass::NONE),
+            255 => Ok(QClass::ANY),
+            _ => ResourceClass::try_from(value).map(QClass::Record),
+        }
+    }
+}
+
+impl From<QClass> for u16 {
+    fn from(qclass: QClass) -> u16 {
+        match qclass {
+            QClass::Record(class) => class.into(),
+            QClass::NONE => 254,
+            QClass::ANY => 255,
+        }
+    }
+}
+
+impl From<ResourceClass> for QClass {
+    fn from(class: ResourceClass) -> QClass {
+        QClass::Record(class)
+    }
+}
+
+impl TryFrom<QClass> for ResourceClass {
+    type Error = ParseError;
+
+    fn try_from(qclass: QClass) -> Result<Self, Self::Error> {
+        match qclass {
+            QClass::Record(class) => Ok(class),
+            _ => Err(ParseError::QueryOnlyClass(qclass.into())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Question {
+    pub name: Name,
+    pub rtype: QType,
+    pub class: QClass,
+}
+
+impl Question {
+    pub fn parse<T: Read + Seek>(reader: &mut T) -> Result<Question, ParseError> {
+        let name = Name::parse(reader)?;
+
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf)?;
+        let rtype = u16::from_be_bytes(buf).try_into()?;
+
+        // a class we don't know is still a well-formed question, left for
+        // the server to turn down
+        reader.read_exact(&mut buf)?;
+        let class = u16::from_be_bytes(buf);
+        let class = class.try_into().unwrap_or(QClass::Record(ResourceClass::Unknown(class)));
+
+        Ok(Question {name, rtype, class})
+    }
+
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
+        self.name.write_to(buffer);
+        self.write_fields(buffer);
+    }
+
+    // everything after the name
+    fn write_fields(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&u16::from(self.rtype).to_be_bytes());
+        buffer.extend_from_slice(&u16::from(self.class).to_be_bytes());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Answer {
+    pub name: Name,
+    pub rtype: ResourceType,
+    pub class: ResourceClass,
+    pub ttl: u32,
+    pub rdlength: u16,
+    pub rdata: Vec<u8>
+}
+
+impl Answer {
+    pub fn parse<T: Read + Seek>(reader: &mut T) -> Result<Answer, ParseError> {
+        let name = Name::parse(reader)?;
+
+        let mut buf = [0; 2];
+        let mut buf4 = [0; 4];
+
+        reader.read_exact(&mut buf)?;
+        let rtype = u16::from_be_bytes(buf).try_into()?;
+
+        reader.read_exact(&mut buf)?;
+        let class = u16::from_be_bytes(buf);
+        let class = class.try_into().unwrap_or(ResourceClass::Unknown(class));
+
+        reader.read_exact(&mut buf4)?;
+        let ttl = u32::from_be_bytes(buf4);
+
+        reader.read_exact(&mut buf)?;
+        let rdlength = u16::from_be_bytes(buf);
+
+        let rdata_start = reader.stream_position()?;
+        let mut rdata = vec![0; rdlength as usize];
+        reader.read_exact(&mut rdata)?;
+
+        let mut answer = Answer { name, rtype, class, ttl, rdlength, rdata };
+
+        // how many bytes of fixed-size fields come before the names, and
+        // how many names; whatever follows them (SOA's five numbers) is
+        // kept as it is
+        let (fixed, names) = match rtype {
+            ResourceType::NS | ResourceType::MD | ResourceType::MF | ResourceType::CNAME
+            | ResourceType::MB | ResourceType::MG | ResourceType::MR | ResourceType::PTR => (0, 1),
+            ResourceType::MINFO | ResourceType::SOA => (0, 2),
+            ResourceType::MX => (2, 1),
+            ResourceType::SRV => (6, 1),
+            _ => (0, 0),
+        };
+        if names > 0 && answer.rdata.len() >= fixed {
+            // the names may be compressed against the rest of the packet;
+            // store them expanded so the record stands on its own
+            let rdata_end = reader.stream_position()?;
+            reader.seek(SeekFrom::Start(rdata_start + fixed as u64))?;
+            answer.rdata.truncate(fixed);
+            for _ in 0..names {
+                Name::parse(reader)?.write_to(&mut answer.rdata);
+            }
+            // names running past RDLENGTH leave the record in pieces
+            let names_end = reader.stream_position()?;
+            if names_end > rdata_end {
+                return Err(ParseError::Truncated);
+            }
+            let mut trailing = vec![0; (rdata_end - names_end) as usize];
+            reader.read_exact(&mut trailing)?;
+            answer.rdata.extend_from_slice(&trailing);
+            answer.rdlength = answer.rdata.len() as u16;
+        }
+
+        Ok(answer)
+    }
+
+    /// The canonical name a CNAME record points to.
+    pub fn cname(&self) -> Option<Name> {
+        match self.rtype {
+            ResourceType::CNAME => Name::parse(&mut Cursor::new(&self.rdata)).ok(),
+            _ => None,
+        }
+    }
+
+    /// The host an NS record names as a server for its zone.
+    pub fn ns(&self) -> Option<Name> {
+        match self.rtype {
+            ResourceType::NS => Name::parse(&mut Cursor::new(&self.rdata)).ok(),
+            _ => None,
+        }
+    }
+
+    /// The RDATA decoded according to the record type, if we know how.
+    pub fn data(&self) -> Option<rdata::RData> {
+        rdata::RData::parse(self.rtype, &self.rdata)
+    }
+
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
+        self.name.write_to(buffer);
+        self.write_fields(buffer);
+    }
+
+    // everything after the name
+    fn write_fields(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&(self.rtype as u16).to_be_bytes());
+        buffer.extend_from_slice(&u16::from(self.class).to_be_bytes());
+        buffer.extend_from_slice(&self.ttl.to_be_bytes());
+        buffer.extend_from_slice(&self.rdlength.to_be_bytes());
+        buffer.extend_from_slice(&self.rdata);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MessageType {
+    Query = 0,
+    Reply
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = ParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            x if x == MessageType::Query as u8  => Ok(MessageType::Query),
+            x if x == MessageType::Reply as u8  => Ok(MessageType::Reply),
+            _ => Err(ParseError::UnknownMessageType(value)),
+        }
+    }
+}
+
+pub enum MessageOpcode {
+    Query = 0,
+    IQuery = 1,
+    Status = 2,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ResponseCode {
+    NoError = 0,
+    FormErr = 1,
+    ServFail = 2,
+    NXDomain = 3,
+    NotImp = 4,
+    Refused = 5,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flags {
+    pub qr: MessageType,
+    pub opcode: u8,
+    pub aa: u8,
+    pub tc: u8,
+    pub rd: u8,
+    pub ra: u8,
+    pub z: u8,
+    pub rcode: u8
+}
+
+impl Flags {
+    pub fn new(qr: MessageType) -> Flags {
+        Flags {
+            qr,
+            opcode: 0,
+            aa: 0,
+            tc: 0,
+            rd: 0,
+            ra: 0,
+            z: 0,
+            rcode: 0
+        }
+    }
+
+    /// Packs the flags into the second 16 bits of the header. Each field is
+    /// masked to its width, so an out-of-range value can't spill into its
+    /// neighbours.
+    pub fn to_bytes(&self) -> [u8; 2] {
+        let mut bytes = [0; 2];
+        bytes[0] = ((self.qr as u8) << 7) | ((self.opcode & 0xf) << 3) | ((self.aa & 0x1) << 2) | ((self.tc & 0x1) << 1) | (self.rd & 0x1);
+        bytes[1] = ((self.ra & 0x1) << 7) | ((self.z & 0x7) << 4) | (self.rcode & 0xf);
+        bytes
+    }
+}
@@ -0,0 +1,191 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::ResponseCode;
+
+// how long one scrape may take to send its request or read our answer
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters describing what the server has been doing. They only ever go
+/// up and are safe to bump from any thread.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    queries: AtomicU64,
+    // indexed by the 4-bit RCODE
+    responses: [AtomicU64; 16],
+    forward_failures: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    // time from receiving a query to sending its response
+    handling_micros: AtomicU64,
+    // time spent waiting on the resolvers, counted per forwarded message
+    forwards: AtomicU64,
+    forward_micros: AtomicU64,
+}
+
+impl Metrics {
+    pub fn query_received(&self) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn response_sent(&self, rcode: u8) {
+        self.responses[(rcode & 0xf) as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn forward_failed(&self) {
+        self.forward_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn query_handled(&self, latency: Duration) {
+        self.handling_micros.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn forwarded(&self, latency: Duration) {
+        self.forwards.fetch_add(1, Ordering::Relaxed);
+        self.forward_micros.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn queries(&self) -> u64 {
+        self.queries.load(Ordering::Relaxed)
+    }
+
+    pub fn responses(&self, rcode: ResponseCode) -> u64 {
+        self.responses[rcode as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn forward_failures(&self) -> u64 {
+        self.forward_failures.load(Ordering::Relaxed)
+    }
+
+    pub fn cache_hits(&self) -> u64 {
+        self.cache_hits.load(Ordering::Relaxed)
+    }
+
+    pub fn cache_misses(&self) -> u64 {
+        self.cache_misses.load(Ordering::Relaxed)
+    }
+
+    /// Total time spent handling queries.
+    pub fn handling_time(&self) -> Duration {
+        Duration::from_micros(self.handling_micros.load(Ordering::Relaxed))
+    }
+
+    pub fn forwards(&self) -> u64 {
+        self.forwards.load(Ordering::Relaxed)
+    }
+
+    /// Total time spent waiting on the resolvers.
+    pub fn forward_time(&self) -> Duration {
+        Duration::from_micros(self.forward_micros.load(Ordering::Relaxed))
+    }
+
+    /// The counters in the Prometheus text exposition format. Responses
+    /// are broken down by RCODE; the standard ones are always listed,
+    /// others only once they've been sent.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP dns_queries_total Queries received.\n");
+        out.push_str("# TYPE dns_queries_total counter\n");
+        out.push_str(&format!("dns_queries_total {}\n", self.queries()));
+
+        out.push_str("# HELP dns_responses_total Responses sent, by RCODE.\n");
+        out.push_str("# TYPE dns_responses_total counter\n");
+        for (rcode, count) in self.responses.iter().enumerate() {
+            let count = count.load(Ordering::Relaxed);
+            let name = match rcode {
+                0 => "NOERROR".to_string(),
+                1 => "FORMERR".to_string(),
+                2 => "SERVFAIL".to_string(),
+                3 => "NXDOMAIN".to_string(),
+                4 => "NOTIMP".to_string(),
+                5 => "REFUSED".to_string(),
+                _ if count == 0 => continue,
+                other => other.to_string(),
+            };
+            out.push_str(&format!("dns_responses_total{{rcode=\"{}\"}} {}\n", name, count));
+        }
+
+        out.push_str("# HELP dns_forward_failures_total Queries no resolver could answer.\n");
+        out.push_str("# TYPE dns_forward_failures_total counter\n");
+        out.push_str(&format!("dns_forward_failures_total {}\n", self.forward_failures()));
+
+        out.push_str("# HELP dns_cache_hits_total Forwarded questions answered from the cache.\n");
+        out.push_str("# TYPE dns_cache_hits_total counter\n");
+        out.push_str(&format!("dns_cache_hits_total {}\n", self.cache_hits()));
+
+        out.push_str("# HELP dns_cache_misses_total Forwarded questions the cache couldn't answer.\n");
+        out.push_str("# TYPE dns_cache_misses_total counter\n");
+        out.push_str(&format!("dns_cache_misses_total {}\n", self.cache_misses()));
+
+        let responses: u64 = self.responses.iter().map(|count| count.load(Ordering::Relaxed)).sum();
+        out.push_str("# HELP dns_query_duration_seconds Time from receiving a query to sending its response.\n");
+        out.push_str("# TYPE dns_query_duration_seconds summary\n");
+        out.push_str(&format!("dns_query_duration_seconds_sum {}\n", self.handling_time().as_secs_f64()));
+        out.push_str(&format!("dns_query_duration_seconds_count {}\n", responses));
+
+        out.push_str("# HELP dns_forward_duration_seconds Time spent waiting on the resolvers.\n");
+        out.push_str("# TYPE dns_forward_duration_seconds summary\n");
+        out.push_str(&format!("dns_forward_duration_seconds_sum {}\n", self.forward_time().as_secs_f64()));
+        out.push_str(&format!("dns_forward_duration_seconds_count {}\n", self.forwards()));
+
+        out
+    }
+}
+
+/// Answers HTTP requests for `/metrics` on `listener` until the listener
+/// fails. Meant to run on its own thread.
+pub fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    serve_timeout(listener, metrics, TIMEOUT)
+}
+
+/// Like `serve`, giving up on a client that takes longer than `timeout` to
+/// send its request or read the answer, as requests are answered one at a
+/// time and a silent client would hold up every scrape after it.
+pub fn serve_timeout(listener: TcpListener, metrics: Arc<Metrics>, timeout: Duration) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = respond(stream, &metrics, timeout) {
+                    debug!("Failed to answer metrics request: {}", e);
+                }
+            }
+            Err(e) => {
+                error!("Metrics listener failed: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, metrics: &Metrics, timeout: Duration) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let (status, body) = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", "/metrics", ..] => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", String::new()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    )
+}
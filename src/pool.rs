@@ -0,0 +1,88 @@
+use std::net::UdpSocket;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// how many queries a socket serves before it's swapped for a fresh one
+const MAX_USES: u32 = 100;
+
+/// A set of reusable upstream sockets. Cloning the pool is cheap and every
+/// clone hands out sockets from the same set.
+///
+/// A socket is only lent out `max_uses` times. After that it is closed
+/// and a new one bound in its place, on a new random port, so a spoofer
+/// can't learn our source port once and rely on it forever.
+#[derive(Debug, Clone)]
+pub struct SocketPool {
+    bind_addr: String,
+    max_uses: u32,
+    // each idle socket with how many times it was lent out
+    idle: Arc<Mutex<Vec<(UdpSocket, u32)>>>,
+}
+
+impl SocketPool {
+    pub fn new(bind_addr: &str) -> SocketPool {
+        SocketPool {
+            bind_addr: bind_addr.to_string(),
+            max_uses: MAX_USES,
+            idle: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn with_max_uses(mut self, max_uses: u32) -> SocketPool {
+        self.max_uses = max_uses;
+        self
+    }
+
+    /// Takes an idle socket, binding a new one if none is available or the
+    /// idle one has served its share. The socket goes back to the pool when
+    /// the returned guard is dropped.
+    pub fn get(&self) -> std::io::Result<PooledSocket> {
+        // a retired socket stays open until the match is over, so its
+        // replacement can't get the same port
+        let (socket, uses) = match self.idle().pop() {
+            Some((socket, uses)) if uses < self.max_uses => (socket, uses),
+            _ => (UdpSocket::bind(&self.bind_addr)?, 0),
+        };
+
+        Ok(PooledSocket { socket: Some(socket), uses: uses + 1, pool: self.clone() })
+    }
+
+    pub fn idle_count(&self) -> usize {
+        self.idle().len()
+    }
+
+    fn idle(&self) -> MutexGuard<'_, Vec<(UdpSocket, u32)>> {
+        // a poisoned lock means a borrower panicked while holding it; the
+        // list itself is still consistent, so keep going
+        match self.idle.lock() {
+            Ok(idle) => idle,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// A socket on loan from a `SocketPool`. Deliberately not `Clone`: exactly
+/// one guard owns the socket and returns it on drop, including when the
+/// borrower bails out early with an error or panics.
+#[derive(Debug)]
+pub struct PooledSocket {
+    socket: Option<UdpSocket>,
+    uses: u32,
+    pool: SocketPool,
+}
+
+impl Deref for PooledSocket {
+    type Target = UdpSocket;
+
+    fn deref(&self) -> &UdpSocket {
+        self.socket.as_ref().expect("pooled socket already returned")
+    }
+}
+
+impl Drop for PooledSocket {
+    fn drop(&mut self) {
+        if let Some(socket) = self.socket.take() {
+            self.pool.idle().push((socket, self.uses));
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use crate::{Name, Question, ResourceClass, ResourceType};
+
+/// Reads the questions to warm the cache with from a file holding one
+/// name per line, optionally followed by a record type (A by default):
+///
+/// ```text
+/// # comment
+/// example.com
+/// example.com AAAA
+/// ```
+pub fn load(path: &str) -> std::io::Result<Vec<Question>> {
+    let mut questions = Vec::new();
+
+    for (lineno, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let fields: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+        let invalid = |reason: String| Error::new(
+            ErrorKind::InvalidData,
+            format!("{}:{}: {}", path, lineno + 1, reason),
+        );
+
+        let (name, rtype) = match fields[..] {
+            [] => continue,
+            [name] => (name, ResourceType::A),
+            [name, rtype] => (name, rtype.parse().map_err(invalid)?),
+            _ => return Err(invalid("expected: name [type]".to_string())),
+        };
+        questions.push(Question {
+            name: Name::new(name.trim_end_matches('.')),
+            rtype: rtype.into(),
+            class: ResourceClass::IN.into(),
+        });
+    }
+
+    Ok(questions)
+}
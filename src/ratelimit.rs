@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// buckets of clients that have been quiet long enough to be full again are
+// dropped once this many clients are tracked
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Per-client token buckets: each source IP may send `burst` queries at
+/// once, refilled at `rate` queries per second. Safe to share between
+/// threads.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: f64) -> RateLimiter {
+        RateLimiter { rate, burst, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Takes a token from the client's bucket, returning false when it has
+    /// run out.
+    pub fn allow(&self, client: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket { tokens: self.burst, updated: now });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+/// Parses a `--rate-limit` value: queries per second, optionally followed
+/// by `:burst` (which defaults to the rate).
+pub fn parse_rate_limit(spec: &str) -> Result<RateLimiter, String> {
+    let invalid = || format!("invalid rate limit {}", spec);
+    let (rate, burst) = match spec.split_once(':') {
+        Some((rate, burst)) => (rate, Some(burst)),
+        None => (spec, None),
+    };
+
+    let rate: f64 = rate.parse().map_err(|_| invalid())?;
+    let burst: f64 = match burst {
+        Some(burst) => burst.parse().map_err(|_| invalid())?,
+        None => rate,
+    };
+    if !(rate > 0.0 && burst >= 1.0) {
+        return Err(invalid());
+    }
+
+    Ok(RateLimiter::new(rate, burst))
+}
@@ -0,0 +1,156 @@
+use std::io::Cursor;
+use std::net::Ipv4Addr;
+
+use crate::{Name, ResourceType};
+
+/// The RDATA of a record, decoded for the types whose layout we know.
+/// Everything else stays as the raw bytes in `Answer::rdata`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RData {
+    // anything at all, up to 65535 bytes
+    Null(Vec<u8>),
+    Wks { address: Ipv4Addr, protocol: u8, bitmap: Vec<u8> },
+    Hinfo { cpu: String, os: String },
+    // the obsolete mail destination and forwarder
+    Md(Name),
+    Mf(Name),
+    Mb(Name),
+    Mg(Name),
+    Mr(Name),
+    Minfo { rmailbx: Name, emailbx: Name },
+    Ptr(Name),
+    // where to find a service (RFC 2782)
+    Srv { priority: u16, weight: u16, port: u16, target: Name },
+    // which certificate authorities may issue for the name (RFC 8659)
+    Caa { flags: u8, tag: String, value: Vec<u8> },
+}
+
+impl RData {
+    /// Decodes `rdata` as a record of type `rtype`. Returns `None` for
+    /// types without a decoder and for malformed data.
+    pub fn parse(rtype: ResourceType, rdata: &[u8]) -> Option<RData> {
+        match rtype {
+            ResourceType::NULL => Some(RData::Null(rdata.to_vec())),
+            ResourceType::WKS => {
+                let (address, rest) = rdata.split_first_chunk::<4>()?;
+                let (protocol, bitmap) = rest.split_first()?;
+                Some(RData::Wks { address: Ipv4Addr::from(*address), protocol: *protocol, bitmap: bitmap.to_vec() })
+            }
+            ResourceType::HINFO => {
+                let (cpu, rest) = parse_character_string(rdata)?;
+                let (os, rest) = parse_character_string(rest)?;
+                if !rest.is_empty() {
+                    return None;
+                }
+                Some(RData::Hinfo { cpu, os })
+            }
+            ResourceType::MD => parse_names(rdata).map(|[name]| RData::Md(name)),
+            ResourceType::MF => parse_names(rdata).map(|[name]| RData::Mf(name)),
+            ResourceType::MB => parse_names(rdata).map(|[name]| RData::Mb(name)),
+            ResourceType::MG => parse_names(rdata).map(|[name]| RData::Mg(name)),
+            ResourceType::MR => parse_names(rdata).map(|[name]| RData::Mr(name)),
+            ResourceType::PTR => parse_names(rdata).map(|[name]| RData::Ptr(name)),
+            ResourceType::MINFO => parse_names(rdata).map(|[rmailbx, emailbx]| RData::Minfo { rmailbx, emailbx }),
+            ResourceType::SRV => {
+                let (fields, target) = rdata.split_first_chunk::<6>()?;
+                let [target] = parse_names(target)?;
+                Some(RData::Srv {
+                    priority: u16::from_be_bytes([fields[0], fields[1]]),
+                    weight: u16::from_be_bytes([fields[2], fields[3]]),
+                    port: u16::from_be_bytes([fields[4], fields[5]]),
+                    target,
+                })
+            }
+            ResourceType::CAA => {
+                let (flags, rest) = rdata.split_first()?;
+                let (tag, value) = parse_character_string(rest)?;
+                if tag.is_empty() {
+                    return None;
+                }
+                Some(RData::Caa { flags: *flags, tag, value: value.to_vec() })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn rtype(&self) -> ResourceType {
+        match self {
+            RData::Null(_) => ResourceType::NULL,
+            RData::Wks { .. } => ResourceType::WKS,
+            RData::Hinfo { .. } => ResourceType::HINFO,
+            RData::Md(_) => ResourceType::MD,
+            RData::Mf(_) => ResourceType::MF,
+            RData::Mb(_) => ResourceType::MB,
+            RData::Mg(_) => ResourceType::MG,
+            RData::Mr(_) => ResourceType::MR,
+            RData::Minfo { .. } => ResourceType::MINFO,
+            RData::Ptr(_) => ResourceType::PTR,
+            RData::Srv { .. } => ResourceType::SRV,
+            RData::Caa { .. } => ResourceType::CAA,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            RData::Null(data) => bytes.extend_from_slice(data),
+            RData::Wks { address, protocol, bitmap } => {
+                bytes.extend_from_slice(&address.octets());
+                bytes.push(*protocol);
+                bytes.extend_from_slice(bitmap);
+            }
+            RData::Hinfo { cpu, os } => {
+                write_character_string(cpu, &mut bytes);
+                write_character_string(os, &mut bytes);
+            }
+            RData::Md(name) | RData::Mf(name) | RData::Mb(name) | RData::Mg(name) | RData::Mr(name) | RData::Ptr(name) => {
+                name.write_to(&mut bytes);
+            }
+            RData::Minfo { rmailbx, emailbx } => {
+                rmailbx.write_to(&mut bytes);
+                emailbx.write_to(&mut bytes);
+            }
+            RData::Srv { priority, weight, port, target } => {
+                bytes.extend_from_slice(&priority.to_be_bytes());
+                bytes.extend_from_slice(&weight.to_be_bytes());
+                bytes.extend_from_slice(&port.to_be_bytes());
+                target.write_to(&mut bytes);
+            }
+            RData::Caa { flags, tag, value } => {
+                bytes.push(*flags);
+                write_character_string(tag, &mut bytes);
+                bytes.extend_from_slice(value);
+            }
+        }
+        bytes
+    }
+}
+
+/// Reads exactly `N` names taking up all of `rdata`. Answer::parse has
+/// already expanded any compression, so they stand on their own.
+fn parse_names<const N: usize>(rdata: &[u8]) -> Option<[Name; N]> {
+    let mut reader = Cursor::new(rdata);
+    let names = [(); N].map(|_| Name::parse(&mut reader).ok());
+    if reader.position() != rdata.len() as u64 {
+        return None;
+    }
+    names.into_iter().collect::<Option<Vec<Name>>>()?.try_into().ok()
+}
+
+/// Reads a <character-string>: a length octet followed by that many bytes.
+fn parse_character_string(bytes: &[u8]) -> Option<(String, &[u8])> {
+    let (len, rest) = bytes.split_first()?;
+    if rest.len() < *len as usize {
+        return None;
+    }
+    let (string, rest) = rest.split_at(*len as usize);
+    Some((String::from_utf8(string.to_vec()).ok()?, rest))
+}
+
+/// Writes a <character-string>, cutting it off at the 255 bytes the
+/// length octet can describe.
+fn write_character_string(string: &str, bytes: &mut Vec<u8>) {
+    let string = &string.as_bytes()[..string.len().min(u8::MAX as usize)];
+    bytes.push(string.len() as u8);
+    bytes.extend_from_slice(string);
+}
@@ -0,0 +1,224 @@
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use crate::server::forward_query_timeout;
+use crate::upstream::{Upstream, DEFAULT_PORT};
+use crate::{Message, Name, QType, Question, ResourceClass, ResourceType, ResponseCode};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+// queries per resolution, referrals, minimized steps and the lookups of
+// server names without glue together
+const MAX_QUERIES: usize = 64;
+// how deep looking up a server name may nest in looking up another
+const MAX_DEPTH: usize = 4;
+
+/// Resolves names by itself instead of forwarding them: the root servers
+/// are asked first, and referrals are followed down to an authority for
+/// the name, which answers the question.
+///
+/// With QNAME minimization (RFC 9156) an authority is only told one label
+/// more than the zone it is known to serve, in an NS query, so the root
+/// servers learn `com` and the com servers `example.com`, and only the
+/// last authority sees the full question. A minimized query that gets
+/// neither a referral nor an error just means the same servers are asked
+/// one label further. One that comes back NXDOMAIN, SERVFAIL or REFUSED
+/// may be a server mishandling the shortened name, so the full question
+/// is asked instead, and minimization is off for the rest of the
+/// resolution (RFC 9156 section 2.3).
+///
+/// Referrals are followed through their glue addresses. A referral without
+/// glue has its server names resolved from the roots first, sharing the
+/// query budget. Every server is asked on the same port as the roots.
+#[derive(Debug)]
+pub struct Recursor {
+    roots: Vec<IpAddr>,
+    port: u16,
+    minimize: bool,
+    timeout: Duration,
+}
+
+impl Recursor {
+    pub fn new(roots: Vec<IpAddr>) -> Recursor {
+        Recursor { roots, port: DEFAULT_PORT, minimize: false, timeout: TIMEOUT }
+    }
+
+    pub fn with_port(mut self, port: u16) -> Recursor {
+        self.port = port;
+        self
+    }
+
+    pub fn with_qname_minimization(mut self, minimize: bool) -> Recursor {
+        self.minimize = minimize;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Recursor {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends `query` to each of `servers` in turn until one answers.
+    fn ask(&self, servers: &[IpAddr], query: &Message) -> std::io::Result<Message> {
+        let mut last_error = Error::new(ErrorKind::NotFound, "no server to ask");
+        for ip in servers {
+            let addr = SocketAddr::new(*ip, self.port).to_string();
+            match forward_query_timeout(query, &addr, self.timeout) {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    debug!("Authority {} failed: {}", addr, e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Resolves `question` from the roots down, spending queries from
+    /// `budget`. `depth` counts the lookups of server names this one is
+    /// nested in.
+    fn resolve(&self, question: &Question, id: u16, budget: &mut usize, depth: usize) -> std::io::Result<Message> {
+        let labels = labels(&question.name.name);
+
+        let mut servers = self.roots.clone();
+        // labels of the deepest zone we know the servers of
+        let mut zone_labels = 0;
+        // labels of the name in the next minimized query
+        let mut asked = 1;
+        // set once a minimized query got an error, see below
+        let mut minimize = self.minimize;
+
+        while *budget > 0 {
+            *budget -= 1;
+            let minimized = minimize && asked < labels.len();
+            let step = if minimized {
+                Question {
+                    name: Name::new(&labels[labels.len() - asked..].join(".")),
+                    rtype: QType::Record(ResourceType::NS),
+                    class: question.class,
+                }
+            } else {
+                question.clone()
+            };
+            let mut query = Message::query(id, step);
+            query.header.flags.rd = 0;
+
+            let mut response = self.ask(&servers, &query)?;
+
+            if let Some((zone, names, glue)) = referral(&response, zone_labels, &labels) {
+                debug!("Referred to {} for {}", zone.join("."), question.name);
+                servers = if glue.is_empty() { self.find_servers(&zone, &names, budget, depth)? } else { glue };
+                zone_labels = zone.len();
+                asked = zone_labels + 1;
+                continue;
+            }
+
+            let rcode = response.header.flags.rcode;
+            if minimized && rcode == ResponseCode::NoError as u8 {
+                asked += 1;
+                continue;
+            }
+            // some servers answer a shortened name with an error they'd never
+            // give for the full one, so the full question gets asked before
+            // believing it, and is from then on
+            if minimized && [ResponseCode::NXDomain, ResponseCode::ServFail, ResponseCode::Refused].iter().any(|&code| rcode == code as u8) {
+                debug!("Minimized query for {} got rcode {}, asking the full name", query.questions[0].name, rcode);
+                minimize = false;
+                continue;
+            }
+
+            if minimized {
+                // the records are about the shortened name
+                response.answers.clear();
+            }
+            return Ok(response);
+        }
+
+        Err(Error::other(format!("gave up resolving {} after {} queries", question.name, MAX_QUERIES)))
+    }
+
+    /// The addresses of the first of `names`, the servers of `zone`, that
+    /// can be resolved, for a referral that came without glue.
+    fn find_servers(&self, zone: &[&str], names: &[Name], budget: &mut usize, depth: usize) -> std::io::Result<Vec<IpAddr>> {
+        if depth >= MAX_DEPTH {
+            return Err(Error::other(format!("server names for {} nest more than {} deep", zone.join("."), MAX_DEPTH)));
+        }
+
+        for name in names {
+            let question = Question { name: name.clone(), rtype: QType::Record(ResourceType::A), class: ResourceClass::IN.into() };
+            let response = match self.resolve(&question, rand::random(), budget, depth + 1) {
+                Ok(response) => response,
+                Err(e) => {
+                    debug!("Couldn't resolve {}, a server of {}: {}", name, zone.join("."), e);
+                    continue;
+                }
+            };
+            let addresses: Vec<IpAddr> = response.answers.iter()
+                .filter(|record| record.rtype == ResourceType::A)
+                .filter_map(|record| <[u8; 4]>::try_from(&record.rdata[..]).ok().map(IpAddr::from))
+                .collect();
+            if !addresses.is_empty() {
+                return Ok(addresses);
+            }
+        }
+
+        Err(Error::new(ErrorKind::NotFound, format!("no address for any server of {}", zone.join("."))))
+    }
+}
+
+impl Upstream for Recursor {
+    fn query(&self, msg: &Message) -> std::io::Result<Message> {
+        let [question] = &msg.questions[..] else {
+            return Err(Error::new(ErrorKind::InvalidInput, "only single-question queries can be resolved"));
+        };
+
+        let mut budget = MAX_QUERIES;
+        let mut response = self.resolve(question, msg.header.id, &mut budget, 0)?;
+        response.header.id = msg.header.id;
+        response.questions = msg.questions.clone();
+        Ok(response)
+    }
+
+    fn describe(&self) -> String {
+        let roots: Vec<String> = self.roots.iter().map(IpAddr::to_string).collect();
+        let mode = if self.minimize { ", qname minimization" } else { "" };
+        format!("iterating from {} on port {}{}", roots.join(","), self.port, mode)
+    }
+}
+
+/// The zone `response` delegates to, the names of its servers and the
+/// glue addresses for them, if it is a referral to a zone below the one
+/// with `zone_labels` labels that is on the way to `target`.
+fn referral<'a>(response: &Message, zone_labels: usize, target: &[&'a str]) -> Option<(Vec<&'a str>, Vec<Name>, Vec<IpAddr>)> {
+    if response.header.flags.rcode != ResponseCode::NoError as u8 || !response.answers.is_empty() {
+        return None;
+    }
+    let delegation: Vec<_> = response.name_servers.iter().filter(|record| record.rtype == ResourceType::NS).collect();
+    let zone = labels(&delegation.first()?.name.name);
+    if zone.len() <= zone_labels || zone.len() > target.len() || !ends_with(target, &zone) {
+        return None;
+    }
+
+    let names: Vec<Name> = delegation.iter().filter_map(|record| record.ns()).collect();
+    let servers: Vec<String> = names.iter().map(Name::canonical).collect();
+    let glue = response.additional.iter()
+        .filter(|record| servers.contains(&record.name.canonical()))
+        .filter_map(|record| match (record.rtype, &record.rdata[..]) {
+            (ResourceType::A, rdata) => <[u8; 4]>::try_from(rdata).ok().map(IpAddr::from),
+            (ResourceType::AAAA, rdata) => <[u8; 16]>::try_from(rdata).ok().map(IpAddr::from),
+            _ => None,
+        })
+        .collect();
+
+    Some((target[target.len() - zone.len()..].to_vec(), names, glue))
+}
+
+fn labels(name: &str) -> Vec<&str> {
+    name.split('.').filter(|label| !label.is_empty()).collect()
+}
+
+fn ends_with(name: &[&str], suffix: &[&str]) -> bool {
+    name.len() >= suffix.len() && name[name.len() - suffix.len()..].iter()
+        .zip(suffix)
+        .all(|(label, other)| label.eq_ignore_ascii_case(other))
+}
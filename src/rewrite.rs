@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::server::{ipv4_to_bytes, ipv6_to_bytes};
+use crate::{Answer, ResourceType};
+
+/// Addresses to swap for others in forwarded answers, for split-horizon
+/// setups where a public address should resolve to an internal one. Only
+/// exact matches are rewritten, and only within the same address family.
+#[derive(Debug, Default)]
+pub struct Rewrites {
+    rules: HashMap<IpAddr, IpAddr>,
+}
+
+impl Rewrites {
+    pub fn add(&mut self, from: IpAddr, to: IpAddr) {
+        self.rules.insert(from, to);
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Replaces the RDATA of every A and AAAA record holding an address
+    /// that has a rule.
+    pub fn apply(&self, answers: &mut [Answer]) {
+        for answer in answers {
+            let from = match (answer.rtype, &answer.rdata[..]) {
+                (ResourceType::A, rdata) => <[u8; 4]>::try_from(rdata).map(IpAddr::from),
+                (ResourceType::AAAA, rdata) => <[u8; 16]>::try_from(rdata).map(IpAddr::from),
+                _ => continue,
+            };
+            let Some(to) = from.ok().and_then(|from| self.rules.get(&from)) else {
+                continue;
+            };
+
+            debug!("Rewriting an address in the answer for {} to {}", answer.name.name, to);
+            answer.rdata = match to {
+                IpAddr::V4(ip) => ipv4_to_bytes(*ip),
+                IpAddr::V6(ip) => ipv6_to_bytes(*ip),
+            };
+        }
+    }
+}
+
+/// Parses a `--rewrite` value, `from=to`, e.g. `1.1.1.1=10.0.0.1`.
+pub fn parse_rewrite(spec: &str) -> Result<(IpAddr, IpAddr), String> {
+    let invalid = || format!("invalid rewrite {}, expected from-ip=to-ip", spec);
+    let (from, to) = spec.split_once('=').ok_or_else(invalid)?;
+    let from: IpAddr = from.parse().map_err(|_| invalid())?;
+    let to: IpAddr = to.parse().map_err(|_| invalid())?;
+
+    if from.is_ipv4() != to.is_ipv4() {
+        return Err(format!("invalid rewrite {}, both addresses must be of the same family", spec));
+    }
+    Ok((from, to))
+}
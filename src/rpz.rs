@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+
+use crate::hosts::address_answers;
+use crate::{Answer, Name, Question, ResourceClass};
+
+/// What to do with a query for a name a policy rule matches.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Answer NXDOMAIN, as if the name didn't exist.
+    NxDomain,
+    /// Answer address questions with these addresses instead.
+    Sinkhole(Vec<IpAddr>),
+    /// Leave the query alone, even if a broader rule would match.
+    Passthru,
+}
+
+/// A response policy loaded from an RPZ-style file, one rule per line:
+///
+/// ```text
+/// # comment
+/// ads.example.com        CNAME .
+/// *.ads.example.com      CNAME .
+/// tracker.test           A     0.0.0.0
+/// tracker.test           AAAA  ::
+/// ok.tracker.test        CNAME rpz-passthru.
+/// ```
+///
+/// As in RPZ, `CNAME .` means NXDOMAIN, `CNAME rpz-passthru.` exempts the
+/// name, and A/AAAA records sinkhole it to the given addresses. A rule for
+/// `*.name` covers every name below `name` but not `name` itself. The most
+/// specific rule wins, so an exact rule beats a wildcard, and a wildcard
+/// beats the wildcards of its parents.
+#[derive(Debug, Default)]
+pub struct Policy {
+    rules: HashMap<String, Action>,
+    wildcards: HashMap<String, Action>,
+}
+
+impl Policy {
+    pub fn load(path: &str) -> std::io::Result<Policy> {
+        let mut policy = Policy::default();
+
+        for (lineno, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let fields: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+
+            let invalid = |reason: &str| Error::new(
+                ErrorKind::InvalidData,
+                format!("{}:{}: {}", path, lineno + 1, reason),
+            );
+
+            let [name, rtype, value] = fields[..] else {
+                return Err(invalid("expected: name type value"));
+            };
+            let action = match (rtype, value) {
+                ("CNAME", ".") => Action::NxDomain,
+                ("CNAME", "rpz-passthru.") => Action::Passthru,
+                ("A", value) => match value.parse() {
+                    Ok(ip @ IpAddr::V4(_)) => Action::Sinkhole(vec![ip]),
+                    _ => return Err(invalid("invalid IPv4 address")),
+                },
+                ("AAAA", value) => match value.parse() {
+                    Ok(ip @ IpAddr::V6(_)) => Action::Sinkhole(vec![ip]),
+                    _ => return Err(invalid("invalid IPv6 address")),
+                },
+                _ => return Err(invalid("unsupported rule")),
+            };
+
+            let (rules, name) = match name.strip_prefix("*.") {
+                Some(parent) => (&mut policy.wildcards, parent),
+                None => (&mut policy.rules, name),
+            };
+            match (rules.get_mut(&Name::key(name)), action) {
+                // several addresses for one name add up
+                (Some(Action::Sinkhole(ips)), Action::Sinkhole(more)) => ips.extend(more),
+                (Some(_), _) => return Err(invalid("conflicting rules for the same name")),
+                (None, action) => {
+                    rules.insert(Name::key(name), action);
+                }
+            }
+        }
+
+        Ok(policy)
+    }
+
+    /// The action of the most specific rule matching `name`, if any.
+    pub fn action(&self, name: &Name) -> Option<&Action> {
+        let name = Name::key(&name.name);
+        if let Some(action) = self.rules.get(&name) {
+            return Some(action);
+        }
+
+        let mut suffix = &name[..];
+        while let Some((_, parent)) = suffix.split_once('.') {
+            if let Some(action) = self.wildcards.get(parent) {
+                return Some(action);
+            }
+            suffix = parent;
+        }
+        None
+    }
+
+    /// Whether the policy says `name` doesn't exist.
+    pub fn is_nxdomain(&self, name: &Name) -> bool {
+        self.action(name) == Some(&Action::NxDomain)
+    }
+
+    /// The sinkhole answers to a question, named as the question was asked,
+    /// or `None` if no sinkhole rule matches. Questions for other types of
+    /// a sinkholed name get no answers rather than the real ones.
+    pub fn lookup(&self, question: &Question) -> Option<Vec<Answer>> {
+        let Some(Action::Sinkhole(addresses)) = self.action(&question.name) else {
+            return None;
+        };
+        if !question.class.matches(ResourceClass::IN) {
+            return Some(Vec::new());
+        }
+
+        Some(address_answers(question, addresses))
+    }
+}
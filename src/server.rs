@@ -0,0 +1,909 @@
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::seq::SliceRandom;
+
+use crate::blocklist::Blocklist;
+use crate::cache::Cache;
+use crate::cookie::{Cookie, Cookies, BADCOOKIE};
+use crate::ede::ExtendedError;
+use crate::hosts::Hosts;
+use crate::metrics::Metrics;
+use crate::ratelimit::RateLimiter;
+use crate::rpz::{Action, Policy};
+use crate::rewrite::Rewrites;
+use crate::upstream::Upstream;
+use crate::zone::Zone;
+use crate::{activation, dump, logging, tcp, Answer, Header, Message, MessageType, Name, QClass, QType, Question, ResourceClass, ResourceType, ResponseCode, MAX_RECORDS};
+
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(2);
+
+const DEFAULT_LISTEN: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const DEFAULT_PORT: u16 = 2053;
+const DEFAULT_BUFFER_SIZE: usize = 512;
+// the DO flag in the TTL field of an OPT record
+const DNSSEC_OK: u32 = 0x8000;
+const DEFAULT_TTL: u32 = 60;
+const DEFAULT_HEALTH_NAME: &str = "health.check";
+// what version.bind and friends say unless configured otherwise
+const DEFAULT_CHAOS_TXT: &str = "not disclosed";
+// the CH TXT names scanners probe for the server's software and identity
+const CHAOS_NAMES: [&str; 2] = ["version.bind", "hostname.bind"];
+const DEFAULT_A: Ipv4Addr = Ipv4Addr::new(8, 8, 8, 8);
+const DEFAULT_AAAA: Ipv6Addr = Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888);
+
+#[derive(Debug)]
+pub struct Config {
+    // address and UDP port to listen on, port 0 lets the OS pick a free one
+    pub listen: IpAddr,
+    pub port: u16,
+    // answer a single query read from stdin on stdout instead of listening
+    pub stdio: bool,
+    // also serve length-prefixed queries on a Unix domain socket here
+    pub unix_socket: Option<String>,
+    // largest query accepted, anything longer is cut off by recv_from
+    pub buffer_size: usize,
+    // queries claiming more records than this in all get FORMERR unparsed
+    pub max_records: usize,
+    // serve the metrics over HTTP on this port
+    pub metrics_port: Option<u16>,
+    pub metrics: Arc<Metrics>,
+    // where queries we can't answer ourselves are forwarded
+    pub upstreams: Option<Box<dyn Upstream>>,
+    // reject protocol deviations (e.g. a misplaced OPT record) with FORMERR
+    // instead of working around them
+    pub strict: bool,
+    // refuse RD=1 queries we can't answer from local data, as an
+    // authoritative-only server would, instead of forwarding them
+    pub deny_recursion: bool,
+    // answer like a pre-EDNS server: no OPT records in responses, and no
+    // DNSSEC records requested from the resolvers
+    pub strip_edns: bool,
+    // leave the authority and additional sections out of responses, OPT
+    // record included, for clients that only want the answers
+    pub answer_section_only: bool,
+    // write every name in responses out in full, for clients that trip
+    // over compression pointers
+    pub no_compression: bool,
+    pub log_level: Option<logging::Level>,
+    pub log_format: logging::Format,
+    pub zone: Option<Zone>,
+    // names answered locally from an /etc/hosts-style file
+    pub hosts: Option<Hosts>,
+    // always answered with TXT "ok", so load balancers can probe us
+    // without involving the resolvers
+    pub health_name: String,
+    // the TXT answer to version.bind and hostname.bind in class CH, or
+    // `None` to refuse those queries
+    pub chaos_txt: Option<String>,
+    // debugging aid: skew the advertised answer count by this much to
+    // produce deliberately malformed responses for client testing
+    pub corrupt_ancount: Option<i32>,
+    // follow CNAME-only answers from the resolver up to this many hops
+    pub cname_chase_depth: Option<usize>,
+    pub blocklist: Option<Blocklist>,
+    // RPZ-style rules, applied before any other source of answers; they
+    // take precedence over the blocklist for the names they match
+    pub policy: Option<Policy>,
+    pub rate_limiter: Option<RateLimiter>,
+    // answer EDNS cookies and refuse wrong server cookies with BADCOOKIE
+    pub cookies: Option<Cookies>,
+    // remember forwarded answers, positive and negative
+    pub cache: Option<Cache>,
+    // questions resolved into the cache at startup, before serving
+    pub preload: Vec<Question>,
+    // addresses replaced in forwarded answers
+    pub rewrites: Rewrites,
+    // TTL of the answers made up in default mode
+    pub ttl: u32,
+    // put A and AAAA answers in random order so clients don't all pick
+    // the same first address
+    pub shuffle_answers: bool,
+    // addresses the default mode answers A and AAAA questions with
+    pub default_a: Ipv4Addr,
+    pub default_aaaa: Ipv6Addr,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            listen: DEFAULT_LISTEN,
+            port: DEFAULT_PORT,
+            stdio: false,
+            unix_socket: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            max_records: MAX_RECORDS,
+            metrics_port: None,
+            metrics: Arc::new(Metrics::default()),
+            upstreams: None,
+            strict: false,
+            deny_recursion: false,
+            strip_edns: false,
+            answer_section_only: false,
+            no_compression: false,
+            log_level: None,
+            log_format: logging::Format::Text,
+            zone: None,
+            hosts: None,
+            health_name: DEFAULT_HEALTH_NAME.to_string(),
+            chaos_txt: Some(DEFAULT_CHAOS_TXT.to_string()),
+            corrupt_ancount: None,
+            cname_chase_depth: None,
+            blocklist: None,
+            policy: None,
+            rate_limiter: None,
+            cookies: None,
+            cache: None,
+            preload: Vec::new(),
+            rewrites: Rewrites::default(),
+            ttl: DEFAULT_TTL,
+            shuffle_answers: false,
+            default_a: DEFAULT_A,
+            default_aaaa: DEFAULT_AAAA,
+        }
+    }
+}
+
+/// All settings on one line, for the startup log. What was loaded from
+/// files (zone, hosts, blocklist, ...) is only reported as on or off.
+impl std::fmt::Display for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fn or_none<T: std::fmt::Display>(value: &Option<T>) -> String {
+            value.as_ref().map_or("none".to_string(), T::to_string)
+        }
+        let on = |enabled: bool| if enabled { "on" } else { "off" };
+
+        write!(f, "listen={} port={} stdio={} unix_socket={} buffer_size={} max_records={} metrics_port={}",
+            self.listen, self.port, on(self.stdio), or_none(&self.unix_socket), self.buffer_size,
+            self.max_records, or_none(&self.metrics_port))?;
+        write!(f, " upstreams={} strict={} deny_recursion={} strip_edns={} answer_section_only={} compression={} log_level={} log_format={:?}",
+            or_none(&self.upstreams.as_ref().map(|upstreams| upstreams.describe())), on(self.strict),
+            on(self.deny_recursion), on(self.strip_edns), on(self.answer_section_only), on(!self.no_compression),
+            or_none(&self.log_level),
+            self.log_format)?;
+        write!(f, " zone={} hosts={} health_name={} chaos_txt={} corrupt_ancount={} cname_chase_depth={}",
+            on(self.zone.is_some()), on(self.hosts.is_some()), self.health_name,
+            or_none(&self.chaos_txt.as_deref().map(logging::json_string)), or_none(&self.corrupt_ancount),
+            or_none(&self.cname_chase_depth))?;
+        write!(f, " blocklist={} rpz={} rate_limit={} cookies={} cache={} preload={} rewrites={}",
+            on(self.blocklist.is_some()), on(self.policy.is_some()), on(self.rate_limiter.is_some()),
+            on(self.cookies.is_some()), or_none(&self.cache.as_ref().map(Cache::capacity)), self.preload.len(),
+            self.rewrites.len())?;
+        write!(f, " ttl={} shuffle_answers={} default_a={} default_aaaa={}",
+            self.ttl, on(self.shuffle_answers), self.default_a, self.default_aaaa)
+    }
+}
+
+/// Binds the socket queries arrive on and reports the address it ended up
+/// with, which is the only way to learn the port when `config.port` is 0.
+/// Under systemd socket activation the socket systemd passed in is used
+/// instead and `config.port` is ignored.
+pub fn bind(config: &Config) -> std::io::Result<(UdpSocket, SocketAddr)> {
+    let socket = match activation::listen_socket() {
+        Some(socket) => {
+            info!("Using the socket passed in by systemd");
+            socket
+        }
+        None => UdpSocket::bind((config.listen, config.port))?,
+    };
+    let addr = socket.local_addr()?;
+    Ok((socket, addr))
+}
+
+/// What happened while handling one query, beyond what the reply shows.
+#[derive(Debug, Default)]
+struct QueryStats {
+    cache_hit: bool,
+    // part of the handling time spent waiting on the resolvers
+    forward_time: Duration,
+    // why the query failed, for clients speaking EDNS
+    extended_error: Option<ExtendedError>,
+}
+
+impl QueryStats {
+    fn record_forward(&mut self, latency: Duration, config: &Config) {
+        self.forward_time += latency;
+        config.metrics.forwarded(latency);
+    }
+}
+
+/// Answers the datagram `source` sent, read into `buffer`. A datagram
+/// longer than `config.buffer_size` was cut off by recv_from (the caller
+/// reads one byte more than that to tell), so rather than parse what's
+/// left of it the client gets FORMERR. A datagram with QR set is a reply,
+/// not a query, and answering it could make us part of a reflection loop,
+/// so it is dropped.
+pub fn handle_connection(socket: &UdpSocket, source: &SocketAddr, buffer: &[u8], config: &Config) {
+    if Header::parse(buffer).is_ok_and(|header| header.flags.qr == MessageType::Reply) {
+        warn!("Dropping a response from {} that arrived as a query", source);
+        return;
+    }
+
+    let response = if buffer.len() > config.buffer_size {
+        warn!("Query from {} doesn't fit in {} bytes", source, config.buffer_size);
+        too_large(buffer, config)
+    } else {
+        respond(source, buffer, config)
+    };
+    let Some(response) = response else {
+        return;
+    };
+
+    // the client may be gone already, which is no reason to stop serving
+    if let Err(e) = socket.send_to(&response, source) {
+        error!("Failed to send response to {}: {}", source, e);
+    }
+}
+
+/// The FORMERR reply to a query too large to read in full, made from its
+/// header alone.
+fn too_large(buffer: &[u8], config: &Config) -> Option<Vec<u8>> {
+    config.metrics.query_received();
+    let query = Header::parse(buffer).ok()?;
+
+    let mut header = Header::new(query.id, MessageType::Reply);
+    header.flags.opcode = query.flags.opcode;
+    header.flags.rd = query.flags.rd;
+    header.flags.rcode = ResponseCode::FormErr as u8;
+    config.metrics.response_sent(header.flags.rcode);
+    Some(Message::new(header).to_bytes())
+}
+
+/// Works out the reply to the query `source` sent in `buffer`, ready to go
+/// on the wire. `None` when the query is too mangled to reply to at all.
+pub fn respond(source: &SocketAddr, buffer: &[u8], config: &Config) -> Option<Vec<u8>> {
+    let received = Instant::now();
+    let mut stats = QueryStats::default();
+    config.metrics.query_received();
+    let (mut orig_msg, parse_error) = match Message::parse_limited(buffer, config.max_records) {
+        Ok(msg) => (msg, None),
+        Err(e) => {
+            warn!("Malformed query from {}: {}", source, e);
+            // the header alone is enough to tell the client what went wrong
+            let mut header = Header::parse(buffer).ok()?;
+            header.qdcount = 0;
+            (Message::new(header), Some(e))
+        }
+    };
+    let query_flags = orig_msg.header.flags.clone();
+    // whether the client speaks EDNS, and if so whether it set DO; the OPT
+    // itself is the client's and never goes back to it
+    let client_do = orig_msg.find_opt(false).ok().flatten().map(|opt| opt.ttl & DNSSEC_OK != 0 && !config.strip_edns);
+
+    // these bits mean nothing in a query, don't echo them back
+    orig_msg.header.flags.aa = 0;
+    orig_msg.header.flags.tc = 0;
+    orig_msg.header.flags.ra = 0;
+    orig_msg.header.flags.z = 0;
+    orig_msg.header.flags.rcode = ResponseCode::NoError as u8;
+
+    for question in &orig_msg.questions {
+        debug!("{} asked {} {:?} {:?}", source, question.name.name, question.rtype, question.class);
+    }
+
+    let rate_limited = match &config.rate_limiter {
+        Some(limiter) => !limiter.allow(source.ip()),
+        None => false,
+    };
+
+    // the client's cookie, only looked for when we do cookies at all
+    let checked = match parse_error {
+        Some(e) => Err(e.rcode()),
+        None => orig_msg.find_opt(config.strict).and_then(|opt| match (&config.cookies, opt) {
+            (Some(_), Some(opt)) => Cookie::find(opt),
+            _ => Ok(None),
+        }),
+    };
+    let bad_cookie = |cookie: &Cookie| config.cookies.as_ref().is_some_and(|cookies| !cookies.accepts(cookie, source.ip()));
+    let rcode = match &checked {
+        _ if rate_limited => {
+            debug!("{} is over its rate limit", source);
+            stats.extended_error = Some(ExtendedError::new(ExtendedError::OTHER, "rate limited"));
+            ResponseCode::Refused as u16
+        }
+        Ok(Some(cookie)) if bad_cookie(cookie) => {
+            debug!("{} sent a server cookie that isn't ours", source);
+            BADCOOKIE
+        }
+        Ok(_) if has_unknown_class(&orig_msg) => {
+            debug!("{} asked in a class we don't know", source);
+            ResponseCode::NotImp as u16
+        }
+        Ok(_) if config.chaos_txt.is_none() && orig_msg.questions.iter().any(is_chaos_probe) => {
+            debug!("{} probed for our version", source);
+            stats.extended_error = Some(ExtendedError::new(ExtendedError::PROHIBITED, "version queries refused"));
+            ResponseCode::Refused as u16
+        }
+        Ok(_) if is_blocked(&orig_msg, config) => {
+            debug!("{} asked for a blocked name", source);
+            stats.extended_error = Some(ExtendedError::new(ExtendedError::BLOCKED, "blocked by policy"));
+            ResponseCode::NXDomain as u16
+        }
+        Ok(_) => {
+            // nothing the client sent besides its questions belongs in the
+            // reply; the resolvers get our own OPT with the client's DO bit
+            orig_msg.name_servers.clear();
+            orig_msg.additional.clear();
+            orig_msg.header.nscount = 0;
+            orig_msg.header.arcount = 0;
+            if let Some(dnssec_ok) = client_do {
+                orig_msg.add_additional(server_opt(config, dnssec_ok));
+            }
+            answer_questions(&mut orig_msg, config, &mut stats);
+            orig_msg.extended_rcode()
+        }
+        Err(error) => {
+            orig_msg.answers.clear();
+            orig_msg.name_servers.clear();
+            orig_msg.additional.clear();
+            orig_msg.header.ancount = 0;
+            orig_msg.header.nscount = 0;
+            orig_msg.header.arcount = 0;
+            *error as u16
+        }
+    };
+    // whatever OPT the resolver's reply carried, the client gets a fresh one
+    // of ours: DO only when DNSSEC records actually came along, a cookie
+    // when we do cookies and an extended error when there is one to explain
+    // the RCODE. A question that failed when others succeeded isn't worth
+    // explaining.
+    orig_msg.additional.retain(|record| record.rtype != ResourceType::OPT);
+    orig_msg.header.arcount = orig_msg.additional.len() as u16;
+    if client_do.is_some() && !config.strip_edns {
+        let dnssec_ok = client_do == Some(true) && has_dnssec_records(&orig_msg);
+        let mut opt = match (&config.cookies, &checked) {
+            (Some(cookies), Ok(Some(cookie))) => cookies.opt_record(cookie, source.ip(), payload_size(config), rcode),
+            _ => server_opt(config, false),
+        };
+        if dnssec_ok {
+            opt.ttl |= DNSSEC_OK;
+        }
+        if let Some(error) = stats.extended_error.as_ref().filter(|_| rcode != ResponseCode::NoError as u16) {
+            error.attach(&mut opt);
+        }
+        orig_msg.add_additional(opt);
+    }
+    // the upper bits of an extended RCODE travel in the OPT record
+    orig_msg.set_extended_rcode(rcode);
+
+    if config.shuffle_answers {
+        shuffle_addresses(&mut orig_msg.answers);
+    }
+    if config.answer_section_only {
+        orig_msg.name_servers.clear();
+        orig_msg.additional.clear();
+    }
+
+    // the forwarded path replaces the message with the upstream reply, so
+    // restore what the client sent (opcode, RD), advertise recursion only
+    // when we can and may forward and keep the reserved Z bits zero. AA and
+    // TC are left as the answering path set them.
+    orig_msg.header.flags.qr = MessageType::Reply;
+    orig_msg.header.flags.opcode = query_flags.opcode;
+    orig_msg.header.flags.rd = query_flags.rd;
+    orig_msg.header.flags.ra = (config.upstreams.is_some() && !config.deny_recursion) as u8;
+    orig_msg.header.flags.z = 0;
+
+    config.metrics.response_sent(orig_msg.header.flags.rcode);
+    let mut response = if config.no_compression { orig_msg.to_bytes() } else { orig_msg.to_bytes_compressed() };
+
+    // serializing always writes the true counts, so the skew is patched
+    // into the encoded header
+    if let Some(delta) = config.corrupt_ancount {
+        let skewed = orig_msg.answers.len() as i64 + delta as i64;
+        response[6..8].copy_from_slice(&(skewed.clamp(0, u16::MAX as i64) as u16).to_be_bytes());
+    }
+
+    let latency = received.elapsed();
+    config.metrics.query_handled(latency);
+    debug!(
+        "replied to {} with rcode {} after {}us, {}us of it forwarding",
+        source, orig_msg.header.flags.rcode, latency.as_micros(), stats.forward_time.as_micros()
+    );
+    if config.log_format == logging::Format::Json {
+        log_query(source, &orig_msg, &stats, latency, config.stdio);
+    }
+
+    Some(response)
+}
+
+/// The UDP payload size we advertise, the largest query we read.
+fn payload_size(config: &Config) -> u16 {
+    config.buffer_size.min(u16::MAX as usize) as u16
+}
+
+/// Our own OPT record, for replies and for the queries we forward: our
+/// payload size, DO as given and no options.
+fn server_opt(config: &Config, dnssec_ok: bool) -> Answer {
+    let payload_size = payload_size(config);
+    Answer {
+        name: Name::new(""),
+        rtype: ResourceType::OPT,
+        class: ResourceClass::try_from(payload_size).unwrap_or(ResourceClass::Unknown(payload_size)),
+        ttl: if dnssec_ok { DNSSEC_OK } else { 0 },
+        rdlength: 0,
+        rdata: Vec::new(),
+    }
+}
+
+fn has_dnssec_records(msg: &Message) -> bool {
+    msg.answers.iter()
+        .chain(&msg.name_servers)
+        .chain(&msg.additional)
+        .any(|record| matches!(record.rtype, ResourceType::RRSIG | ResourceType::NSEC | ResourceType::DNSKEY))
+}
+
+/// Prints one JSON object describing a handled query on stdout, or on
+/// stderr in `stdio` mode, where stdout carries nothing but the reply.
+/// Only the first question is reported, that's all but every real query has.
+fn log_query(source: &SocketAddr, response: &Message, stats: &QueryStats, latency: Duration, stdio: bool) {
+    let (name, rtype) = match response.questions.first() {
+        Some(question) => (logging::json_string(&question.name.name), logging::json_string(&dump::type_name(question.rtype))),
+        None => ("null".to_string(), "null".to_string()),
+    };
+    let line = format!(
+        "{{\"client\":{},\"name\":{},\"type\":{},\"rcode\":{},\"cache_hit\":{},\"latency_us\":{},\"forward_us\":{}}}",
+        logging::json_string(&source.to_string()),
+        name,
+        rtype,
+        response.header.flags.rcode,
+        stats.cache_hit,
+        latency.as_micros(),
+        stats.forward_time.as_micros(),
+    );
+    if stdio {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Shuffles the A and AAAA records among the positions they already hold,
+/// so e.g. a CNAME leading up to them stays in front.
+fn shuffle_addresses(answers: &mut [Answer]) {
+    let positions: Vec<usize> = answers.iter()
+        .enumerate()
+        .filter(|(_, answer)| matches!(answer.rtype, ResourceType::A | ResourceType::AAAA))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut addresses: Vec<Answer> = positions.iter().map(|&i| answers[i].clone()).collect();
+    addresses.shuffle(&mut rand::thread_rng());
+    for (i, address) in positions.into_iter().zip(addresses) {
+        answers[i] = address;
+    }
+}
+
+fn has_unknown_class(msg: &Message) -> bool {
+    msg.questions.iter().any(|question| matches!(question.class, QClass::Record(ResourceClass::Unknown(_))))
+}
+
+fn is_blocked(msg: &Message, config: &Config) -> bool {
+    msg.questions.iter().any(|question| {
+        match config.policy.as_ref().and_then(|policy| policy.action(&question.name)) {
+            Some(action) => *action == Action::NxDomain,
+            None => config.blocklist.as_ref().is_some_and(|blocklist| blocklist.is_blocked(&question.name)),
+        }
+    })
+}
+
+/// How a question is answered depends on whether we hold local data for it
+/// (the health check name, version.bind, a sinkhole policy, the zone,
+/// then the hosts file), whether a resolver is configured, and the query's RD bit:
+///
+/// | local | resolver | RD  | behavior                             |
+/// |-------|----------|-----|--------------------------------------|
+/// | yes   | any      | any | answer from the local data           |
+/// | no    | yes      | 1   | forward to the resolver              |
+/// | no    | yes      | 0   | REFUSED, we only serve our own data  |
+/// | no    | no       | any | synthesized A/AAAA, NOTIMP otherwise |
+///
+/// With `deny_recursion` an RD=1 query we can't answer locally is REFUSED
+/// too, whether or not a resolver is configured.
+///
+/// A query without questions (a keepalive, or a bare DNS cookie exchange)
+/// has nothing to answer and gets an empty NOERROR reply.
+fn answer_questions(orig_msg: &mut Message, config: &Config, stats: &mut QueryStats) {
+    if orig_msg.questions.is_empty() {
+        return;
+    }
+
+    let local_answers: Vec<Option<Vec<Answer>>> = orig_msg.questions.iter()
+        .map(|question| {
+            health_check(question, config)
+                .or_else(|| chaos_txt(question, config))
+                .or_else(|| config.policy.as_ref().and_then(|policy| policy.lookup(question)))
+                .or_else(|| config.zone.as_ref().and_then(|zone| zone.lookup(question)))
+                .or_else(|| config.hosts.as_ref().and_then(|hosts| hosts.lookup(question)))
+        })
+        .collect();
+    let recursion_desired = orig_msg.header.flags.rd == 1;
+    // we only speak with authority when every answer comes from our data;
+    // forwarded and synthesized answers aren't ours to vouch for
+    let authoritative = local_answers.iter().all(Option::is_some);
+
+    match &config.upstreams {
+        Some(upstreams) if recursion_desired && !config.deny_recursion => {
+            if orig_msg.header.qdcount == 1 && local_answers[0].is_none() {
+                // override original message with response from dns server
+                match forward(orig_msg, upstreams.as_ref(), config, stats) {
+                    Ok(response) => *orig_msg = response,
+                    Err(e) => {
+                        error!("Failed to forward query: {}", e);
+                        config.metrics.forward_failed();
+                        stats.extended_error = Some(upstream_error(&e));
+                        orig_msg.header.flags.rcode = ResponseCode::ServFail as u8;
+                    }
+                }
+            } else {
+                // a message with multiple questions is split into 
+                // multiple messages with one question each
+                let mut forwarded_msg = orig_msg.clone();
+                // how each question fared, one failing doesn't sink the others
+                let mut rcodes = Vec::new();
+
+                for (question, local) in orig_msg.questions.clone().into_iter().zip(local_answers) {
+                    if let Some(answers) = local {
+                        add_answers(orig_msg, answers);
+                        rcodes.push(ResponseCode::NoError as u8);
+                        continue;
+                    }
+
+                    forwarded_msg.questions = vec![question];
+                    forwarded_msg.header.qdcount = 1;
+
+                    let response = match forward(&forwarded_msg, upstreams.as_ref(), config, stats) {
+                        Ok(response) => response,
+                        Err(e) => {
+                            error!("Failed to forward query: {}", e);
+                            config.metrics.forward_failed();
+                            stats.extended_error.get_or_insert_with(|| upstream_error(&e));
+                            rcodes.push(ResponseCode::ServFail as u8);
+                            continue;
+                        }
+                    };
+                    rcodes.push(response.header.flags.rcode);
+                    add_answers(orig_msg, response.answers);
+                }
+
+                orig_msg.header.flags.rcode = combined_rcode(&rcodes);
+            }
+        },
+        _ if config.upstreams.is_some() || (recursion_desired && config.deny_recursion) => {
+            for local in local_answers {
+                match local {
+                    Some(answers) => add_answers(orig_msg, answers),
+                    None => {
+                        stats.extended_error = Some(ExtendedError::new(ExtendedError::NOT_AUTHORITATIVE, "recursion not available"));
+                        orig_msg.header.flags.rcode = ResponseCode::Refused as u8;
+                    }
+                }
+            }
+        },
+        // the default mode: without a resolver nothing is ever recursed
+        // for, RD or not, so respond() leaves RA at 0. A synthesized answer
+        // isn't ours to vouch for, so AA is only set when the zone (or
+        // other local data) answered every question.
+        _ => {
+            for (question, local) in orig_msg.questions.clone().into_iter().zip(local_answers) {
+                if let Some(answers) = local {
+                    add_answers(orig_msg, answers);
+                    continue;
+                }
+
+                match synthesize(question, config) {
+                    Ok(answer) => add_answers(orig_msg, vec![answer]),
+                    Err(rcode) => orig_msg.header.flags.rcode = rcode as u8,
+                }
+            }
+        }
+    }
+
+    orig_msg.header.flags.aa = authoritative as u8;
+}
+
+/// The extended error explaining a SERVFAIL after forwarding failed.
+fn upstream_error(e: &Error) -> ExtendedError {
+    match e.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => ExtendedError::new(ExtendedError::NO_REACHABLE_AUTHORITY, "upstream timeout"),
+        _ => ExtendedError::new(ExtendedError::NO_REACHABLE_AUTHORITY, &e.to_string()),
+    }
+}
+
+/// The RCODE of a response to several questions answered one at a time:
+/// a success if any of them succeeded, or else the failure of the first.
+fn combined_rcode(rcodes: &[u8]) -> u8 {
+    let success = ResponseCode::NoError as u8;
+    match rcodes.first() {
+        Some(&first) if !rcodes.contains(&success) => first,
+        _ => success,
+    }
+}
+
+/// The answer to a question for the health check name: a TXT "ok" record
+/// when TXT is asked for, nothing for any other type. `None` for any other
+/// name. Never worth caching, hence the zero TTL.
+fn health_check(question: &Question, config: &Config) -> Option<Vec<Answer>> {
+    let health_name = Name::new(config.health_name.trim_end_matches('.'));
+    if question.name.canonical().trim_end_matches('.') != health_name.canonical() {
+        return None;
+    }
+    if !question.rtype.matches(ResourceType::TXT) {
+        return Some(Vec::new());
+    }
+
+    let rdata = b"\x02ok".to_vec();
+    Some(vec![Answer { name: question.name.clone(), rtype: ResourceType::TXT, class: ResourceClass::IN, ttl: 0, rdlength: rdata.len() as u16, rdata }])
+}
+
+/// Whether `question` is a CH TXT query for version.bind or hostname.bind.
+fn is_chaos_probe(question: &Question) -> bool {
+    let name = question.name.canonical();
+    question.class.matches(ResourceClass::CH)
+        && question.rtype.matches(ResourceType::TXT)
+        && CHAOS_NAMES.contains(&name.trim_end_matches('.'))
+}
+
+/// The configured TXT answer to a CH probe for version.bind or
+/// hostname.bind, so the real version never leaks.
+fn chaos_txt(question: &Question, config: &Config) -> Option<Vec<Answer>> {
+    let text = config.chaos_txt.as_ref().filter(|_| is_chaos_probe(question))?;
+
+    // a TXT record is a sequence of strings of at most 255 bytes each
+    let mut rdata = Vec::new();
+    for chunk in text.as_bytes().chunks(u8::MAX as usize) {
+        rdata.push(chunk.len() as u8);
+        rdata.extend_from_slice(chunk);
+    }
+    Some(vec![Answer { name: question.name.clone(), rtype: ResourceType::TXT, class: ResourceClass::CH, ttl: 0, rdlength: rdata.len() as u16, rdata }])
+}
+
+/// Makes up an answer of the requested type for a question nobody else can
+/// answer: the configured IPv4 address for A and IPv6 address for AAAA.
+/// Other classes are refused and other types, ANY included, aren't
+/// implemented.
+fn synthesize(question: Question, config: &Config) -> Result<Answer, ResponseCode> {
+    if !question.class.matches(ResourceClass::IN) {
+        return Err(ResponseCode::Refused);
+    }
+
+    let (rtype, rdata) = match question.rtype {
+        QType::Record(ResourceType::A) => (ResourceType::A, ipv4_to_bytes(config.default_a)),
+        QType::Record(ResourceType::AAAA) => (ResourceType::AAAA, ipv6_to_bytes(config.default_aaaa)),
+        _ => return Err(ResponseCode::NotImp),
+    };
+
+    Ok(Answer{name: question.name, rtype, class: ResourceClass::IN, ttl: config.ttl, rdlength: rdata.len() as u16, rdata})
+}
+
+/// Resolves every question in `config.preload` through the resolvers into
+/// the cache, so the first client asking doesn't wait on them. A question
+/// that can't be resolved is logged and skipped. Returns how many were.
+pub fn warm_cache(config: &Config) -> usize {
+    let Some(upstreams) = &config.upstreams else {
+        return 0;
+    };
+
+    let mut warmed = 0;
+    for (id, question) in config.preload.iter().enumerate() {
+        let query = Message::query(id as u16, question.clone());
+        match forward(&query, upstreams.as_ref(), config, &mut QueryStats::default()) {
+            Ok(_) => warmed += 1,
+            Err(e) => warn!("Failed to preload {} {}: {}", question.name, dump::type_name(question.rtype), e),
+        }
+    }
+    warmed
+}
+
+/// Forwards a single-question query, answering from the cache instead when
+/// there is one and it holds the answer.
+fn forward(msg: &Message, upstreams: &dyn Upstream, config: &Config, stats: &mut QueryStats) -> std::io::Result<Message> {
+    if let Some(cache) = &config.cache {
+        let question = &msg.questions[0];
+        if let Some(cached) = cache.get(msg) {
+            debug!("cache hit for {} {}", question.name.name, dump::type_name(question.rtype));
+            config.metrics.cache_hit();
+            stats.cache_hit = true;
+            return Ok(cached);
+        }
+        debug!("cache miss for {} {}", question.name.name, dump::type_name(question.rtype));
+        config.metrics.cache_miss();
+    }
+
+    let started = Instant::now();
+    let forwarded = upstreams.query(msg).map(|mut response| {
+        chase_cnames(&mut response, upstreams, config);
+        response
+    });
+    stats.record_forward(started.elapsed(), config);
+    let mut response = forwarded?;
+    // the question goes back exactly as the client spelled it, whatever
+    // case the resolver echoed it in
+    response.questions = msg.questions.clone();
+    config.rewrites.apply(&mut response.answers);
+
+    if let Some(cache) = &config.cache {
+        cache.insert(msg, &response);
+    }
+    Ok(response)
+}
+
+/// When the resolver answered a single question with a CNAME but no record
+/// of the requested type, query the CNAME target ourselves and append what
+/// comes back, repeating up to the configured depth. Stops early when a
+/// target repeats, so a CNAME loop can't keep us busy.
+fn chase_cnames(response: &mut Message, upstreams: &dyn Upstream, config: &Config) {
+    let (Some(max_depth), [question]) = (config.cname_chase_depth, &response.questions[..]) else {
+        return;
+    };
+    let question = question.clone();
+    let mut seen = vec![question.name.canonical()];
+
+    for _ in 0..max_depth {
+        if response.answers.iter().any(|answer| question.rtype.matches(answer.rtype)) {
+            return;
+        }
+        let Some(target) = response.answers.iter().rev().find_map(Answer::cname) else {
+            return;
+        };
+        if seen.contains(&target.canonical()) {
+            warn!("CNAME loop while resolving {}", question.name.name);
+            return;
+        }
+        seen.push(target.canonical());
+
+        let query = Message::query(rand::random(), Question { name: target, ..question.clone() });
+        match upstreams.query(&query) {
+            Ok(next) => add_answers(response, next.answers),
+            Err(e) => {
+                warn!("Failed to chase CNAME for {}: {}", question.name.name, e);
+                return;
+            }
+        }
+    }
+}
+
+fn add_answers(msg: &mut Message, answers: Vec<Answer>) {
+    for answer in answers {
+        msg.add_answer(answer);
+    }
+}
+
+/// Sends `msg` to `resolver` under a fresh random ID and waits for the
+/// reply carrying that ID and echoing the same questions, ignoring anything
+/// else arriving on the socket (e.g. spoofed answers). The reply is handed
+/// back with the caller's original ID.
+pub fn forward_query(msg: &Message, resolver: &str) -> std::io::Result<Message> {
+    forward_query_timeout(msg, resolver, FORWARD_TIMEOUT)
+}
+
+pub fn forward_query_timeout(msg: &Message, resolver: &str, timeout: Duration) -> std::io::Result<Message> {
+    let udp_socket = UdpSocket::bind(local_addr_for(&resolve_addr(resolver)?))?;
+    forward_query_on(&udp_socket, msg, resolver, timeout)
+}
+
+/// Like `forward_query_timeout`, but over a socket the caller already has.
+/// The socket may be reused: a stray reply left over from an earlier query
+/// fails the ID check and is dropped. So is anything that doesn't come
+/// from the resolver's address, however good its ID.
+pub fn forward_query_on(udp_socket: &UdpSocket, msg: &Message, resolver: &str, timeout: Duration) -> std::io::Result<Message> {
+    let mut query = msg.clone();
+    query.header.id = rand::random();
+    let resolver_addr = resolve_addr(resolver)?;
+
+    debug!("forwarding query {} to resolver {} as {}", msg.header.id, resolver, query.header.id);
+
+    udp_socket.send_to(&query.to_bytes(), resolver_addr)?;
+
+    let deadline = Instant::now() + timeout;
+    // with EDNS a reply can be far larger than 512 bytes, so leave room
+    // for the biggest datagram there is
+    let mut buf = vec![0; 65535];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::new(ErrorKind::TimedOut, "no response from resolver"));
+        }
+        udp_socket.set_read_timeout(Some(remaining))?;
+
+        // an ICMP port unreachable surfaces here on some platforms (and
+        // always on a connected socket): the resolver isn't listening,
+        // which is no reason to wait out the timeout
+        let size = match udp_socket.recv_from(&mut buf) {
+            Ok((_, from)) if from != resolver_addr => {
+                warn!("discarding datagram from {} while waiting for {}", from, resolver);
+                continue;
+            }
+            Ok((size, _)) => size,
+            Err(e) if matches!(e.kind(), ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset) => {
+                return Err(Error::new(ErrorKind::ConnectionRefused, format!("resolver {} is unreachable: {}", resolver, e)));
+            }
+            Err(e) => return Err(e),
+        };
+        let mut response = match Message::parse_reply(&buf[..size]) {
+            Ok(response) => response,
+            Err(e) if Header::parse(&buf[..size]).is_ok_and(|header| header.id == query.header.id) => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("malformed response from {}: {}", resolver, e)));
+            }
+            Err(e) => {
+                debug!("discarding malformed response from {}: {}", resolver, e);
+                continue;
+            }
+        };
+        if response.header.id != query.header.id {
+            debug!("discarding response {} while waiting for {}", response.header.id, query.header.id);
+            continue;
+        }
+        if !same_questions(&query, &response) {
+            warn!("discarding response {} from {}: question section doesn't match", response.header.id, resolver);
+            continue;
+        }
+
+        response.header.id = msg.header.id;
+        return Ok(response);
+    }
+}
+
+/// Like `forward_query_timeout`, but over a fresh TCP connection with the
+/// two-byte length framing, for resolvers or answers that don't suit UDP.
+pub fn forward_query_tcp(msg: &Message, resolver: &str, timeout: Duration) -> std::io::Result<Message> {
+    let mut query = msg.clone();
+    query.header.id = rand::random();
+
+    debug!("forwarding query {} to resolver {} over TCP as {}", msg.header.id, resolver, query.header.id);
+
+    let mut stream = TcpStream::connect_timeout(&resolve_addr(resolver)?, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    tcp::send(&mut stream, &query)?;
+    let mut response = tcp::recv(&mut stream)?;
+
+    // nobody else can write to this connection, so a mismatch is a broken
+    // resolver rather than a spoofing attempt
+    if response.header.id != query.header.id || !same_questions(&query, &response) {
+        return Err(Error::new(ErrorKind::InvalidData, "response doesn't match the query"));
+    }
+
+    response.header.id = msg.header.id;
+    Ok(response)
+}
+
+pub fn resolve_addr(resolver: &str) -> std::io::Result<SocketAddr> {
+    resolver.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("resolver address {} doesn't resolve", resolver)))
+}
+
+/// The wildcard address of the same family as `remote`, for binding a
+/// socket that can reach it.
+pub fn local_addr_for(remote: &SocketAddr) -> SocketAddr {
+    match remote {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    }
+}
+
+/// Whether `response` echoes the questions of `query`, the case of the
+/// names aside.
+pub(crate) fn same_questions(query: &Message, response: &Message) -> bool {
+    query.questions.len() == response.questions.len()
+        && query.questions.iter().zip(&response.questions).all(|(asked, echoed)| {
+            asked.name.canonical() == echoed.name.canonical()
+                && asked.rtype == echoed.rtype
+                && asked.class == echoed.class
+        })
+}
+
+pub fn ipv4_to_bytes(ip: Ipv4Addr) -> Vec<u8> {
+    let octets = ip.octets();
+    octets.to_vec()
+}
+
+pub fn ipv6_to_bytes(ip: Ipv6Addr) -> Vec<u8> {
+    let octets = ip.octets();
+    octets.to_vec()
+}
@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static CACHE_DUMP: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+mod ffi {
+    pub const SIGINT: i32 = 2;
+    pub const SIGTERM: i32 = 15;
+    #[cfg(target_os = "linux")]
+    pub const SIGUSR1: i32 = 10;
+    #[cfg(not(target_os = "linux"))]
+    pub const SIGUSR1: i32 = 30;
+
+    extern "C" {
+        // sighandler_t is a plain function pointer, which fits in a usize
+        pub fn signal(signum: i32, handler: usize) -> usize;
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(signum: i32) {
+    // only async-signal-safe work here: flip a flag and let the main loop
+    // notice it
+    match signum {
+        ffi::SIGUSR1 => CACHE_DUMP.store(true, Ordering::SeqCst),
+        _ => SHUTDOWN.store(true, Ordering::SeqCst),
+    }
+}
+
+/// Turns SIGINT and SIGTERM into a shutdown request instead of killing the
+/// process, so the receive loop can finish the request in hand and exit,
+/// and SIGUSR1 into a request to log the cache contents.
+pub fn install_handlers() {
+    #[cfg(unix)]
+    unsafe {
+        for signum in [ffi::SIGINT, ffi::SIGTERM, ffi::SIGUSR1] {
+            ffi::signal(signum, on_signal as extern "C" fn(i32) as usize);
+        }
+    }
+}
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Whether a cache dump was asked for since the last call.
+pub fn take_cache_dump_request() -> bool {
+    CACHE_DUMP.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// Collapses concurrent calls for the same key into one: the first caller
+/// does the work while everyone arriving before it finishes waits and gets
+/// a copy of its result. Once a call completes the key is free again, so
+/// nothing is cached. If the first caller panics instead, the waiters
+/// start over, one of them doing the work this time.
+#[derive(Debug)]
+pub struct SingleFlight<K, V> {
+    flights: Mutex<HashMap<K, Arc<Flight<V>>>>,
+}
+
+#[derive(Debug)]
+struct Flight<V> {
+    outcome: Mutex<Outcome<V>>,
+    done: Condvar,
+}
+
+#[derive(Debug, Clone)]
+enum Outcome<V> {
+    Pending,
+    Done(V),
+    // the leader panicked before finishing
+    Abandoned,
+}
+
+/// Lands the leader's flight however `run` is left, panics included: the
+/// key is freed and the waiters woken, so nobody waits forever.
+struct Landing<'a, K: Eq + Hash, V> {
+    flights: &'a SingleFlight<K, V>,
+    key: &'a K,
+    flight: &'a Flight<V>,
+}
+
+impl<K: Eq + Hash, V> Drop for Landing<'_, K, V> {
+    fn drop(&mut self) {
+        let mut outcome = lock(&self.flight.outcome);
+        if matches!(*outcome, Outcome::Pending) {
+            *outcome = Outcome::Abandoned;
+        }
+        drop(outcome);
+        lock(&self.flights.flights).remove(self.key);
+        self.flight.done.notify_all();
+    }
+}
+
+// nothing is ever left half-updated under these locks, so a poisoned one
+// is still good to use
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> SingleFlight<K, V> {
+    pub fn new() -> SingleFlight<K, V> {
+        SingleFlight { flights: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn run<F: FnOnce() -> V>(&self, key: K, work: F) -> V {
+        loop {
+            let (flight, leader) = {
+                let mut flights = lock(&self.flights);
+                match flights.get(&key) {
+                    Some(flight) => (flight.clone(), false),
+                    None => {
+                        let flight = Arc::new(Flight { outcome: Mutex::new(Outcome::Pending), done: Condvar::new() });
+                        flights.insert(key.clone(), flight.clone());
+                        (flight, true)
+                    }
+                }
+            };
+
+            if leader {
+                let _landing = Landing { flights: self, key: &key, flight: &flight };
+                let value = work();
+                *lock(&flight.outcome) = Outcome::Done(value.clone());
+                return value;
+            }
+
+            let mut outcome = lock(&flight.outcome);
+            while matches!(*outcome, Outcome::Pending) {
+                outcome = flight.done.wait(outcome).unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+            if let Outcome::Done(value) = &*outcome {
+                return value.clone();
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Default for SingleFlight<K, V> {
+    fn default() -> SingleFlight<K, V> {
+        SingleFlight::new()
+    }
+}
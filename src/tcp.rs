@@ -0,0 +1,37 @@
+use std::io::{Read, Write};
+
+use crate::Message;
+
+/// Writes `msg` with the two-byte length prefix DNS uses over TCP.
+pub fn send<W: Write>(stream: &mut W, msg: &Message) -> std::io::Result<()> {
+    send_bytes(stream, &msg.to_bytes())
+}
+
+/// Like `send`, for a message that is already encoded.
+pub fn send_bytes<W: Write>(stream: &mut W, bytes: &[u8]) -> std::io::Result<()> {
+    let len: u16 = bytes.len().try_into().map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "message too large for TCP")
+    })?;
+
+    let mut framed = Vec::with_capacity(bytes.len() + 2);
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(bytes);
+    stream.write_all(&framed)
+}
+
+/// Reads one length-prefixed message, a reply to a query of ours, so
+/// however many records it holds.
+pub fn recv<R: Read>(stream: &mut R) -> std::io::Result<Message> {
+    let bytes = recv_bytes(stream)?;
+    Message::parse_reply(&bytes).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Like `recv`, leaving the message encoded.
+pub fn recv_bytes<R: Read>(stream: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+
+    let mut bytes = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::Arc;
+use std::thread;
+
+use crate::server::{respond, Config};
+use crate::tcp;
+
+/// Binds a Unix domain socket at `path`. A socket left behind by an
+/// earlier run is replaced; any other file there is left alone and the
+/// bind fails.
+pub fn bind(path: &str) -> std::io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        _ => {}
+    }
+    UnixListener::bind(path)
+}
+
+/// Serves queries on `listener`, framed with a two-byte length prefix as
+/// over TCP, each connection on its own thread. A connection may carry any
+/// number of queries and is closed when the client is done.
+pub fn serve(listener: UnixListener, config: Arc<Config>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let config = config.clone();
+                thread::spawn(move || serve_connection(stream, &config));
+            }
+            Err(e) => error!("Failed to accept a Unix socket connection: {}", e),
+        }
+    }
+}
+
+fn serve_connection(mut stream: UnixStream, config: &Config) {
+    // local peers have no address, so they share one for rate limiting
+    let source = SocketAddr::from(([0, 0, 0, 0], 0));
+
+    loop {
+        let query = match tcp::recv_bytes(&mut stream) {
+            Ok(query) => query,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return,
+            Err(e) => {
+                error!("Failed to read from a Unix socket client: {}", e);
+                return;
+            }
+        };
+
+        let Some(response) = respond(&source, &query, config) else {
+            debug!("Unix socket query too malformed to answer");
+            continue;
+        };
+        if let Err(e) = tcp::send_bytes(&mut stream, &response) {
+            error!("Failed to answer a Unix socket client: {}", e);
+            return;
+        }
+    }
+}
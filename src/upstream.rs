@@ -0,0 +1,443 @@
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::pool::SocketPool;
+use crate::server::{forward_query_on, forward_query_tcp, resolve_addr};
+use crate::singleflight::SingleFlight;
+use crate::{Message, Name, ResponseCode};
+
+const FAILURE_THRESHOLD: u32 = 3;
+const COOLDOWN: Duration = Duration::from_secs(30);
+const TIMEOUT: Duration = Duration::from_secs(2);
+const RETRIES: u32 = 2;
+/// The port a resolver given as a bare address is asked on.
+pub const DEFAULT_PORT: u16 = 53;
+
+/// Something queries can be forwarded to: the configured resolvers, or
+/// anything else that turns a query into a response (a different transport,
+/// or a canned answer in tests). The response carries the query's ID.
+pub trait Upstream: Send + Sync + std::fmt::Debug {
+    fn query(&self, msg: &Message) -> std::io::Result<Message>;
+
+    /// Where queries go, for the startup log.
+    fn describe(&self) -> String {
+        "custom upstream".to_string()
+    }
+}
+
+// io::Error isn't Clone, so a shared failure is passed around in pieces
+type SharedResult = Result<Message, (ErrorKind, String)>;
+
+/// The configured resolvers. Each one gets `retries` more attempts after a
+/// timeout, since a lost UDP packet is nothing unusual, before the next
+/// one is tried. Without weights they are tried in order;
+/// once any resolver has a weight, the first attempt goes to a resolver
+/// picked at random in proportion to the weights (unweighted ones count
+/// as 1) and the others remain as fallbacks. A resolver that fails
+/// `failure_threshold` times in a row is taken out of rotation (its circuit
+/// opens) for `cooldown`; after that a single query is let through as a
+/// probe, and it is back in rotation as soon as one succeeds. `health`
+/// reports each resolver's successes, failures and latency so far.
+///
+/// UDP queries go out over a pool of sockets bound once and reused, one per
+/// concurrent query, rather than a fresh socket (and ephemeral port) each.
+/// With `Protocol::Tcp` every query opens its own connection instead.
+///
+/// Identical single-question queries arriving while one is already being
+/// forwarded don't go upstream again; they wait for and share its answer.
+///
+/// With case randomization ("DNS 0x20") the letters of every forwarded
+/// name are randomly upper- or lowercased, and a reply must echo the name
+/// with exactly that case, which an off-path spoofer would have to guess.
+/// The client still sees its name as it spelled it.
+#[derive(Debug)]
+pub struct Upstreams {
+    resolvers: Vec<Resolver>,
+    protocol: Protocol,
+    // one pool per address family
+    sockets_v4: SocketPool,
+    sockets_v6: SocketPool,
+    // in-flight forwards by (name, type, class, DO)
+    inflight: SingleFlight<(String, u16, u16, bool), SharedResult>,
+    timeout: Duration,
+    retries: u32,
+    randomize_case: bool,
+    failure_threshold: u32,
+    cooldown: Duration,
+}
+
+#[derive(Debug)]
+struct Resolver {
+    addr: String,
+    weight: Option<u32>,
+    health: Mutex<Health>,
+}
+
+/// How a resolver has fared so far.
+#[derive(Debug, Default, Clone)]
+pub struct Health {
+    pub successes: u64,
+    pub failures: u64,
+    pub consecutive_failures: u32,
+    // summed over the successes
+    pub latency: Duration,
+    opened_at: Option<Instant>,
+    // a query is out probing the resolver while its circuit is open
+    probing: bool,
+}
+
+impl Health {
+    /// How long a successful query took on average, if there was one.
+    pub fn average_latency(&self) -> Option<Duration> {
+        u32::try_from(self.successes).ok().filter(|&n| n > 0).map(|n| self.latency / n)
+    }
+
+    /// Whether the resolver is out of rotation, waiting out its cooldown.
+    pub fn ejected(&self) -> bool {
+        self.opened_at.is_some()
+    }
+}
+
+impl Upstreams {
+    pub fn new(addrs: Vec<String>) -> Upstreams {
+        Upstreams::weighted(addrs.into_iter().map(|addr| (addr, None)).collect())
+    }
+
+    pub fn weighted(specs: Vec<(String, Option<u32>)>) -> Upstreams {
+        let resolvers = specs.into_iter()
+            .map(|(addr, weight)| Resolver { addr, weight, health: Mutex::new(Health::default()) })
+            .collect();
+
+        Upstreams {
+            resolvers,
+            protocol: Protocol::Udp,
+            sockets_v4: SocketPool::new("0.0.0.0:0"),
+            sockets_v6: SocketPool::new("[::]:0"),
+            inflight: SingleFlight::new(),
+            timeout: TIMEOUT,
+            retries: RETRIES,
+            randomize_case: false,
+            failure_threshold: FAILURE_THRESHOLD,
+            cooldown: COOLDOWN,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Upstreams {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_protocol(mut self, protocol: Protocol) -> Upstreams {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn with_retries(mut self, retries: u32) -> Upstreams {
+        self.retries = retries;
+        self
+    }
+
+    pub fn with_case_randomization(mut self, randomize_case: bool) -> Upstreams {
+        self.randomize_case = randomize_case;
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, failure_threshold: u32, cooldown: Duration) -> Upstreams {
+        self.failure_threshold = failure_threshold;
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// How many forwarding sockets are bound and not in use right now.
+    pub fn idle_sockets(&self) -> usize {
+        self.sockets_v4.idle_count() + self.sockets_v6.idle_count()
+    }
+
+    pub fn health(&self, addr: &str) -> Option<Health> {
+        self.resolvers.iter()
+            .find(|resolver| resolver.addr == addr)
+            .map(|resolver| resolver.health.lock().unwrap().clone())
+    }
+
+    /// Forwards `msg` to the first resolver that answers. Timeouts, socket
+    /// errors and SERVFAIL replies count against a resolver's health and
+    /// move on to the next one. If every resolver's circuit is open they
+    /// are all tried anyway, since that beats failing outright.
+    pub fn forward(&self, msg: &Message) -> std::io::Result<Message> {
+        let [question] = &msg.questions[..] else {
+            return self.forward_uncoalesced(msg);
+        };
+        let key = (question.name.canonical(), u16::from(question.rtype), u16::from(question.class), msg.dnssec_ok());
+
+        let shared = self.inflight.run(key, || {
+            self.forward_uncoalesced(msg).map_err(|e| (e.kind(), e.to_string()))
+        });
+
+        // the answer may have been fetched on behalf of another client
+        match shared {
+            Ok(mut response) => {
+                response.header.id = msg.header.id;
+                response.questions = msg.questions.clone();
+                Ok(response)
+            }
+            Err((kind, e)) => Err(Error::new(kind, e)),
+        }
+    }
+
+    fn forward_uncoalesced(&self, msg: &Message) -> std::io::Result<Message> {
+        let now = Instant::now();
+        let mut candidates: Vec<&Resolver> = self.resolvers.iter()
+            .filter(|resolver| resolver.available(now, self.cooldown))
+            .collect();
+        // with every circuit open, asking them all beats not answering
+        let desperate = candidates.is_empty();
+        if desperate {
+            candidates = self.resolvers.iter().collect();
+        }
+        if candidates.iter().any(|resolver| resolver.weight.is_some()) {
+            let first = pick_weighted(&candidates);
+            candidates[..=first].rotate_right(1);
+        }
+
+        let mut last_error = Error::new(ErrorKind::NotFound, "no resolver configured");
+        let mut servfail = None;
+
+        for resolver in candidates {
+            // another query may have taken the probe since the filter above
+            if !desperate && !resolver.admit(now, self.cooldown) {
+                continue;
+            }
+            let started = Instant::now();
+            match self.ask(resolver, msg) {
+                Ok(response) if response.extended_rcode() == ResponseCode::ServFail as u16 => {
+                    resolver.record_failure(self.failure_threshold);
+                    servfail = Some(response);
+                }
+                Ok(response) => {
+                    resolver.record_success(started.elapsed());
+                    return Ok(response);
+                }
+                Err(e) => {
+                    warn!("Resolver {} failed: {}", resolver.addr, e);
+                    resolver.record_failure(self.failure_threshold);
+                    last_error = e;
+                }
+            }
+        }
+
+        servfail.ok_or(last_error)
+    }
+
+    /// Sends `msg` to one resolver, its names in random case if configured.
+    fn ask(&self, resolver: &Resolver, msg: &Message) -> std::io::Result<Message> {
+        if !self.randomize_case {
+            return self.send(resolver, msg);
+        }
+
+        let mut query = msg.clone();
+        for question in &mut query.questions {
+            question.name = randomize_case(&question.name);
+        }
+        let mut response = self.send(resolver, &query)?;
+        if response.questions != query.questions {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{} changed the case of the question", resolver.addr)));
+        }
+
+        // records about the asked names get the client's spelling back
+        for (asked, original) in query.questions.iter().zip(&msg.questions) {
+            let records = response.answers.iter_mut()
+                .chain(&mut response.name_servers)
+                .chain(&mut response.additional);
+            for record in records.filter(|record| record.name == asked.name) {
+                record.name = original.name.clone();
+            }
+        }
+        response.questions = msg.questions.clone();
+        Ok(response)
+    }
+
+    /// Sends `msg` to one resolver, retrying after timeouts. Every attempt
+    /// goes out under a fresh random ID, so a late reply to an earlier
+    /// attempt is simply discarded. A UDP reply with TC set didn't fit in a
+    /// datagram, so the query is asked again over TCP for the full answer.
+    fn send(&self, resolver: &Resolver, msg: &Message) -> std::io::Result<Message> {
+        let socket = match self.protocol {
+            Protocol::Udp if resolve_addr(&resolver.addr)?.is_ipv4() => Some(self.sockets_v4.get()?),
+            Protocol::Udp => Some(self.sockets_v6.get()?),
+            Protocol::Tcp => None,
+        };
+        let mut attempt = 0;
+        loop {
+            let result = match &socket {
+                Some(socket) => forward_query_on(socket, msg, &resolver.addr, self.timeout),
+                None => forward_query_tcp(msg, &resolver.addr, self.timeout),
+            };
+            match result {
+                Err(e) if is_timeout(&e) && attempt < self.retries => {
+                    attempt += 1;
+                    debug!("Resolver {} timed out, retrying ({}/{})", resolver.addr, attempt, self.retries);
+                }
+                Ok(response) if response.header.flags.tc == 1 && socket.is_some() => {
+                    debug!("Resolver {} truncated its reply, asking again over TCP", resolver.addr);
+                    return forward_query_tcp(msg, &resolver.addr, self.timeout);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Upstream for Upstreams {
+    fn query(&self, msg: &Message) -> std::io::Result<Message> {
+        self.forward(msg)
+    }
+
+    fn describe(&self) -> String {
+        let resolvers: Vec<String> = self.resolvers.iter()
+            .map(|resolver| match resolver.weight {
+                Some(weight) => format!("{}:{}", resolver.addr, weight),
+                None => resolver.addr.clone(),
+            })
+            .collect();
+        let case = if self.randomize_case { ", 0x20" } else { "" };
+        format!("{} over {}, {} retries{}", resolvers.join(","), self.protocol, self.retries, case)
+    }
+}
+
+/// The transport used to talk to the resolvers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Protocol, String> {
+        match s {
+            "udp" => Ok(Protocol::Udp),
+            "tcp" => Ok(Protocol::Tcp),
+            _ => Err(format!("unknown protocol {}, expected udp or tcp", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Protocol::Udp => "udp",
+            Protocol::Tcp => "tcp",
+        })
+    }
+}
+
+/// `name` with each ASCII letter in upper- or lowercase at random.
+fn randomize_case(name: &Name) -> Name {
+    let mut rng = rand::thread_rng();
+    let name = name.name.chars()
+        .map(|c| if rng.gen() { c.to_ascii_uppercase() } else { c.to_ascii_lowercase() })
+        .collect::<String>();
+    Name::new(&name)
+}
+
+fn is_timeout(e: &Error) -> bool {
+    matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock)
+}
+
+fn pick_weighted(candidates: &[&Resolver]) -> usize {
+    let weight = |resolver: &Resolver| resolver.weight.unwrap_or(1) as u64;
+    let total: u64 = candidates.iter().map(|resolver| weight(resolver)).sum();
+
+    let mut roll = rand::thread_rng().gen_range(0..total);
+    for (i, resolver) in candidates.iter().enumerate() {
+        if roll < weight(resolver) {
+            return i;
+        }
+        roll -= weight(resolver);
+    }
+    unreachable!("roll is always below the total weight")
+}
+
+/// Parses a `--resolver` value: an address, optionally followed by
+/// `:port` and then `:weight`, e.g. `8.8.8.8`, `8.8.8.8:53` or
+/// `8.8.8.8:53:3`. A bare address is asked on port 53.
+pub fn parse_resolver_spec(spec: &str) -> Result<(String, Option<u32>), String> {
+    parse_resolver_spec_with_port(spec, DEFAULT_PORT)
+}
+
+/// Like `parse_resolver_spec`, asking a bare address on `default_port`.
+pub fn parse_resolver_spec_with_port(spec: &str, default_port: u16) -> Result<(String, Option<u32>), String> {
+    if let Ok(addr) = spec.parse::<SocketAddr>() {
+        return Ok((addr.to_string(), None));
+    }
+    let bare = spec.strip_prefix('[').and_then(|spec| spec.strip_suffix(']')).unwrap_or(spec);
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return Ok((SocketAddr::new(ip, default_port).to_string(), None));
+    }
+
+    match spec.rsplit_once(':') {
+        Some((addr, weight)) if addr.parse::<SocketAddr>().is_ok() => match weight.parse() {
+            Ok(0) | Err(_) => Err(format!("invalid weight in resolver {}", spec)),
+            Ok(weight) => Ok((addr.to_string(), Some(weight))),
+        },
+        _ => Err(format!("invalid resolver address {}", spec)),
+    }
+}
+
+impl Resolver {
+    /// Whether a query could be sent now: the circuit is closed, or its
+    /// cooldown is over and no probe is out yet.
+    fn available(&self, now: Instant, cooldown: Duration) -> bool {
+        let health = self.health.lock().unwrap();
+        match health.opened_at {
+            Some(opened_at) => !health.probing && now.duration_since(opened_at) >= cooldown,
+            None => true,
+        }
+    }
+
+    /// Like `available`, but an open circuit lets the caller through as its
+    /// one probe, turning away everyone else until that probe has an outcome.
+    fn admit(&self, now: Instant, cooldown: Duration) -> bool {
+        let mut health = self.health.lock().unwrap();
+        match health.opened_at {
+            Some(opened_at) if health.probing || now.duration_since(opened_at) < cooldown => false,
+            Some(_) => {
+                health.probing = true;
+                true
+            }
+            None => true,
+        }
+    }
+
+    fn record_success(&self, latency: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health.probing = false;
+        if health.opened_at.take().is_some() {
+            info!("Resolver {} recovered", self.addr);
+        }
+        health.successes += 1;
+        health.latency += latency;
+        health.consecutive_failures = 0;
+    }
+
+    fn record_failure(&self, threshold: u32) {
+        let mut health = self.health.lock().unwrap();
+        health.probing = false;
+        health.failures += 1;
+        health.consecutive_failures += 1;
+
+        // a failed probe restarts the cooldown
+        if health.consecutive_failures >= threshold {
+            if health.opened_at.is_none() {
+                warn!("Resolver {} failed {} times in a row, skipping it", self.addr, health.consecutive_failures);
+            }
+            health.opened_at = Some(Instant::now());
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::server::ipv4_to_bytes;
+use crate::{Answer, Name, Question, ResourceClass, ResourceType};
+
+const DEFAULT_TTL: u32 = 60;
+
+/// Records this server is authoritative for, loaded from a zone file with
+/// one record per line:
+///
+/// ```text
+/// # comment
+/// example.com A 1.2.3.4
+/// www.example.com 300 A 1.2.3.5
+/// pool.example.com A 1.2.3.6,1.2.3.7,1.2.3.8
+/// ```
+///
+/// A name with several records, given comma-separated or on separate
+/// lines, gets all of them in every answer, rotated by one position per
+/// query so clients taking the first address spread the load.
+#[derive(Debug, Default)]
+pub struct Zone {
+    records: HashMap<String, Records>,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    answers: Vec<Answer>,
+    // bumped on every lookup, picks which record goes first
+    rotation: AtomicUsize,
+}
+
+impl Zone {
+    pub fn load(path: &str) -> std::io::Result<Zone> {
+        let mut zone = Zone::default();
+
+        for (lineno, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |reason: &str| Error::new(
+                ErrorKind::InvalidData,
+                format!("{}:{}: {}", path, lineno + 1, reason),
+            );
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (name, ttl, rtype, value) = match fields[..] {
+                [name, rtype, value] => (name, DEFAULT_TTL, rtype, value),
+                [name, ttl, rtype, value] => {
+                    let ttl = ttl.parse().map_err(|_| invalid("invalid ttl"))?;
+                    (name, ttl, rtype, value)
+                }
+                _ => return Err(invalid("expected: name [ttl] type value")),
+            };
+
+            for value in value.split(',') {
+                let answer = match rtype {
+                    "A" => {
+                        let ip: Ipv4Addr = value.parse().map_err(|_| invalid("invalid IPv4 address"))?;
+                        let rdata = ipv4_to_bytes(ip);
+                        Answer { name: Name::new(name), rtype: ResourceType::A, class: ResourceClass::IN, ttl, rdlength: 4, rdata }
+                    }
+                    _ => return Err(invalid("unsupported record type")),
+                };
+
+                zone.records.entry(Name::key(name)).or_default().answers.push(answer);
+            }
+        }
+
+        Ok(zone)
+    }
+
+    /// Returns the records matching the question, named as the question
+    /// was asked and in this query's rotation, or `None` if the name isn't
+    /// part of the zone.
+    pub fn lookup(&self, question: &Question) -> Option<Vec<Answer>> {
+        let records = self.records.get(&Name::key(&question.name.name))?;
+        let mut answers: Vec<Answer> = records.answers.iter()
+            .filter(|record| question.rtype.matches(record.rtype) && question.class.matches(record.class))
+            .map(|record| Answer { name: question.name.clone(), ..record.clone() })
+            .collect();
+
+        if !answers.is_empty() {
+            let rotation = records.rotation.fetch_add(1, Ordering::Relaxed);
+            let len = answers.len();
+            answers.rotate_left(rotation % len);
+        }
+        Some(answers)
+    }
+}
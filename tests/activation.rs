@@ -0,0 +1,47 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use common::{query, ServerProcess};
+use dns_starter_rust::ResourceType;
+
+extern "C" {
+    fn dup2(oldfd: i32, newfd: i32) -> i32;
+}
+
+#[test]
+fn socket_passed_by_systemd_is_used() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    // a duplicate is never descriptor 3 itself, so dup2 below always
+    // creates a fresh descriptor that survives exec
+    let duplicate = socket.try_clone().unwrap();
+    let fd = duplicate.as_raw_fd();
+
+    // exec keeps the shell's PID, which is how LISTEN_PID can name the
+    // server before it exists
+    let mut command = Command::new("sh");
+    command.args(["-c", "LISTEN_PID=$$ LISTEN_FDS=1 exec \"$0\" \"$@\"", env!("CARGO_BIN_EXE_dns-starter-rust"), "--port", "0"]);
+    unsafe {
+        command.pre_exec(move || {
+            // put the socket where systemd would, as descriptor 3
+            if dup2(fd, 3) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let server = ServerProcess::spawn(command);
+    drop(duplicate);
+    drop(socket);
+
+    assert_eq!(server.addr, addr);
+    let response = server.exchange(&query(5, &[("example.com", ResourceType::A)]));
+    assert_eq!(response.header.id, 5);
+}
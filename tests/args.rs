@@ -0,0 +1,169 @@
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use common::temp_file;
+use dns_starter_rust::args;
+use dns_starter_rust::logging;
+
+fn argv(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn representative_argv_sets_every_field() {
+    let hosts = temp_file("192.0.2.7 nas.lan\n");
+    let blocklist = temp_file("ads.example.com\n");
+    let config = args::parse(argv(&[
+        "--listen", "::1",
+        "--port", "5353",
+        "--unix-socket", "/tmp/dns.sock",
+        "--buffer-size", "1232",
+        "--max-records", "64",
+        "--metrics-port", "9153",
+        "--resolver", "192.0.2.1",
+        "--resolver", "192.0.2.2:5300:3",
+        "--upstream-port", "5353",
+        "--forward-protocol", "tcp",
+        "--retries", "1",
+        "--hosts", hosts.to_str().unwrap(),
+        "--blocklist", blocklist.to_str().unwrap(),
+        "--health-name", "alive.check",
+        "--chaos-txt", "dns 1.0",
+        "--rate-limit", "10:20",
+        "--rewrite", "1.1.1.1=10.0.0.1",
+        "--ttl", "300",
+        "--default-a", "192.0.2.53",
+        "--default-aaaa", "2001:db8::53",
+        "--cache-size", "100",
+        "--cookies",
+        "--strict",
+        "--deny-recursion",
+        "--strip-edns",
+        "--no-compression",
+        "--shuffle-answers",
+        "--chase-cnames",
+        "--corrupt-ancount", "-1",
+        "--log-format", "json",
+        "-vv",
+    ])).unwrap();
+
+    assert_eq!(config.listen, IpAddr::V6(Ipv6Addr::LOCALHOST));
+    assert_eq!(config.port, 5353);
+    assert!(!config.stdio);
+    assert_eq!(config.unix_socket.as_deref(), Some("/tmp/dns.sock"));
+    assert_eq!(config.buffer_size, 1232);
+    assert_eq!(config.max_records, 64);
+    assert_eq!(config.metrics_port, Some(9153));
+    // the bare address picks up --upstream-port even though it came first
+    assert_eq!(config.upstreams.as_ref().unwrap().describe(), "192.0.2.1:5353,192.0.2.2:5300:3 over tcp, 1 retries");
+    assert!(config.strict);
+    assert!(config.deny_recursion);
+    assert!(config.strip_edns);
+    assert!(config.no_compression);
+    assert_eq!(config.log_level, Some(logging::Level::Trace));
+    assert_eq!(config.log_format, logging::Format::Json);
+    assert!(config.zone.is_none());
+    assert!(config.hosts.is_some());
+    assert_eq!(config.health_name, "alive.check");
+    assert_eq!(config.chaos_txt.as_deref(), Some("dns 1.0"));
+    assert_eq!(config.corrupt_ancount, Some(-1));
+    assert_eq!(config.cname_chase_depth, Some(8));
+    assert!(config.blocklist.is_some());
+    assert!(config.policy.is_none());
+    assert!(config.rate_limiter.is_some());
+    assert!(config.cookies.is_some());
+    assert_eq!(config.cache.as_ref().map(|cache| cache.capacity()), Some(100));
+    assert!(config.preload.is_empty());
+    assert_eq!(config.rewrites.len(), 1);
+    assert_eq!(config.ttl, 300);
+    assert!(config.shuffle_answers);
+    assert_eq!(config.default_a, Ipv4Addr::new(192, 0, 2, 53));
+    assert_eq!(config.default_aaaa, "2001:db8::53".parse::<Ipv6Addr>().unwrap());
+
+    let summary = config.to_string();
+    assert!(summary.starts_with("listen=::1 port=5353 "), "{}", summary);
+    assert!(summary.contains(" cache=100 "), "{}", summary);
+    assert!(summary.contains(" chaos_txt=\"dns 1.0\" "), "{}", summary);
+}
+
+#[test]
+fn no_arguments_give_the_defaults() {
+    let config = args::parse(Vec::new()).unwrap();
+    assert_eq!(config.listen, IpAddr::V4(Ipv4Addr::LOCALHOST));
+    assert_eq!(config.port, 2053);
+    assert!(config.upstreams.is_none());
+    assert!(config.cache.is_none());
+    assert_eq!(config.chaos_txt.as_deref(), Some("not disclosed"));
+}
+
+#[test]
+fn bad_arguments_are_reported() {
+    let err = args::parse(argv(&["--port", "http"])).unwrap_err();
+    assert_eq!(err, "Invalid value for --port");
+
+    let err = args::parse(argv(&["--ttl"])).unwrap_err();
+    assert_eq!(err, "Missing value for --ttl");
+
+    let err = args::parse(argv(&["--bogus"])).unwrap_err();
+    assert_eq!(err, "Unrecognized option --bogus");
+
+    let err = args::parse(argv(&["--qname-minimization"])).unwrap_err();
+    assert_eq!(err, "--qname-minimization needs --root");
+
+    let err = args::parse(argv(&["--root", "127.0.0.1", "--resolver", "192.0.2.1"])).unwrap_err();
+    assert_eq!(err, "--root and --resolver can't be combined");
+
+    let err = args::parse(argv(&["--preload", temp_file("example.com\n").to_str().unwrap()])).unwrap_err();
+    assert_eq!(err, "--preload needs --cache and a --resolver");
+}
+
+#[test]
+fn config_file_settings_apply_and_the_command_line_overrides_them() {
+    let path = temp_file(concat!(
+        "# a forwarding cache\n",
+        "port = 5353\n",
+        "resolver = [\"192.0.2.1\", \"192.0.2.2:5300:3\"]  # two of them\n",
+        "cache-size = 1_000\n",
+        "strict = true\n",
+        "strip-edns = false\n",
+        "chaos-txt = \"dns \\\"1.0\\\"\"\n",
+        "log-format = \"json\"\n",
+    ));
+    let path = path.to_str().unwrap();
+
+    let config = args::parse(argv(&["--config", path])).unwrap();
+    assert_eq!(config.port, 5353);
+    assert_eq!(config.upstreams.as_ref().unwrap().describe(), "192.0.2.1:53,192.0.2.2:5300:3 over udp, 2 retries");
+    assert_eq!(config.cache.as_ref().map(|cache| cache.capacity()), Some(1000));
+    assert!(config.strict);
+    assert!(!config.strip_edns);
+    assert_eq!(config.chaos_txt.as_deref(), Some("dns \"1.0\""));
+    assert_eq!(config.log_format, logging::Format::Json);
+
+    // the file applies first even when --config comes last
+    let config = args::parse(argv(&["--port", "8053", "--resolver", "192.0.2.9", "--config", path])).unwrap();
+    assert_eq!(config.port, 8053);
+    assert_eq!(config.upstreams.as_ref().unwrap().describe(), "192.0.2.9:53 over udp, 2 retries");
+    assert_eq!(config.cache.as_ref().map(|cache| cache.capacity()), Some(1000));
+}
+
+#[test]
+fn config_file_errors_name_the_line() {
+    let path = temp_file("port = 5353\nresolvers = \"192.0.2.1\"\n");
+    let err = args::parse(argv(&["--config", path.to_str().unwrap()])).unwrap_err();
+    assert_eq!(err, format!("Failed to load config file: {}:2: unknown key resolvers", path.display()));
+
+    let path = temp_file("cache = \"yes\"\n");
+    let err = args::parse(argv(&["--config", path.to_str().unwrap()])).unwrap_err();
+    assert_eq!(err, format!("Failed to load config file: {}:1: cache takes true or false", path.display()));
+
+    let path = temp_file("listen = 127.0.0.1\n");
+    let err = args::parse(argv(&["--config", path.to_str().unwrap()])).unwrap_err();
+    assert!(err.ends_with(":1: invalid value 127.0.0.1, strings need quotes"), "{}", err);
+
+    // values are checked like the command line's
+    let path = temp_file("port = \"http\"\n");
+    let err = args::parse(argv(&["--config", path.to_str().unwrap()])).unwrap_err();
+    assert_eq!(err, "Invalid value for --port");
+}
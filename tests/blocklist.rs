@@ -0,0 +1,81 @@
+mod common;
+
+use std::fs;
+use std::net::Ipv4Addr;
+
+use common::{exchange, query, temp_file, MockResolver};
+use dns_starter_rust::blocklist::Blocklist;
+use dns_starter_rust::ede::ExtendedError;
+use dns_starter_rust::server::Config;
+use dns_starter_rust::upstream::Upstreams;
+use dns_starter_rust::{Answer, Name, ResourceClass, ResourceType, ResponseCode};
+
+fn blocking_config(upstream: &MockResolver) -> Config {
+    let path = temp_file("# ads\nAds.Example.com.\n\ntracker.test # analytics\n");
+    let blocklist = Blocklist::load(path.to_str().unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    Config {
+        upstreams: Some(Box::new(Upstreams::new(vec![upstream.addr.clone()]))),
+        blocklist: Some(blocklist),
+        ..Config::default()
+    }
+}
+
+#[test]
+fn blocked_names_and_their_subdomains_get_nxdomain() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = blocking_config(&upstream);
+
+    for name in ["ads.example.com", "cdn.ADS.example.com", "tracker.test"] {
+        let response = exchange(&config, &query(1, &[(name, ResourceType::A)]));
+        assert_eq!(response.header.flags.rcode, ResponseCode::NXDomain as u8, "{} wasn't blocked", name);
+        assert!(response.answers.is_empty());
+    }
+    assert_eq!(upstream.queries(), 0);
+}
+
+#[test]
+fn unrelated_names_pass_through() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = blocking_config(&upstream);
+
+    for name in ["example.com", "notads.example.com", "tracker.test.example"] {
+        let response = exchange(&config, &query(1, &[(name, ResourceType::A)]));
+        assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8, "{} was blocked", name);
+        assert_eq!(response.answers.len(), 1);
+    }
+    assert_eq!(upstream.queries(), 3);
+}
+
+#[test]
+fn blocked_names_carry_an_extended_error_when_asked_over_edns() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = blocking_config(&upstream);
+
+    let opt = Answer { name: Name::new(""), rtype: ResourceType::OPT, class: ResourceClass::Unknown(4096), ttl: 0, rdlength: 0, rdata: Vec::new() };
+    let response = exchange(&config, &query(1, &[("ads.example.com", ResourceType::A)]).with_additional(opt.clone()));
+    assert_eq!(response.header.flags.rcode, ResponseCode::NXDomain as u8);
+    assert_eq!(ExtendedError::find(&response).unwrap().code, ExtendedError::BLOCKED);
+
+    // a client that didn't ask for EDNS gets no OPT record to put it in
+    let response = exchange(&config, &query(2, &[("ads.example.com", ResourceType::A)]));
+    assert!(response.additional.is_empty());
+
+    let response = exchange(&config, &query(3, &[("example.com", ResourceType::A)]).with_additional(opt));
+    assert_eq!(ExtendedError::find(&response), None);
+}
+
+#[test]
+fn extended_error_goes_in_an_opt_of_our_own() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = blocking_config(&upstream);
+
+    // padding, which is the client's business and not to come back
+    let padding = vec![0, 12, 0, 4, 0, 0, 0, 0];
+    let opt = Answer { name: Name::new(""), rtype: ResourceType::OPT, class: ResourceClass::Unknown(4096), ttl: 0, rdlength: padding.len() as u16, rdata: padding };
+    let response = exchange(&config, &query(1, &[("ads.example.com", ResourceType::A)]).with_additional(opt));
+
+    assert_eq!(response.additional.len(), 1);
+    assert_eq!(response.additional[0].rdata, ExtendedError::new(ExtendedError::BLOCKED, "blocked by policy").to_option());
+}
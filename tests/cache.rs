@@ -0,0 +1,215 @@
+mod common;
+
+use std::fs;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use common::{a_record, exchange, query, reply_to, soa_record, temp_file, MockResolver};
+use dns_starter_rust::cache::Cache;
+use dns_starter_rust::preload;
+use dns_starter_rust::server::{warm_cache, Config};
+use dns_starter_rust::upstream::Upstreams;
+use dns_starter_rust::{Answer, Name, ResourceClass, ResourceType, ResponseCode};
+
+fn caching_config(upstream: &MockResolver) -> Config {
+    Config {
+        upstreams: Some(Box::new(Upstreams::new(vec![upstream.addr.clone()]))),
+        cache: Some(Cache::new()),
+        ..Config::default()
+    }
+}
+
+/// Answers NXDOMAIN, with the zone's SOA in the authority section if
+/// `with_soa` is set.
+fn nxdomain_resolver(with_soa: bool) -> MockResolver {
+    MockResolver::start(move |query| {
+        let mut reply = reply_to(query);
+        reply.header.flags.rcode = ResponseCode::NXDomain as u8;
+        if with_soa {
+            reply.add_name_server(soa_record(&Name::new("example"), 1));
+        }
+        Some(reply)
+    })
+}
+
+#[test]
+fn nxdomain_is_answered_from_the_cache() {
+    let upstream = nxdomain_resolver(true);
+    let config = caching_config(&upstream);
+
+    let first = exchange(&config, &query(1, &[("missing.example", ResourceType::A)]));
+    let second = exchange(&config, &query(2, &[("Missing.Example", ResourceType::A)]));
+
+    assert_eq!(upstream.queries(), 1);
+    assert_eq!(first.header.flags.rcode, ResponseCode::NXDomain as u8);
+    assert_eq!(second.header.id, 2);
+    assert_eq!(second.header.flags.rcode, ResponseCode::NXDomain as u8);
+    assert_eq!(second.questions[0].name.name, "Missing.Example");
+    assert_eq!(second.name_servers[0].rtype, ResourceType::SOA);
+    // the SOA's own TTL is 3600, but negative answers are capped by MINIMUM
+    assert!(second.name_servers[0].ttl <= 300);
+    assert_eq!(config.metrics.cache_hits(), 1);
+    assert_eq!(config.metrics.cache_misses(), 1);
+}
+
+#[test]
+fn nxdomain_without_an_soa_is_not_cached() {
+    let upstream = nxdomain_resolver(false);
+    let config = caching_config(&upstream);
+
+    exchange(&config, &query(1, &[("missing.example", ResourceType::A)]));
+    exchange(&config, &query(2, &[("missing.example", ResourceType::A)]));
+
+    assert_eq!(upstream.queries(), 2);
+}
+
+#[test]
+fn positive_answers_are_cached_per_type() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = caching_config(&upstream);
+
+    for id in 0..3 {
+        let response = exchange(&config, &query(id, &[("www.example", ResourceType::A)]));
+        assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+    }
+    exchange(&config, &query(4, &[("www.example", ResourceType::MX)]));
+
+    assert_eq!(upstream.queries(), 2);
+    assert_eq!(config.cache.as_ref().unwrap().len(), 2);
+}
+
+#[test]
+fn answers_are_cached_per_do_bit() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = caching_config(&upstream);
+    let with_do = |id: u16| {
+        let mut msg = query(id, &[("www.example", ResourceType::A)]);
+        msg.add_additional(Answer { name: Name::new(""), rtype: ResourceType::OPT, class: ResourceClass::Unknown(1232), ttl: 0x8000, rdlength: 0, rdata: Vec::new() });
+        msg
+    };
+
+    exchange(&config, &query(1, &[("www.example", ResourceType::A)]));
+    // an answer fetched without DNSSEC records won't do for a DO query
+    exchange(&config, &with_do(2));
+    assert_eq!(upstream.queries(), 2);
+
+    // but each is there for the next query like it
+    exchange(&config, &with_do(3));
+    exchange(&config, &query(4, &[("www.example", ResourceType::A)]));
+    assert_eq!(upstream.queries(), 2);
+    assert_eq!(config.cache.as_ref().unwrap().len(), 2);
+}
+
+#[test]
+fn cache_hits_carry_our_opt_like_forwarded_replies() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = caching_config(&upstream);
+    let with_edns = |id: u16| {
+        let mut msg = query(id, &[("www.example", ResourceType::A)]);
+        msg.add_additional(Answer { name: Name::new(""), rtype: ResourceType::OPT, class: ResourceClass::Unknown(1232), ttl: 0, rdlength: 0, rdata: Vec::new() });
+        msg
+    };
+
+    let forwarded = exchange(&config, &with_edns(1));
+    let cached = exchange(&config, &with_edns(2));
+
+    assert_eq!(upstream.queries(), 1);
+    assert_eq!(cached.answers[0].rdata, vec![10, 0, 0, 1]);
+    assert_eq!(forwarded.additional.len(), 1);
+    assert_eq!(forwarded.additional[0].rtype, ResourceType::OPT);
+    assert_eq!(cached.additional, forwarded.additional);
+    assert_eq!(cached.header.arcount, 1);
+}
+
+#[test]
+fn full_cache_evicts_the_least_recently_used_entry() {
+    let cache = Cache::new().with_capacity(3);
+    let question = |name: &str| query(1, &[(name, ResourceType::A)]);
+    let answer = |name: &str| {
+        let mut reply = reply_to(question(name));
+        reply.add_answer(a_record(&Name::new(name), Ipv4Addr::new(10, 0, 0, 1)));
+        reply
+    };
+
+    for name in ["a.example", "b.example", "c.example"] {
+        cache.insert(&question(name), &answer(name));
+    }
+    // a is used again, so b is now the oldest
+    assert!(cache.get(&question("a.example")).is_some());
+    cache.insert(&question("d.example"), &answer("d.example"));
+
+    assert_eq!(cache.len(), 3);
+    assert!(cache.get(&question("b.example")).is_none());
+    for name in ["a.example", "c.example", "d.example"] {
+        assert!(cache.get(&question(name)).is_some(), "{} was evicted", name);
+    }
+
+    // replacing an entry doesn't evict anything
+    cache.insert(&question("c.example"), &answer("c.example"));
+    assert_eq!(cache.len(), 3);
+
+    // and a long run of distinct names never grows it past the cap
+    for i in 0..1000 {
+        let name = format!("{}.flood.example", i);
+        cache.insert(&question(&name), &answer(&name));
+    }
+    assert_eq!(cache.len(), 3);
+    assert!(cache.get(&question("999.flood.example")).is_some());
+}
+
+#[test]
+fn dump_lists_live_entries_with_their_remaining_ttls() {
+    let cache = Cache::new();
+    let question = query(1, &[("WWW.Example", ResourceType::A)]);
+    let mut positive = reply_to(question.clone());
+    positive.add_answer(a_record(&Name::new("WWW.Example"), Ipv4Addr::new(10, 0, 0, 1)));
+    cache.insert(&question, &positive);
+    let question = query(2, &[("missing.example", ResourceType::AAAA)]);
+    let mut negative = reply_to(question.clone());
+    negative.header.flags.rcode = ResponseCode::NXDomain as u8;
+    negative.add_name_server(soa_record(&Name::new("example"), 1));
+    cache.insert(&question, &negative);
+
+    let dump = cache.dump();
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", dump);
+
+    // most recently used first, keyed by the lowercased name
+    assert!(lines[0].starts_with(";; missing.example. IN AAAA, rcode 3, "), "{}", lines[0]);
+    assert!(lines[1].starts_with("example.\t300\tIN\tSOA\t"), "{}", lines[1]);
+    assert!(lines[2].starts_with(";; www.example. IN A, rcode 0, "), "{}", lines[2]);
+    assert_eq!(lines[3], "WWW.Example.\t300\tIN\tA\t10.0.0.1");
+
+    // a TTL of 300 leaves at most 300 seconds, and at least a few
+    let left: u64 = lines[2].rsplit(", ").next().unwrap().trim_end_matches("s left").parse().unwrap();
+    assert!((295..=300).contains(&left), "{}", left);
+}
+
+#[test]
+fn names_that_fail_to_preload_are_skipped() {
+    let upstream = MockResolver::start(|query| {
+        // the resolver never answers for this one
+        if query.questions[0].name.name == "down.example" {
+            return None;
+        }
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.clone();
+        reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 1)));
+        Some(reply)
+    });
+    let path = temp_file("down.example\nup.example AAAA\n");
+    let config = Config {
+        upstreams: Some(Box::new(Upstreams::new(vec![upstream.addr.clone()]).with_timeout(Duration::from_millis(50)).with_retries(0))),
+        preload: preload::load(path.to_str().unwrap()).unwrap(),
+        ..caching_config(&upstream)
+    };
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(warm_cache(&config), 1);
+    assert_eq!(config.cache.as_ref().unwrap().len(), 1);
+
+    let response = exchange(&config, &query(1, &[("up.example", ResourceType::AAAA)]));
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(config.metrics.cache_hits(), 1);
+    assert_eq!(upstream.queries(), 2);
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use std::net::{Ipv4Addr, TcpListener};
+use std::thread;
+
+use common::{a_record, reply_to, soa_record, MockResolver};
+use dns_starter_rust::client::{resolve, transfer_zone};
+use dns_starter_rust::{tcp, Message, Name, QType, ResourceType};
+
+#[test]
+fn resolve_returns_every_a_record() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]);
+
+    let answers = resolve("multi.example", ResourceType::A, &upstream.addr).unwrap();
+
+    let addresses: Vec<&[u8]> = answers.iter().map(|answer| &answer.rdata[..]).collect();
+    assert_eq!(addresses, vec![&[10, 0, 0, 1][..], &[10, 0, 0, 2][..]]);
+}
+
+#[test]
+fn zone_transfer_collects_records_across_messages() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let primary = listener.local_addr().unwrap().to_string();
+
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let query = tcp::recv(&mut stream).unwrap();
+        assert_eq!(query.questions[0].rtype, QType::AXFR);
+
+        let zone = query.questions[0].name.clone();
+        let streamed = [
+            vec![soa_record(&zone, 2024010101), a_record(&Name::new("www.example"), Ipv4Addr::new(192, 0, 2, 1))],
+            vec![a_record(&Name::new("mail.example"), Ipv4Addr::new(192, 0, 2, 2))],
+            vec![soa_record(&zone, 2024010101)],
+        ];
+        for answers in streamed {
+            let mut reply: Message = reply_to(query.clone());
+            reply.header.ancount = answers.len() as u16;
+            reply.answers = answers;
+            tcp::send(&mut stream, &reply).unwrap();
+        }
+    });
+
+    let records = transfer_zone("example", &primary).unwrap();
+
+    let types: Vec<ResourceType> = records.iter().map(|record| record.rtype).collect();
+    assert_eq!(types, vec![ResourceType::SOA, ResourceType::A, ResourceType::A, ResourceType::SOA]);
+    assert_eq!(records[1].rdata, vec![192, 0, 2, 1]);
+    assert_eq!(records[2].rdata, vec![192, 0, 2, 2]);
+}
+
+/// An AXFR reply holding just the SOA of `example`, its MNAME and RNAME
+/// compressed. With `owner_inline` the owner is written out after the
+/// question and the names point at it; otherwise everything points back at
+/// the question.
+fn compressed_soa_reply(id: u16, serial: u32, owner_inline: bool) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&id.to_be_bytes());
+    bytes.extend_from_slice(&[0x84, 0x00, 0, 1, 0, 1, 0, 0, 0, 0]);
+    bytes.extend_from_slice(b"\x07example\x00\x00\xfc\x00\x01");
+
+    let target = if owner_inline {
+        let offset = bytes.len() as u8;
+        bytes.extend_from_slice(b"\x07example\x00");
+        offset
+    } else {
+        bytes.extend_from_slice(&[0xc0, 12]);
+        12
+    };
+    bytes.extend_from_slice(&[0, 6, 0, 1, 0, 0, 0x0e, 0x10]);
+
+    let mut rdata = Vec::new();
+    rdata.extend_from_slice(b"\x03ns1");
+    rdata.extend_from_slice(&[0xc0, target]);
+    rdata.extend_from_slice(b"\x0ahostmaster");
+    rdata.extend_from_slice(&[0xc0, target]);
+    for value in [serial, 3600, 600, 86400, 300] {
+        rdata.extend_from_slice(&value.to_be_bytes());
+    }
+    bytes.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(&rdata);
+    bytes
+}
+
+#[test]
+fn zone_transfer_ends_on_an_soa_compressed_differently_from_the_first() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let primary = listener.local_addr().unwrap().to_string();
+
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let query = tcp::recv(&mut stream).unwrap();
+
+        tcp::send_bytes(&mut stream, &compressed_soa_reply(query.header.id, 2024010101, false)).unwrap();
+        let mut reply: Message = reply_to(query.clone());
+        reply.header.ancount = 1;
+        reply.answers = vec![a_record(&Name::new("www.example"), Ipv4Addr::new(192, 0, 2, 1))];
+        tcp::send(&mut stream, &reply).unwrap();
+        tcp::send_bytes(&mut stream, &compressed_soa_reply(query.header.id, 2024010101, true)).unwrap();
+    });
+
+    let records = transfer_zone("example", &primary).unwrap();
+
+    let types: Vec<ResourceType> = records.iter().map(|record| record.rtype).collect();
+    assert_eq!(types, vec![ResourceType::SOA, ResourceType::A, ResourceType::SOA]);
+    assert_eq!(records[0].rdata, soa_record(&Name::new("example"), 2024010101).rdata);
+}
+
+#[test]
+fn zone_transfer_takes_messages_with_more_than_a_thousand_records() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let primary = listener.local_addr().unwrap().to_string();
+
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let query = tcp::recv(&mut stream).unwrap();
+
+        let zone = query.questions[0].name.clone();
+        let mut answers = vec![soa_record(&zone, 2024010101)];
+        for i in 0..1500u16 {
+            let [high, low] = i.to_be_bytes();
+            answers.push(a_record(&Name::new(&format!("host{}.example", i)), Ipv4Addr::new(10, 0, high, low)));
+        }
+        answers.push(soa_record(&zone, 2024010101));
+        let mut reply: Message = reply_to(query);
+        reply.header.ancount = answers.len() as u16;
+        reply.answers = answers;
+        tcp::send_bytes(&mut stream, &reply.to_bytes_compressed()).unwrap();
+    });
+
+    let records = transfer_zone("example", &primary).unwrap();
+
+    assert_eq!(records.len(), 1502);
+    assert_eq!(records[1500].rdata, vec![10, 0, 5, 219]);
+}
@@ -0,0 +1,238 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns_starter_rust::server::{handle_connection, local_addr_for, Config};
+use dns_starter_rust::{Answer, Header, Message, MessageType, Name, QType, Question, ResourceClass, ResourceType};
+
+/// A fake upstream resolver running on its own thread. Every query is
+/// passed to the handler, whose reply (if any) is sent back.
+pub struct MockResolver {
+    pub addr: String,
+    queries: Arc<AtomicUsize>,
+}
+
+impl MockResolver {
+    pub fn start<F>(handler: F) -> MockResolver
+    where
+        F: Fn(Message) -> Option<Message> + Send + 'static,
+    {
+        MockResolver::start_on("127.0.0.1:0", handler)
+    }
+
+    /// Like `start`, listening on `addr` instead of an IPv4 loopback port.
+    pub fn start_on<F>(addr: &str, handler: F) -> MockResolver
+    where
+        F: Fn(Message) -> Option<Message> + Send + 'static,
+    {
+        let socket = UdpSocket::bind(addr).unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let queries = Arc::new(AtomicUsize::new(0));
+
+        let counter = queries.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            while let Ok((size, source)) = socket.recv_from(&mut buf) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                if let Some(reply) = handler(Message::parse(&buf[..size]).unwrap()) {
+                    let _ = socket.send_to(&reply.to_bytes(), source);
+                }
+            }
+        });
+
+        MockResolver { addr, queries }
+    }
+
+    /// Answers every question with the given A records.
+    pub fn answering(ips: Vec<Ipv4Addr>) -> MockResolver {
+        MockResolver::start(move |query| {
+            let mut reply = reply_to(query);
+            let name = reply.questions[0].name.clone();
+            for ip in &ips {
+                reply.add_answer(a_record(&name, *ip));
+            }
+            Some(reply)
+        })
+    }
+
+    pub fn queries(&self) -> usize {
+        self.queries.load(Ordering::SeqCst)
+    }
+}
+
+pub fn reply_to(query: Message) -> Message {
+    let mut reply = query;
+    reply.header.flags.qr = MessageType::Reply;
+    reply.header.flags.ra = 1;
+    reply
+}
+
+pub fn a_record(name: &Name, ip: Ipv4Addr) -> Answer {
+    Answer {
+        name: name.clone(),
+        rtype: ResourceType::A,
+        class: ResourceClass::IN,
+        ttl: 300,
+        rdlength: 4,
+        rdata: ip.octets().to_vec(),
+    }
+}
+
+/// Builds a recursive query with one question per `(name, type)` pair.
+pub fn query<T: Into<QType> + Copy>(id: u16, questions: &[(&str, T)]) -> Message {
+    let mut header = Header::new(id, MessageType::Query);
+    header.flags.rd = 1;
+    let mut msg = Message::new(header);
+    for (name, rtype) in questions {
+        msg.add_question(Question { name: Name::new(name), rtype: (*rtype).into(), class: ResourceClass::IN.into() });
+    }
+    msg
+}
+
+/// Runs `query` through handle_connection and returns the raw reply bytes.
+pub fn exchange_bytes(config: &Config, query: &Message) -> Vec<u8> {
+    exchange_raw(config, &query.to_bytes())
+}
+
+/// Runs raw query bytes, well-formed or not, through handle_connection and
+/// returns the raw reply bytes.
+pub fn exchange_raw(config: &Config, query: &[u8]) -> Vec<u8> {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    let source = client.local_addr().unwrap();
+    handle_connection(&server, &source, query, config);
+
+    let mut buf = [0; 4096];
+    let (size, _) = client.recv_from(&mut buf).unwrap();
+    buf[..size].to_vec()
+}
+
+/// Runs `query` through handle_connection and parses the reply.
+pub fn exchange(config: &Config, query: &Message) -> Message {
+    Message::parse(&exchange_bytes(config, query)).unwrap()
+}
+
+pub fn cname_record(name: &Name, target: &str) -> Answer {
+    let mut rdata = Vec::new();
+    Name::new(target).write_to(&mut rdata);
+    Answer {
+        name: name.clone(),
+        rtype: ResourceType::CNAME,
+        class: ResourceClass::IN,
+        ttl: 300,
+        rdlength: rdata.len() as u16,
+        rdata,
+    }
+}
+
+pub fn ns_record(zone: &Name, server: &str) -> Answer {
+    let mut rdata = Vec::new();
+    Name::new(server).write_to(&mut rdata);
+    Answer {
+        name: zone.clone(),
+        rtype: ResourceType::NS,
+        class: ResourceClass::IN,
+        ttl: 3600,
+        rdlength: rdata.len() as u16,
+        rdata,
+    }
+}
+
+/// An SOA for `zone` whose MINIMUM (the negative caching TTL) is 300.
+pub fn soa_record(zone: &Name, serial: u32) -> Answer {
+    let mut rdata = Vec::new();
+    Name::new("ns1.example").write_to(&mut rdata);
+    Name::new("hostmaster.example").write_to(&mut rdata);
+    for value in [serial, 3600, 600, 86400, 300] {
+        rdata.extend_from_slice(&value.to_be_bytes());
+    }
+    Answer { name: zone.clone(), rtype: ResourceType::SOA, class: ResourceClass::IN, ttl: 3600, rdlength: rdata.len() as u16, rdata }
+}
+
+/// Writes `contents` to a fresh file in the temp directory. Each call gets
+/// its own path, so tests running in parallel don't trip over each other.
+pub fn temp_file(contents: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let id = COUNTER.fetch_add(1, Ordering::SeqCst);
+    let path = std::env::temp_dir().join(format!("dns-test-{}-{}", std::process::id(), id));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+/// The real server binary, listening on a port picked by the OS. It is
+/// killed when dropped.
+pub struct ServerProcess {
+    pub addr: SocketAddr,
+    pub child: Child,
+}
+
+impl ServerProcess {
+    /// Starts the server with `args` and waits until it reports the address
+    /// it is listening on.
+    pub fn start(args: &[&str]) -> ServerProcess {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_dns-starter-rust"));
+        command.args(["--port", "0"]).args(args);
+        ServerProcess::spawn(command)
+    }
+
+    /// Like `start`, for a command that runs the server some other way.
+    pub fn spawn(mut command: Command) -> ServerProcess {
+        let mut child = command.stderr(Stdio::piped()).spawn().unwrap();
+
+        let mut stderr = BufReader::new(child.stderr.take().unwrap());
+        let mut line = String::new();
+        let addr = loop {
+            line.clear();
+            if stderr.read_line(&mut line).unwrap() == 0 {
+                let _ = child.kill();
+                panic!("server exited before listening");
+            }
+            if let Some((_, addr)) = line.trim().split_once("Listening on ") {
+                break addr.parse().unwrap();
+            }
+        };
+
+        // keep draining the log so the server never blocks on a full pipe
+        thread::spawn(move || {
+            let mut line = String::new();
+            while stderr.read_line(&mut line).is_ok_and(|n| n > 0) {
+                line.clear();
+            }
+        });
+
+        ServerProcess { addr, child }
+    }
+
+    /// Sends `query` over UDP and parses the reply.
+    pub fn exchange(&self, query: &Message) -> Message {
+        Message::parse(&self.exchange_bytes(&query.to_bytes())).unwrap()
+    }
+
+    /// Sends raw query bytes over UDP and returns the raw reply.
+    pub fn exchange_bytes(&self, query: &[u8]) -> Vec<u8> {
+        let client = UdpSocket::bind(local_addr_for(&self.addr)).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.send_to(query, self.addr).unwrap();
+
+        let mut buf = [0; 4096];
+        let (size, _) = client.recv_from(&mut buf).unwrap();
+        buf[..size].to_vec()
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
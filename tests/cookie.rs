@@ -0,0 +1,86 @@
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use common::{exchange, query};
+use dns_starter_rust::cookie::{Cookie, Cookies, BADCOOKIE};
+use dns_starter_rust::server::Config;
+use dns_starter_rust::{Answer, Message, Name, ResourceClass, ResourceType, ResponseCode};
+
+const CLIENT: [u8; 8] = *b"clientck";
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+fn opt(rdata: Vec<u8>) -> Answer {
+    Answer {
+        name: Name::new(""),
+        rtype: ResourceType::OPT,
+        class: ResourceClass::Unknown(1232),
+        ttl: 0,
+        rdlength: rdata.len() as u16,
+        rdata,
+    }
+}
+
+fn health_query(rdata: Vec<u8>) -> Message {
+    query(1, &[("health.check", ResourceType::TXT)]).with_additional(opt(rdata))
+}
+
+fn cookie_config() -> Config {
+    Config { cookies: Some(Cookies::default()), ..Config::default() }
+}
+
+#[test]
+fn client_cookie_is_answered_with_a_server_cookie() {
+    let config = cookie_config();
+    let sent = Cookie { client: CLIENT, server: None };
+
+    let response = exchange(&config, &health_query(sent.to_option()));
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.answers.len(), 1);
+
+    let returned = Cookie::find(&response.additional[0]).unwrap().unwrap();
+    let server = config.cookies.as_ref().unwrap().server_cookie(&CLIENT, LOCALHOST);
+    assert_eq!(returned, Cookie { client: CLIENT, server: Some(server.to_vec()) });
+
+    // sending it back is accepted as well
+    let response = exchange(&config, &health_query(returned.to_option()));
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.answers.len(), 1);
+}
+
+#[test]
+fn wrong_server_cookie_gets_badcookie_and_a_fresh_one() {
+    let config = cookie_config();
+    let sent = Cookie { client: CLIENT, server: Some(b"notyours".to_vec()) };
+
+    let response = exchange(&config, &health_query(sent.to_option()));
+    assert!(response.answers.is_empty());
+
+    let opt = &response.additional[0];
+    let rcode = (opt.ttl >> 24) << 4 | response.header.flags.rcode as u32;
+    assert_eq!(rcode, BADCOOKIE as u32);
+
+    let server = config.cookies.as_ref().unwrap().server_cookie(&CLIENT, LOCALHOST);
+    assert_eq!(Cookie::find(opt).unwrap().unwrap().server, Some(server.to_vec()));
+}
+
+#[test]
+fn malformed_cookie_is_formerr() {
+    let config = cookie_config();
+    let mut short_client = Cookie { client: CLIENT, server: None }.to_option();
+    short_client.truncate(10);
+    short_client[3] = 6;
+
+    let response = exchange(&config, &health_query(short_client));
+    assert_eq!(response.header.flags.rcode, ResponseCode::FormErr as u8);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn cookies_are_ignored_unless_enabled() {
+    let sent = Cookie { client: CLIENT, server: Some(b"notyours".to_vec()) };
+
+    let response = exchange(&Config::default(), &health_query(sent.to_option()));
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.answers.len(), 1);
+}
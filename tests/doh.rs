@@ -0,0 +1,149 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use common::{a_record, exchange, query, reply_to};
+use dns_starter_rust::doh::DohResolver;
+use dns_starter_rust::server::Config;
+use dns_starter_rust::upstream::Upstream;
+use dns_starter_rust::{Message, Name, ResourceType};
+
+// the request line and query ID of every request the endpoint got
+type Requests = Arc<Mutex<Vec<(String, u16)>>>;
+
+/// A bare-bones DoH endpoint: answers every POSTed query with an A record,
+/// and remembers what it was sent.
+fn doh_server() -> (String, Requests) {
+    doh_endpoint(|reply| {
+        let body = reply.to_bytes();
+        let mut response = format!("HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+        response.extend_from_slice(&body);
+        response
+    })
+}
+
+/// Like `doh_server`, but `respond` turns the DNS reply into the bytes of
+/// the whole HTTP response.
+fn doh_endpoint<F: Fn(Message) -> Vec<u8> + Send + 'static>(respond: F) -> (String, Requests) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/dns-query", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+
+    let seen = requests.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(len) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = len.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let query = Message::parse(&body).unwrap();
+            seen.lock().unwrap().push((request_line.trim().to_string(), query.header.id));
+            let mut reply = reply_to(query);
+            let name = reply.questions[0].name.clone();
+            reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 53)));
+            // the client may hang up early on a response it doesn't like
+            let _ = stream.write_all(&respond(reply));
+        }
+    });
+
+    (url, requests)
+}
+
+#[test]
+fn queries_are_posted_to_the_doh_resolver() {
+    let (url, requests) = doh_server();
+    let doh = DohResolver::new(&url).unwrap();
+
+    let response = doh.query(&query(7, &[("example.com", ResourceType::A)])).unwrap();
+
+    assert_eq!(response.header.id, 7);
+    assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 53]);
+    assert_eq!(*requests.lock().unwrap(), vec![("POST /dns-query HTTP/1.1".to_string(), 0)]);
+}
+
+#[test]
+fn server_forwards_over_doh() {
+    let (url, _) = doh_server();
+    let config = Config { upstreams: Some(Box::new(DohResolver::new(&url).unwrap())), ..Config::default() };
+
+    let response = exchange(&config, &query(8, &[("example.com", ResourceType::A)]));
+
+    assert_eq!(response.header.id, 8);
+    assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 53]);
+}
+
+#[test]
+fn doh_urls_are_checked_up_front() {
+    assert!(DohResolver::is_url("https://dns.example/dns-query"));
+    assert!(!DohResolver::is_url("1.1.1.1:53"));
+
+    assert!(DohResolver::new("http://127.0.0.1:8053/dns-query").is_ok());
+    assert!(DohResolver::new("http://[::1]").is_ok());
+    assert!(DohResolver::new("https://dns.example/dns-query").unwrap_err().contains("TLS"));
+    assert!(DohResolver::new("http:///dns-query").is_err());
+}
+
+const HEADERS: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\n";
+
+#[test]
+fn chunked_response_body_is_put_back_together() {
+    let (url, _) = doh_endpoint(|reply| {
+        let body = reply.to_bytes();
+        let (first, second) = body.split_at(10);
+        let mut response = format!("{}Transfer-Encoding: chunked\r\n\r\n", HEADERS).into_bytes();
+        for chunk in [first, second] {
+            response.extend_from_slice(format!("{:x};ext=1\r\n", chunk.len()).as_bytes());
+            response.extend_from_slice(chunk);
+            response.extend_from_slice(b"\r\n");
+        }
+        response.extend_from_slice(b"0\r\n\r\n");
+        response
+    });
+
+    let response = DohResolver::new(&url).unwrap().query(&query(7, &[("example.com", ResourceType::A)])).unwrap();
+
+    assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 53]);
+}
+
+#[test]
+fn content_length_beyond_a_dns_message_is_refused() {
+    let (url, _) = doh_endpoint(|_| format!("{}Content-Length: 4000000000\r\n\r\n", HEADERS).into_bytes());
+
+    let error = DohResolver::new(&url).unwrap().query(&query(7, &[("example.com", ResourceType::A)])).unwrap_err();
+
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("longer than 65535"), "{}", error);
+}
+
+#[test]
+fn response_to_another_question_is_refused() {
+    let (url, _) = doh_endpoint(|mut reply| {
+        reply.questions[0].name = Name::new("elsewhere.example");
+        let body = reply.to_bytes();
+        let mut response = format!("{}Content-Length: {}\r\n\r\n", HEADERS, body.len()).into_bytes();
+        response.extend_from_slice(&body);
+        response
+    });
+
+    let error = DohResolver::new(&url).unwrap().query(&query(7, &[("example.com", ResourceType::A)])).unwrap_err();
+
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
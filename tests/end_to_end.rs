@@ -0,0 +1,176 @@
+mod common;
+
+use std::io::{BufRead, BufReader};
+use std::net::Ipv4Addr;
+use std::process::{Command, Stdio};
+
+use common::{a_record, query, reply_to, temp_file, MockResolver, ServerProcess};
+use dns_starter_rust::{Message, MessageType, ResourceType, ResponseCode};
+
+#[test]
+fn default_mode_answers_over_udp() {
+    let server = ServerProcess::start(&["--default-a", "1.2.3.4"]);
+
+    let response = server.exchange(&query(7, &[("codecrafters.io", ResourceType::A)]));
+
+    assert_eq!(response.header.id, 7);
+    assert_eq!(response.header.flags.qr, MessageType::Reply);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].name.name, "codecrafters.io");
+    assert_eq!(response.answers[0].rdata, vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn resolver_answers_are_forwarded_over_udp() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let server = ServerProcess::start(&["--resolver", &upstream.addr]);
+
+    let response = server.exchange(&query(8, &[("example.com", ResourceType::A)]));
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+    assert_eq!(upstream.queries(), 1);
+}
+
+#[test]
+fn resolver_given_without_a_port_uses_the_upstream_port() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let (ip, port) = upstream.addr.rsplit_once(':').unwrap();
+    let server = ServerProcess::start(&["--resolver", ip, "--upstream-port", port]);
+
+    let response = server.exchange(&query(8, &[("example.com", ResourceType::A)]));
+
+    assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+    assert_eq!(upstream.queries(), 1);
+}
+
+#[test]
+fn blocked_names_get_nxdomain_over_udp() {
+    let blocklist = temp_file("ads.example\n");
+    let server = ServerProcess::start(&["--blocklist", blocklist.to_str().unwrap()]);
+
+    let response = server.exchange(&query(9, &[("tracker.ads.example", ResourceType::A)]));
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::NXDomain as u8);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn server_listens_and_forwards_over_ipv6() {
+    let upstream = MockResolver::start_on("[::1]:0", |query| {
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.clone();
+        reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 6)));
+        Some(reply)
+    });
+    let server = ServerProcess::start(&["--listen", "::1", "--resolver", &upstream.addr]);
+
+    assert!(server.addr.is_ipv6());
+    let response = server.exchange(&query(6, &[("example.com", ResourceType::A)]));
+
+    assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 6]);
+    assert_eq!(upstream.queries(), 1);
+}
+
+#[test]
+fn receive_buffer_size_is_configurable() {
+    let server = ServerProcess::start(&["--buffer-size", "2048"]);
+    let names: Vec<String> = (0..30).map(|i| format!("host-{:02}.a-rather-long-subdomain.example", i)).collect();
+    let long: Vec<(&str, ResourceType)> = names.iter().map(|name| (&name[..], ResourceType::A)).collect();
+
+    let long = query(1, &long);
+    assert!(long.to_bytes().len() > 512);
+    let response = server.exchange(&long);
+    assert_eq!(response.answers.len(), 30);
+
+    // the next, shorter query must not see anything of the first
+    let response = server.exchange(&query(2, &[("short.example", ResourceType::A)]));
+    assert_eq!(response.header.id, 2);
+    assert_eq!(response.questions.len(), 1);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].name.name, "short.example");
+}
+
+#[test]
+fn short_query_after_a_long_one_has_no_phantom_questions() {
+    let server = ServerProcess::start(&[]);
+    let long = query(1, &[("first.example", ResourceType::A), ("second.example", ResourceType::A)]);
+    assert_eq!(server.exchange(&long).answers.len(), 2);
+
+    // claims two questions but carries one: the second must not be read
+    // from where the first query's second question used to be
+    let mut short = query(2, &[("first.example", ResourceType::A)]).to_bytes();
+    short[5] = 2;
+    let response = Message::parse(&server.exchange_bytes(&short)).unwrap();
+    assert_eq!(response.header.id, 2);
+    assert_ne!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn query_larger_than_the_buffer_gets_formerr() {
+    let server = ServerProcess::start(&[]);
+    let names: Vec<String> = (0..20).map(|i| format!("host-{:02}.a-rather-long-subdomain.example", i)).collect();
+    let questions: Vec<(&str, ResourceType)> = names.iter().map(|name| (&name[..], ResourceType::A)).collect();
+    let mut oversized = query(1, &questions).to_bytes();
+    oversized.resize(700, 0);
+
+    let response = Message::parse(&server.exchange_bytes(&oversized)).unwrap();
+    assert_eq!(response.header.id, 1);
+    assert_eq!(response.header.flags.rcode, ResponseCode::FormErr as u8);
+    assert!(response.questions.is_empty());
+
+    // still serving
+    let response = server.exchange(&query(2, &[("short.example", ResourceType::A)]));
+    assert_eq!(response.answers.len(), 1);
+}
+
+/// The raw value of `key` in a flat JSON object, e.g. `"A"` or `0`.
+fn json_field<'a>(object: &'a str, key: &str) -> &'a str {
+    let start = object.find(&format!("\"{}\":", key)).unwrap() + key.len() + 3;
+    let len = object[start..].find([',', '}']).unwrap();
+    &object[start..start + len]
+}
+
+#[test]
+fn json_log_format_reports_each_query() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let mut command = Command::new(env!("CARGO_BIN_EXE_dns-starter-rust"));
+    command.args(["--port", "0", "--resolver", &upstream.addr, "--cache", "--log-format", "json"]).stdout(Stdio::piped());
+    let mut server = ServerProcess::spawn(command);
+    let mut stdout = BufReader::new(server.child.stdout.take().unwrap());
+
+    let mut lines = Vec::new();
+    for id in [1, 2] {
+        server.exchange(&query(id, &[("example.com", ResourceType::AAAA)]));
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        lines.push(line);
+    }
+
+    let line = lines[0].trim();
+    assert!(line.starts_with('{') && line.ends_with('}'));
+    assert!(json_field(line, "client").starts_with("\"127.0.0.1:"));
+    assert_eq!(json_field(line, "name"), "\"example.com\"");
+    assert_eq!(json_field(line, "type"), "\"AAAA\"");
+    assert_eq!(json_field(line, "rcode"), "0");
+    assert_eq!(json_field(line, "cache_hit"), "false");
+    let latency: u64 = json_field(line, "latency_us").parse().unwrap();
+    let forward: u64 = json_field(line, "forward_us").parse().unwrap();
+    assert!(forward <= latency);
+    assert_eq!(json_field(lines[1].trim(), "cache_hit"), "true");
+}
+
+#[test]
+fn preloaded_names_are_served_from_the_cache() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let preload = temp_file("# warm these up\nexample.com\n");
+    let server = ServerProcess::start(&["--resolver", &upstream.addr, "--cache", "--preload", preload.to_str().unwrap()]);
+    assert_eq!(upstream.queries(), 1);
+
+    let response = server.exchange(&query(8, &[("example.com", ResourceType::A)]));
+
+    assert_eq!(response.header.id, 8);
+    assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+    assert_eq!(upstream.queries(), 1);
+}
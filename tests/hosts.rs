@@ -0,0 +1,53 @@
+mod common;
+
+use std::net::Ipv4Addr;
+
+use common::{exchange, query, temp_file, MockResolver};
+use dns_starter_rust::hosts::Hosts;
+use dns_starter_rust::server::Config;
+use dns_starter_rust::upstream::Upstreams;
+use dns_starter_rust::ResourceType;
+
+fn hosts_config(upstream: &MockResolver) -> Config {
+    let path = temp_file("# local machines\n192.0.2.7 nas.lan nas  # the NAS\n2001:db8::7 nas.lan\n");
+    Config {
+        hosts: Some(Hosts::load(path.to_str().unwrap()).unwrap()),
+        upstreams: Some(Box::new(Upstreams::new(vec![upstream.addr.clone()]))),
+        ..Config::default()
+    }
+}
+
+#[test]
+fn hosts_file_names_are_answered_locally() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = hosts_config(&upstream);
+
+    let response = exchange(&config, &query(1, &[("NAS", ResourceType::A)]));
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].name.name, "NAS");
+    assert_eq!(response.answers[0].rdata, vec![192, 0, 2, 7]);
+
+    let response = exchange(&config, &query(2, &[("nas.lan", ResourceType::AAAA)]));
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].rdata.len(), 16);
+
+    assert_eq!(upstream.queries(), 0);
+}
+
+#[test]
+fn other_types_and_names_are_still_forwarded() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = hosts_config(&upstream);
+
+    exchange(&config, &query(1, &[("nas.lan", ResourceType::MX)]));
+    let response = exchange(&config, &query(2, &[("example.com", ResourceType::A)]));
+
+    assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+    assert_eq!(upstream.queries(), 2);
+}
+
+#[test]
+fn invalid_address_fails_to_load() {
+    let path = temp_file("not-an-ip host\n");
+    assert!(Hosts::load(path.to_str().unwrap()).is_err());
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+
+use common::{exchange, query, MockResolver};
+use dns_starter_rust::cache::Cache;
+use dns_starter_rust::logging::{self, Level};
+use dns_starter_rust::server::Config;
+use dns_starter_rust::upstream::Upstreams;
+use dns_starter_rust::ResourceType;
+
+// the sink and level are process-wide, so everything that touches them
+// lives in this one test
+#[test]
+fn debug_records_reach_the_sink_only_when_enabled() {
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let sink = records.clone();
+    logging::set_sink(move |level, message| sink.lock().unwrap().push((level, message.to_string())));
+
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = Config {
+        upstreams: Some(Box::new(Upstreams::new(vec![upstream.addr.clone()]))),
+        cache: Some(Cache::new()),
+        ..Config::default()
+    };
+
+    logging::set_max_level(Level::Info);
+    exchange(&config, &query(1, &[("quiet.example", ResourceType::A)]));
+    assert!(records.lock().unwrap().iter().all(|(level, _)| *level <= Level::Info));
+
+    logging::set_max_level(Level::Debug);
+    for id in [2, 3] {
+        exchange(&config, &query(id, &[("example.com", ResourceType::A)]));
+    }
+
+    let records = records.lock().unwrap();
+    let cache_lines: Vec<&(Level, String)> = records.iter().filter(|(_, message)| message.starts_with("cache ")).collect();
+    assert_eq!(cache_lines, [
+        &(Level::Debug, "cache miss for example.com A".to_string()),
+        &(Level::Debug, "cache hit for example.com A".to_string()),
+    ]);
+}
@@ -0,0 +1,375 @@
+use std::net::Ipv4Addr;
+
+use dns_starter_rust::{Answer, Header, Message, MessageType, Name, ParseError, QClass, QType, Question, ResourceClass, ResourceType, ResponseCode};
+
+fn sample_message() -> Message {
+    let mut header = Header::new(0xbeef, MessageType::Reply);
+    header.flags.rd = 1;
+
+    let mut msg = Message::new(header);
+    msg.add_question(Question {
+        name: Name::new("codecrafters.io"),
+        rtype: ResourceType::A.into(),
+        class: ResourceClass::IN.into(),
+    });
+    msg.add_answer(Answer {
+        name: Name::new("codecrafters.io"),
+        rtype: ResourceType::A,
+        class: ResourceClass::IN,
+        ttl: 60,
+        rdlength: 4,
+        rdata: Ipv4Addr::new(76, 76, 21, 21).octets().to_vec(),
+    });
+    msg
+}
+
+#[test]
+fn message_round_trips_through_bytes() {
+    let msg = sample_message();
+    let bytes = msg.to_bytes();
+
+    assert_eq!(&bytes[..2], &[0xbe, 0xef]);
+    assert_eq!(Message::parse(&bytes).unwrap(), msg);
+}
+
+#[test]
+fn write_to_reused_buffer_matches_to_bytes() {
+    let msg = sample_message();
+
+    let mut buffer = vec![0xff; 600];
+    msg.write_to(&mut buffer);
+
+    assert_eq!(buffer, msg.to_bytes());
+}
+
+#[test]
+fn names_differing_in_case_share_a_canonical_key() {
+    let mixed = Name::new("Example.COM");
+    let lower = Name::new("example.com");
+    assert_eq!(mixed.canonical(), lower.canonical());
+
+    let mut bytes = Vec::new();
+    mixed.write_to(&mut bytes);
+    let parsed = Name::parse(&mut std::io::Cursor::new(&bytes)).unwrap();
+    assert_eq!(parsed.name, "Example.COM");
+}
+
+#[test]
+fn canonical_key_leaves_non_ascii_alone() {
+    assert_eq!(Name::new("ÉCOLE.Fr").canonical(), "École.fr");
+}
+
+// A reply to "example.com A" as sent by a real resolver: two A records
+// with compressed owner names, followed by an EDNS OPT record.
+const FORWARDED_REPLY: [u8; 72] = [
+    0x8a, 0x4f, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01,
+    0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+    0x00, 0x01, 0x00, 0x01,
+    0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 93, 184, 216, 34,
+    0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 93, 184, 216, 35,
+    0x00, 0x00, 0x29, 0x04, 0xd0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[test]
+fn forwarded_reply_with_several_records_parses_every_section() {
+    let msg = Message::parse(&FORWARDED_REPLY).unwrap();
+
+    assert_eq!(msg.questions.len(), 1);
+    assert_eq!(msg.answers.len(), 2);
+    assert_eq!(msg.additional.len(), 1);
+
+    let addresses: Vec<&[u8]> = msg.answers.iter().map(|answer| &answer.rdata[..]).collect();
+    assert_eq!(addresses, vec![&[93, 184, 216, 34][..], &[93, 184, 216, 35][..]]);
+    assert!(msg.answers.iter().all(|answer| answer.name.name == "example.com" && answer.ttl == 3600));
+
+    let opt = &msg.additional[0];
+    assert_eq!(opt.rtype, ResourceType::OPT);
+    assert_eq!(opt.class, ResourceClass::Unknown(1232));
+    assert_eq!(opt.rdlength, 0);
+
+    // re-serializing (as the forwarding path does) keeps every section
+    assert_eq!(Message::parse(&msg.to_bytes()).unwrap(), msg);
+}
+
+#[test]
+fn question_with_query_only_type_and_class_parses() {
+    let mut bytes = Vec::new();
+    Name::new("example.com").write_to(&mut bytes);
+    bytes.extend_from_slice(&[0x00, 0xff, 0x00, 0xff]);
+
+    let question = Question::parse(&mut std::io::Cursor::new(&bytes)).unwrap();
+    assert_eq!(question.rtype, QType::ANY);
+    assert_eq!(question.class, QClass::ANY);
+
+    let mut written = Vec::new();
+    question.write_to(&mut written);
+    assert_eq!(written, bytes);
+}
+
+#[test]
+fn query_enums_convert_to_record_enums_only_when_valid() {
+    assert_eq!(ResourceType::try_from(QType::Record(ResourceType::MX)), Ok(ResourceType::MX));
+    assert_eq!(ResourceType::try_from(QType::ANY), Err(ParseError::QueryOnlyType(255)));
+    assert_eq!(ResourceClass::try_from(QClass::from(ResourceClass::CH)), Ok(ResourceClass::CH));
+    assert_eq!(ResourceClass::try_from(QClass::ANY), Err(ParseError::QueryOnlyClass(255)));
+}
+
+#[test]
+fn header_with_every_flag_bit_set_round_trips() {
+    let bytes = [0xab, 0xcd, 0xff, 0xff, 0, 1, 0, 0, 0, 0, 0, 0];
+    let header = Header::parse(&bytes).unwrap();
+
+    assert_eq!(header.flags.opcode, 0xf);
+    assert_eq!(header.flags.z, 0x7);
+    assert_eq!(header.flags.rcode, 0xf);
+
+    let mut written = Vec::new();
+    header.write_to(&mut written);
+    assert_eq!(written, bytes);
+}
+
+#[test]
+fn binary_labels_survive_a_round_trip() {
+    let mut bytes = vec![4, b'a', 0xff, 0xfe, b'.', 3, b'c', b'\\', b'm', 0];
+    bytes.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+
+    let question = Question::parse(&mut std::io::Cursor::new(&bytes)).unwrap();
+    assert_eq!(question.name.name, "a\\255\\254\\..c\\\\m");
+
+    let mut written = Vec::new();
+    question.write_to(&mut written);
+    assert_eq!(written, bytes);
+}
+
+#[test]
+fn root_name_round_trips_as_a_single_zero_octet() {
+    let root = Name::parse(&mut std::io::Cursor::new(&[0u8])).unwrap();
+    assert_eq!(root, Name::new(""));
+
+    let mut written = Vec::new();
+    root.write_to(&mut written);
+    assert_eq!(written, vec![0]);
+}
+
+#[test]
+fn unknown_codes_are_reported_with_their_value() {
+    assert_eq!(ResourceType::try_from(99), Err(ParseError::UnknownResourceType(99)));
+    assert_eq!(ResourceClass::try_from(42), Err(ParseError::UnknownResourceClass(42)));
+    assert_eq!(MessageType::try_from(2), Err(ParseError::UnknownMessageType(2)));
+    assert_eq!(ParseError::UnknownResourceType(99).to_string(), "unknown resource type 99");
+
+    let mut bytes = Vec::new();
+    Name::new("example.com").write_to(&mut bytes);
+    bytes.extend_from_slice(&[0x00, 0x63, 0x00, 0x01]);
+    let error = Question::parse(&mut std::io::Cursor::new(&bytes)).unwrap_err();
+    assert_eq!(error, ParseError::UnknownResourceType(99));
+    assert_eq!(error.rcode(), ResponseCode::NotImp);
+}
+
+#[test]
+fn additional_record_class_holding_a_payload_size_is_kept() {
+    let mut msg = sample_message();
+    msg.add_additional(Answer {
+        name: Name::new(""),
+        rtype: ResourceType::OPT,
+        class: ResourceClass::Unknown(4096),
+        ttl: 0,
+        rdlength: 0,
+        rdata: Vec::new(),
+    });
+    let bytes = msg.to_bytes();
+    assert_eq!(&bytes[bytes.len() - 8..bytes.len() - 6], &[0x10, 0x00]);
+
+    let parsed = Message::parse(&bytes).unwrap();
+    assert_eq!(parsed.additional[0].class, ResourceClass::Unknown(4096));
+    assert_eq!(parsed, msg);
+}
+
+#[test]
+fn builder_keeps_header_counts_in_step_with_sections() {
+    let name = Name::new("codecrafters.io");
+    let record = |ip: Ipv4Addr| Answer {
+        name: name.clone(),
+        rtype: ResourceType::A,
+        class: ResourceClass::IN,
+        ttl: 60,
+        rdlength: 4,
+        rdata: ip.octets().to_vec(),
+    };
+
+    let msg = Message::new(Header::new(7, MessageType::Reply))
+        .with_question(Question { name: name.clone(), rtype: ResourceType::A.into(), class: ResourceClass::IN.into() })
+        .with_answer(record(Ipv4Addr::new(10, 0, 0, 1)))
+        .with_answer(record(Ipv4Addr::new(10, 0, 0, 2)))
+        .with_name_server(record(Ipv4Addr::new(10, 0, 0, 3)))
+        .with_additional(record(Ipv4Addr::new(10, 0, 0, 4)));
+
+    assert_eq!(
+        (msg.header.qdcount, msg.header.ancount, msg.header.nscount, msg.header.arcount),
+        (1, 2, 1, 1)
+    );
+    assert_eq!(Message::parse(&msg.to_bytes()).unwrap(), msg);
+}
+
+#[test]
+fn serialized_counts_follow_the_sections_not_the_header() {
+    let mut msg = sample_message();
+    msg.header.qdcount = 0;
+    msg.header.ancount = 5;
+    msg.answers.push(msg.answers[0].clone());
+
+    let bytes = msg.to_bytes();
+    assert_eq!(bytes[4..12], [0, 1, 0, 2, 0, 0, 0, 0]);
+}
+
+// found by fuzzing (fuzz/fuzz_targets/parse_message.rs)
+#[test]
+fn message_shorter_than_a_header_is_an_error() {
+    assert_eq!(Message::parse(&[0x12, 0x34, 0x01]), Err(ParseError::TruncatedHeader));
+    assert_eq!(Message::parse(&[]), Err(ParseError::TruncatedHeader));
+}
+
+// found by fuzzing: used to recurse until the stack overflowed
+#[test]
+fn name_pointing_at_itself_is_an_error() {
+    let mut header = Header::new(1, MessageType::Query);
+    header.qdcount = 1;
+    let mut bytes = Vec::new();
+    header.write_to(&mut bytes);
+    bytes.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01]);
+
+    assert_eq!(Message::parse(&bytes), Err(ParseError::BadPointer(12)));
+}
+
+/// A query for `www` followed by a pointer to `ptr`, with the rest of
+/// the message being whatever comes after the question.
+fn question_pointing_at(ptr: u8) -> Vec<u8> {
+    let mut header = Header::new(1, MessageType::Query);
+    header.qdcount = 1;
+    let mut bytes = Vec::new();
+    header.write_to(&mut bytes);
+    bytes.extend_from_slice(&[0x03, b'w', b'w', b'w', 0xc0, ptr, 0x00, 0x01, 0x00, 0x01]);
+    bytes
+}
+
+#[test]
+fn pointer_at_or_past_the_end_of_the_message_is_an_error() {
+    // the message is 22 bytes long, so offset 22 is just past its end
+    let bytes = question_pointing_at(22);
+    assert_eq!(bytes.len(), 22);
+    assert_eq!(Message::parse(&bytes), Err(ParseError::BadPointer(22)));
+
+    let bytes = question_pointing_at(255);
+    assert_eq!(Message::parse(&bytes), Err(ParseError::BadPointer(255)));
+    assert_eq!(ParseError::BadPointer(255).rcode(), ResponseCode::FormErr);
+
+    // the same goes for a name inside RDATA: a CNAME at the root whose
+    // target points at the end of the message
+    let mut header = Header::new(1, MessageType::Reply);
+    header.ancount = 1;
+    let mut bytes = Vec::new();
+    header.write_to(&mut bytes);
+    bytes.extend_from_slice(&[0x00, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x02, 0xc0, 25]);
+    assert_eq!(bytes.len(), 25);
+    assert_eq!(Message::parse(&bytes), Err(ParseError::BadPointer(25)));
+}
+
+#[test]
+fn forward_pointer_is_an_error() {
+    // `example` follows the question, so the pointer to it lands inside
+    // the message but after the pointer itself
+    let mut bytes = question_pointing_at(22);
+    bytes.extend_from_slice(&[0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x00]);
+    assert_eq!(Message::parse(&bytes), Err(ParseError::BadPointer(22)));
+
+    // one byte past the pointer is still forwards
+    let bytes = question_pointing_at(18);
+    assert_eq!(Message::parse(&bytes), Err(ParseError::BadPointer(18)));
+}
+
+#[test]
+fn long_chains_of_backward_pointers_are_cut_off() {
+    let mut msg = Message::new(Header::new(1, MessageType::Query));
+    msg.header.qdcount = 200;
+    let mut bytes = Vec::new();
+    msg.header.write_to(&mut bytes);
+    // the first question is for the root, every later one points at the
+    // name of the question before it
+    bytes.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x01]);
+    let mut previous = 12u16;
+    for _ in 1..200 {
+        let at = bytes.len() as u16;
+        bytes.extend_from_slice(&(0xc000 | previous).to_be_bytes());
+        bytes.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        previous = at;
+    }
+
+    assert_eq!(Message::parse(&bytes), Err(ParseError::PointerLoop));
+}
+
+#[test]
+fn every_prefix_of_a_real_packet_parses_or_fails_cleanly() {
+    let packet = std::fs::read("tests/fixtures/example_com_reply.bin").unwrap();
+    for len in 0..=packet.len() {
+        let _ = Message::parse(&packet[..len]);
+    }
+}
+
+#[test]
+fn header_claiming_too_many_records_is_refused_up_front() {
+    let mut header = Header::new(1, MessageType::Reply);
+    header.ancount = u16::MAX;
+    let mut bytes = Vec::new();
+    header.write_to(&mut bytes);
+
+    assert_eq!(Message::parse(&bytes), Err(ParseError::TooManyRecords(65535)));
+    assert_eq!(Message::parse_limited(&sample_message().to_bytes(), 1), Err(ParseError::TooManyRecords(2)));
+    assert!(Message::parse_limited(&sample_message().to_bytes(), 2).is_ok());
+}
+
+#[test]
+fn names_display_in_presentation_format() {
+    let bytes = [3, b'a', b'.', b'b', 4, b'c', 0x00, b' ', 0xff, 3, b'c', b'o', b'm', 0];
+    let name = Name::parse(&mut std::io::Cursor::new(&bytes)).unwrap();
+
+    assert_eq!(name.to_string(), "a\\.b.c\\000\\032\\255.com");
+    assert_eq!(format!("{:#}", name), "a\\.b.c\\000\\032\\255.com.");
+    assert_eq!(format!("{:#}", Name::new("example.com")), "example.com.");
+    assert_eq!(Name::new("").to_string(), ".");
+    assert_eq!(format!("{:#}", Name::new("")), ".");
+}
+
+#[test]
+fn message_ending_mid_answer_is_an_error() {
+    let bytes = sample_message().to_bytes();
+
+    // cut inside the answer's TTL, and inside its address
+    for cut in [bytes.len() - 8, bytes.len() - 2] {
+        assert_eq!(Message::parse(&bytes[..cut]), Err(ParseError::Truncated));
+    }
+    // and inside the question's name
+    assert_eq!(Message::parse(&bytes[..16]), Err(ParseError::Truncated));
+    assert_eq!(ParseError::Truncated.rcode(), ResponseCode::FormErr);
+}
+
+#[test]
+fn extended_rcode_survives_a_round_trip() {
+    let mut msg = sample_message();
+    // EDNS version 0 with DO set, which the RCODE mustn't disturb
+    let opt = Answer { name: Name::new(""), rtype: ResourceType::OPT, class: ResourceClass::Unknown(1232), ttl: 0x8000, rdlength: 0, rdata: Vec::new() };
+    msg.add_additional(opt);
+
+    // BADCOOKIE: 0x17, so 7 in the header and 1 in the OPT record
+    msg.set_extended_rcode(23);
+    let bytes = msg.to_bytes();
+    assert_eq!(bytes[3] & 0xf, 7);
+
+    let parsed = Message::parse(&bytes).unwrap();
+    assert_eq!(parsed.extended_rcode(), 23);
+    assert_eq!(parsed.additional[0].ttl, 0x0100_8000);
+
+    // without an OPT record only the header's 4 bits are left
+    msg.additional.clear();
+    msg.set_extended_rcode(23);
+    assert_eq!(Message::parse(&msg.to_bytes()).unwrap().extended_rcode(), 7);
+}
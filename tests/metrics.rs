@@ -0,0 +1,101 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use common::{exchange, query, reply_to, MockResolver};
+use dns_starter_rust::metrics::{self, Metrics};
+use dns_starter_rust::server::Config;
+use dns_starter_rust::upstream::Upstreams;
+use dns_starter_rust::{ResourceType, ResponseCode};
+
+#[test]
+fn queries_are_counted_by_outcome() {
+    let config = Config::default();
+    exchange(&config, &query(1, &[("a.example", ResourceType::A)]));
+    exchange(&config, &query(2, &[("b.example", ResourceType::AAAA)]));
+    exchange(&config, &query(3, &[("c.example", ResourceType::MX)]));
+
+    assert_eq!(config.metrics.queries(), 3);
+    assert_eq!(config.metrics.responses(ResponseCode::NoError), 2);
+    assert_eq!(config.metrics.responses(ResponseCode::NotImp), 1);
+
+    let exported = config.metrics.render();
+    assert!(exported.contains("dns_queries_total 3\n"));
+    assert!(exported.contains("dns_responses_total{rcode=\"NOERROR\"} 2\n"));
+    assert!(exported.contains("dns_responses_total{rcode=\"NOTIMP\"} 1\n"));
+    assert!(exported.contains("dns_forward_failures_total 0\n"));
+}
+
+#[test]
+fn unanswered_forwards_are_counted() {
+    // bound but never answering
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let config = Config {
+        upstreams: Some(Box::new(Upstreams::new(vec![silent.local_addr().unwrap().to_string()]).with_timeout(Duration::from_millis(100)))),
+        ..Config::default()
+    };
+
+    let response = exchange(&config, &query(1, &[("example.com", ResourceType::A)]));
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::ServFail as u8);
+    assert_eq!(config.metrics.forward_failures(), 1);
+    assert_eq!(config.metrics.responses(ResponseCode::ServFail), 1);
+}
+
+#[test]
+fn forward_latency_is_measured_apart_from_handling() {
+    let slow = MockResolver::start(|query| {
+        thread::sleep(Duration::from_millis(150));
+        Some(reply_to(query))
+    });
+    let config = Config { upstreams: Some(Box::new(Upstreams::new(vec![slow.addr.clone()]))), ..Config::default() };
+
+    exchange(&config, &query(1, &[("example.com", ResourceType::A)]));
+
+    assert_eq!(config.metrics.forwards(), 1);
+    assert!(config.metrics.forward_time() >= Duration::from_millis(150));
+    assert!(config.metrics.handling_time() >= config.metrics.forward_time());
+    assert!(config.metrics.render().contains("dns_forward_duration_seconds_count 1\n"));
+}
+
+#[test]
+fn metrics_are_served_over_http() {
+    let metrics = Arc::new(Metrics::default());
+    metrics.query_received();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let served = metrics.clone();
+    thread::spawn(move || metrics::serve(listener, served));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(&metrics.render()));
+    assert!(response.contains("dns_queries_total 1\n"));
+}
+
+#[test]
+fn silent_client_does_not_hold_up_the_next_scrape() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || metrics::serve_timeout(listener, Arc::new(Metrics::default()), Duration::from_millis(200)));
+
+    // connects, then never says a word
+    let _silent = TcpStream::connect(addr).unwrap();
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+}
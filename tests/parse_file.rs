@@ -0,0 +1,32 @@
+use std::process::Command;
+
+#[test]
+fn parse_file_prints_the_packet_and_exits() {
+    let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/example_com_reply.bin");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dns-starter-rust"))
+        .args(["--parse-file", fixture])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let printed = String::from_utf8(output.stdout).unwrap();
+    assert!(printed.contains(";; id 35407, Reply, opcode 0, rcode 0, flags: rd ra\n"));
+    assert!(printed.contains("example.com.\tIN\tA\n"));
+    assert!(printed.contains("example.com.\t3600\tIN\tA\t93.184.216.34\n"));
+    assert!(printed.contains("example.com.\t3600\tIN\tA\t93.184.216.35\n"));
+    assert!(printed.contains(".\t0\tCLASS1232\tOPT\t\n"));
+}
+
+#[test]
+fn parse_file_reports_malformed_packets() {
+    let unknown_type = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/unknown_type_query.bin");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dns-starter-rust"))
+        .args(["--parse-file", unknown_type])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("unknown resource type 99"));
+}
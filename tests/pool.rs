@@ -0,0 +1,55 @@
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+
+use dns_starter_rust::pool::SocketPool;
+
+/// Borrows a socket and fails before using it, the way a forward does when
+/// the resolver address is bad.
+fn borrow_and_fail(pool: &SocketPool) -> std::io::Result<()> {
+    let socket = pool.get()?;
+    let resolver: SocketAddr = "not a resolver".parse().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    socket.send_to(b"query", resolver)?;
+    Ok(())
+}
+
+#[test]
+fn socket_goes_back_to_the_pool_when_dropped() {
+    let pool = SocketPool::new("127.0.0.1:0");
+    assert_eq!(pool.idle_count(), 0);
+
+    let socket = pool.get().unwrap();
+    let addr = socket.local_addr().unwrap();
+    assert_eq!(pool.idle_count(), 0);
+    drop(socket);
+    assert_eq!(pool.idle_count(), 1);
+
+    // the same socket is handed out again rather than a new one
+    let again = pool.get().unwrap();
+    assert_eq!(again.local_addr().unwrap(), addr);
+    assert_eq!(pool.idle_count(), 0);
+}
+
+#[test]
+fn socket_goes_back_to_the_pool_on_an_early_error_return() {
+    let pool = SocketPool::new("127.0.0.1:0");
+
+    assert!(borrow_and_fail(&pool).is_err());
+    assert_eq!(pool.idle_count(), 1);
+
+    assert!(borrow_and_fail(&pool).is_err());
+    assert_eq!(pool.idle_count(), 1);
+}
+
+#[test]
+fn socket_is_replaced_after_its_last_use() {
+    let pool = SocketPool::new("127.0.0.1:0").with_max_uses(2);
+
+    let first = pool.get().unwrap().local_addr().unwrap();
+    assert_eq!(pool.get().unwrap().local_addr().unwrap(), first);
+
+    // worn out: a new socket on another port takes its place
+    let replacement = pool.get().unwrap().local_addr().unwrap();
+    assert_ne!(replacement, first);
+    assert_eq!(pool.idle_count(), 1);
+    assert_eq!(pool.get().unwrap().local_addr().unwrap(), replacement);
+}
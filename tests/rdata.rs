@@ -0,0 +1,225 @@
+mod common;
+
+use std::net::Ipv4Addr;
+
+use common::{exchange, query, reply_to, MockResolver};
+use dns_starter_rust::rdata::RData;
+use dns_starter_rust::server::Config;
+use dns_starter_rust::upstream::Upstreams;
+use dns_starter_rust::{Answer, Header, Message, MessageType, Name, ResourceClass, ResourceType};
+
+/// Wraps `data` in a record and sends it through the wire format and back.
+fn round_trip(data: &RData) -> Answer {
+    let rdata = data.to_bytes();
+    let record = Answer {
+        name: Name::new("host.example"),
+        rtype: data.rtype(),
+        class: ResourceClass::IN,
+        ttl: 300,
+        rdlength: rdata.len() as u16,
+        rdata,
+    };
+
+    let header = Header::new(1, MessageType::Reply);
+    let mut msg = Message::new(header);
+    msg.add_answer(record.clone());
+
+    let parsed = Message::parse(&msg.to_bytes()).unwrap();
+    assert_eq!(parsed.answers, vec![record]);
+    parsed.answers[0].clone()
+}
+
+#[test]
+fn wks_round_trips() {
+    // TCP (6) with the bits for ports 21, 23 and 25 set
+    let wks = RData::Wks { address: Ipv4Addr::new(192, 0, 2, 1), protocol: 6, bitmap: vec![0, 0, 0, 0b0101_0100] };
+
+    let answer = round_trip(&wks);
+
+    assert_eq!(answer.rdlength, 9);
+    assert_eq!(answer.data(), Some(wks));
+}
+
+#[test]
+fn hinfo_round_trips() {
+    let hinfo = RData::Hinfo { cpu: "INTEL-386".to_string(), os: "UNIX".to_string() };
+
+    let answer = round_trip(&hinfo);
+
+    assert_eq!(answer.rdata, b"\x09INTEL-386\x04UNIX");
+    assert_eq!(answer.data(), Some(hinfo));
+}
+
+#[test]
+fn truncated_rdata_does_not_decode() {
+    assert_eq!(RData::parse(ResourceType::WKS, &[192, 0, 2]), None);
+    assert_eq!(RData::parse(ResourceType::HINFO, b"\x09INTEL"), None);
+}
+
+fn null_record() -> RData {
+    RData::Null((0..300).map(|i| (i % 256) as u8).collect())
+}
+
+#[test]
+fn null_round_trips_its_whole_payload() {
+    let answer = round_trip(&null_record());
+
+    assert_eq!(answer.rdlength, 300);
+    assert_eq!(answer.data(), Some(null_record()));
+}
+
+#[test]
+fn null_records_survive_forwarding() {
+    let upstream = MockResolver::start(|query| {
+        let mut reply = reply_to(query);
+        let rdata = null_record().to_bytes();
+        reply.add_answer(Answer {
+            name: reply.questions[0].name.clone(),
+            rtype: ResourceType::NULL,
+            class: ResourceClass::IN,
+            ttl: 60,
+            rdlength: rdata.len() as u16,
+            rdata,
+        });
+        Some(reply)
+    });
+    let config = Config { upstreams: Some(Box::new(Upstreams::new(vec![upstream.addr.clone()]))), ..Config::default() };
+
+    let response = exchange(&config, &query(1, &[("blob.example", ResourceType::NULL)]));
+
+    assert_eq!(response.answers[0].rdlength, 300);
+    assert_eq!(response.answers[0].data(), Some(null_record()));
+}
+
+#[test]
+fn ptr_round_trips() {
+    let ptr = RData::Ptr(Name::new("host.example"));
+
+    let answer = round_trip(&ptr);
+
+    assert_eq!(answer.rdata, b"\x04host\x07example\x00");
+    assert_eq!(answer.data(), Some(ptr));
+}
+
+#[test]
+fn minfo_round_trips() {
+    let minfo = RData::Minfo { rmailbx: Name::new("admin.example"), emailbx: Name::new("errors.example") };
+
+    let answer = round_trip(&minfo);
+
+    assert_eq!(answer.data(), Some(minfo));
+}
+
+#[test]
+fn compressed_names_in_rdata_are_expanded() {
+    let mut header = Header::new(1, MessageType::Reply);
+    header.qdcount = 1;
+    header.ancount = 1;
+    let mut bytes = Vec::new();
+    header.write_to(&mut bytes);
+    // the question's name sits at offset 12
+    bytes.extend_from_slice(b"\x011\x012\x010\x03192\x07in-addr\x04arpa\x00\x00\x0c\x00\x01");
+    // owned by the question's name, pointing below it
+    bytes.extend_from_slice(&[0xc0, 12, 0, 12, 0, 1, 0, 0, 0, 60, 0, 7]);
+    bytes.extend_from_slice(b"\x04host\xc0\x0c");
+
+    let answer = &Message::parse(&bytes).unwrap().answers[0];
+
+    assert_eq!(answer.data(), Some(RData::Ptr(Name::new("host.1.2.0.192.in-addr.arpa"))));
+    assert_eq!(usize::from(answer.rdlength), answer.rdata.len());
+    assert!(!answer.rdata.contains(&0xc0));
+}
+
+fn caa_record() -> RData {
+    RData::Caa { flags: 0, tag: "issue".to_string(), value: b"letsencrypt.org".to_vec() }
+}
+
+#[test]
+fn caa_round_trips() {
+    let answer = round_trip(&caa_record());
+
+    assert_eq!(answer.rtype, ResourceType::CAA);
+    assert_eq!(answer.rdata, b"\x00\x05issueletsencrypt.org");
+    assert_eq!(answer.data(), Some(caa_record()));
+    assert_eq!(RData::parse(ResourceType::CAA, b"\x80\x00"), None);
+}
+
+#[test]
+fn caa_records_survive_forwarding() {
+    let upstream = MockResolver::start(|query| {
+        let mut reply = reply_to(query);
+        let rdata = caa_record().to_bytes();
+        reply.add_answer(Answer {
+            name: reply.questions[0].name.clone(),
+            rtype: ResourceType::CAA,
+            class: ResourceClass::IN,
+            ttl: 3600,
+            rdlength: rdata.len() as u16,
+            rdata,
+        });
+        Some(reply)
+    });
+    let config = Config { upstreams: Some(Box::new(Upstreams::new(vec![upstream.addr.clone()]))), ..Config::default() };
+
+    let response = exchange(&config, &query(1, &[("example.com", ResourceType::CAA)]));
+
+    assert_eq!(response.answers[0].data(), Some(caa_record()));
+}
+
+#[test]
+fn srv_round_trips_with_a_compressed_target() {
+    let srv = RData::Srv { priority: 10, weight: 60, port: 5060, target: Name::new("sip.example.com") };
+    assert_eq!(round_trip(&srv).data(), Some(srv.clone()));
+
+    let mut header = Header::new(1, MessageType::Reply);
+    header.qdcount = 1;
+    header.ancount = 1;
+    let mut bytes = Vec::new();
+    header.write_to(&mut bytes);
+    // "_sip._udp.example.com" SRV, with "example.com" at offset 22
+    bytes.extend_from_slice(b"\x04_sip\x04_udp\x07example\x03com\x00\x00\x21\x00\x01");
+    bytes.extend_from_slice(&[0xc0, 12, 0, 33, 0, 1, 0, 0, 0, 60, 0, 12]);
+    bytes.extend_from_slice(&[0, 10, 0, 60, 0x13, 0xc4]);
+    bytes.extend_from_slice(b"\x03sip\xc0\x16");
+
+    let msg = Message::parse(&bytes).unwrap();
+    let answer = &msg.answers[0];
+
+    assert_eq!(answer.data(), Some(srv));
+    assert_eq!(usize::from(answer.rdlength), answer.rdata.len());
+    assert_eq!(Message::parse(&msg.to_bytes()).unwrap(), msg);
+}
+
+#[test]
+fn compressed_mx_and_soa_names_still_hold_behind_another_question() {
+    let mut header = Header::new(1, MessageType::Reply);
+    header.qdcount = 1;
+    header.ancount = 2;
+    let mut bytes = Vec::new();
+    header.write_to(&mut bytes);
+    // the question's name, example.com, sits at offset 12
+    bytes.extend_from_slice(b"\x07example\x03com\x00\x00\x0f\x00\x01");
+    // MX 10 mail.example.com, the exchange pointing at the question
+    bytes.extend_from_slice(&[0xc0, 12, 0, 15, 0, 1, 0, 0, 0, 60, 0, 9, 0, 10]);
+    bytes.extend_from_slice(b"\x04mail\xc0\x0c");
+    // SOA with both names pointing at the question, then its five numbers
+    bytes.extend_from_slice(&[0xc0, 12, 0, 6, 0, 1, 0, 0, 0, 60, 0, 38]);
+    bytes.extend_from_slice(b"\x02ns\xc0\x0c\x0ahostmaster\xc0\x0c");
+    let numbers: Vec<u8> = [2024010101u32, 7200, 3600, 1209600, 300].iter().flat_map(|n| n.to_be_bytes()).collect();
+    bytes.extend_from_slice(&numbers);
+
+    let mut msg = Message::parse(&bytes).unwrap();
+    let mx = b"\x00\x0a\x04mail\x07example\x03com\x00".to_vec();
+    let soa = [&b"\x02ns\x07example\x03com\x00\x0ahostmaster\x07example\x03com\x00"[..], &numbers].concat();
+    assert_eq!(msg.answers[0].rdata, mx);
+    assert_eq!(msg.answers[1].rdata, soa);
+    for answer in &msg.answers {
+        assert_eq!(usize::from(answer.rdlength), answer.rdata.len());
+    }
+
+    // written out behind a longer question, the records mean the same
+    msg.questions[0].name = Name::new("a.much.longer.example.org");
+    let moved = Message::parse(&msg.to_bytes()).unwrap();
+    assert_eq!(moved.answers[0].rdata, mx);
+    assert_eq!(moved.answers[1].rdata, soa);
+}
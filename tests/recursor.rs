@@ -0,0 +1,192 @@
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+
+use common::{a_record, ns_record, query, reply_to, soa_record, MockResolver};
+use dns_starter_rust::recursor::Recursor;
+use dns_starter_rust::upstream::Upstream;
+use dns_starter_rust::{Message, Name, QType, ResourceType, ResponseCode};
+
+type Log = Arc<Mutex<Vec<(&'static str, String, QType)>>>;
+
+/// A root server, a com server and an example.com server, on three
+/// loopback addresses sharing one port, logging every query they get.
+struct Authorities {
+    _servers: Vec<MockResolver>,
+    port: u16,
+    log: Log,
+}
+
+fn referral(query: Message, zone: &str, server: &str, ip: Ipv4Addr) -> Option<Message> {
+    let mut reply = reply_to(query);
+    reply.add_name_server(ns_record(&Name::new(zone), server));
+    reply.add_additional(a_record(&Name::new(server), ip));
+    Some(reply)
+}
+
+fn start_authorities() -> Authorities {
+    let log: Log = Arc::new(Mutex::new(Vec::new()));
+    let logger = |server: &'static str, log: &Log| {
+        let log = log.clone();
+        move |query: &Message| {
+            let question = &query.questions[0];
+            log.lock().unwrap().push((server, question.name.name.clone(), question.rtype));
+        }
+    };
+
+    let record = logger("root", &log);
+    let root = MockResolver::start(move |query| {
+        record(&query);
+        referral(query, "com", "a.gtld.test", Ipv4Addr::new(127, 0, 0, 2))
+    });
+    let port = root.addr.parse::<std::net::SocketAddr>().unwrap().port();
+
+    let record = logger("com", &log);
+    let com = MockResolver::start_on(&format!("127.0.0.2:{}", port), move |query| {
+        record(&query);
+        let name = query.questions[0].name.name.clone();
+        // other.com is served from example.com's server, and loop.com by a
+        // server inside it, both without glue
+        let glueless = [("other.com", "ns.example.com"), ("loop.com", "ns.loop.com")];
+        if let Some((zone, server)) = glueless.iter().find(|(zone, _)| name.ends_with(zone)) {
+            let mut reply = reply_to(query);
+            reply.add_name_server(ns_record(&Name::new(zone), server));
+            return Some(reply);
+        }
+        referral(query, "example.com", "ns.example.com", Ipv4Addr::new(127, 0, 0, 3))
+    });
+
+    let record = logger("example.com", &log);
+    let example = MockResolver::start_on(&format!("127.0.0.3:{}", port), move |query| {
+        record(&query);
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.clone();
+        let rtype = reply.questions[0].rtype;
+        match &name.name[..] {
+            n if n.ends_with("nx.example.com") => {
+                reply.header.flags.rcode = ResponseCode::NXDomain as u8;
+                reply.add_name_server(soa_record(&Name::new("example.com"), 1));
+            }
+            // a server that can't cope with minimized queries
+            "picky.example.com" if rtype == NS => reply.header.flags.rcode = ResponseCode::Refused as u8,
+            // an empty non-terminal: it exists, but has no records
+            "b.example.com" => reply.add_name_server(soa_record(&Name::new("example.com"), 1)),
+            "ns.example.com" if rtype == A => reply.add_answer(a_record(&name, Ipv4Addr::new(127, 0, 0, 3))),
+            _ if rtype == A => {
+                reply.add_answer(a_record(&name, Ipv4Addr::new(192, 0, 2, 80)));
+            }
+            _ => reply.add_name_server(soa_record(&Name::new("example.com"), 1)),
+        }
+        Some(reply)
+    });
+
+    Authorities { _servers: vec![root, com, example], port, log }
+}
+
+fn recursor(authorities: &Authorities, minimize: bool) -> Recursor {
+    Recursor::new(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)])
+        .with_port(authorities.port)
+        .with_qname_minimization(minimize)
+}
+
+fn queries(authorities: &Authorities) -> Vec<(&'static str, String, QType)> {
+    authorities.log.lock().unwrap().clone()
+}
+
+const NS: QType = QType::Record(ResourceType::NS);
+const A: QType = QType::Record(ResourceType::A);
+
+#[test]
+fn minimized_queries_reveal_one_label_more_per_authority() {
+    let authorities = start_authorities();
+    let response = recursor(&authorities, true).query(&query(7, &[("www.example.com", ResourceType::A)])).unwrap();
+
+    assert_eq!(response.header.id, 7);
+    assert_eq!(response.questions[0].name.name, "www.example.com");
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].rdata, vec![192, 0, 2, 80]);
+    assert_eq!(queries(&authorities), vec![
+        ("root", "com".to_string(), NS),
+        ("com", "example.com".to_string(), NS),
+        ("example.com", "www.example.com".to_string(), A),
+    ]);
+}
+
+#[test]
+fn without_minimization_every_authority_sees_the_full_name() {
+    let authorities = start_authorities();
+    let response = recursor(&authorities, false).query(&query(7, &[("www.example.com", ResourceType::A)])).unwrap();
+
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(queries(&authorities), vec![
+        ("root", "www.example.com".to_string(), A),
+        ("com", "www.example.com".to_string(), A),
+        ("example.com", "www.example.com".to_string(), A),
+    ]);
+}
+
+#[test]
+fn minimization_steps_through_empty_non_terminals() {
+    let authorities = start_authorities();
+    let response = recursor(&authorities, true).query(&query(7, &[("a.b.example.com", ResourceType::A)])).unwrap();
+
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(queries(&authorities)[2..], [
+        ("example.com", "b.example.com".to_string(), NS),
+        ("example.com", "a.b.example.com".to_string(), A),
+    ]);
+}
+
+#[test]
+fn nxdomain_for_a_minimized_name_is_confirmed_with_the_full_name() {
+    let authorities = start_authorities();
+    let response = recursor(&authorities, true).query(&query(7, &[("www.nx.example.com", ResourceType::A)])).unwrap();
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::NXDomain as u8);
+    assert_eq!(response.questions[0].name.name, "www.nx.example.com");
+    assert!(response.answers.is_empty());
+    assert_eq!(queries(&authorities)[2..], [
+        ("example.com", "nx.example.com".to_string(), NS),
+        ("example.com", "www.nx.example.com".to_string(), A),
+    ]);
+}
+
+#[test]
+fn refused_minimized_query_falls_back_to_the_full_name() {
+    let authorities = start_authorities();
+    let response = recursor(&authorities, true).query(&query(7, &[("www.picky.example.com", ResourceType::A)])).unwrap();
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.answers[0].rdata, vec![192, 0, 2, 80]);
+    assert_eq!(queries(&authorities)[2..], [
+        ("example.com", "picky.example.com".to_string(), NS),
+        ("example.com", "www.picky.example.com".to_string(), A),
+    ]);
+}
+
+#[test]
+fn referral_without_glue_has_its_server_names_resolved() {
+    let authorities = start_authorities();
+    let response = recursor(&authorities, false).query(&query(7, &[("www.other.com", ResourceType::A)])).unwrap();
+
+    assert_eq!(response.answers[0].rdata, vec![192, 0, 2, 80]);
+    assert_eq!(queries(&authorities), vec![
+        ("root", "www.other.com".to_string(), A),
+        ("com", "www.other.com".to_string(), A),
+        // ns.example.com, from the roots down
+        ("root", "ns.example.com".to_string(), A),
+        ("com", "ns.example.com".to_string(), A),
+        ("example.com", "ns.example.com".to_string(), A),
+        ("example.com", "www.other.com".to_string(), A),
+    ]);
+}
+
+#[test]
+fn server_names_that_need_themselves_to_resolve_give_up() {
+    let authorities = start_authorities();
+    let result = recursor(&authorities, false).query(&query(7, &[("www.loop.com", ResourceType::A)]));
+
+    assert!(result.is_err());
+    assert!(queries(&authorities).len() <= 64);
+}
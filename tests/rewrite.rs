@@ -0,0 +1,31 @@
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use common::{exchange, query, MockResolver};
+use dns_starter_rust::rewrite::{parse_rewrite, Rewrites};
+use dns_starter_rust::server::Config;
+use dns_starter_rust::upstream::Upstreams;
+use dns_starter_rust::ResourceType;
+
+#[test]
+fn forwarded_addresses_with_a_rule_are_rewritten() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(1, 1, 1, 1), Ipv4Addr::new(1, 1, 1, 2)]);
+    let mut rewrites = Rewrites::default();
+    let (from, to) = parse_rewrite("1.1.1.1=10.0.0.1").unwrap();
+    rewrites.add(from, to);
+    let config = Config { upstreams: Some(Box::new(Upstreams::new(vec![upstream.addr.clone()]))), rewrites, ..Config::default() };
+
+    let response = exchange(&config, &query(1, &[("public.example", ResourceType::A)]));
+
+    let addresses: Vec<&[u8]> = response.answers.iter().map(|answer| &answer.rdata[..]).collect();
+    assert_eq!(addresses, vec![&[10, 0, 0, 1][..], &[1, 1, 1, 2][..]]);
+}
+
+#[test]
+fn rewrite_rules_must_stay_within_a_family() {
+    assert_eq!(parse_rewrite("::1=::2"), Ok((IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1u16]), IpAddr::from([0, 0, 0, 0, 0, 0, 0, 2u16]))));
+    assert!(parse_rewrite("1.1.1.1=::1").is_err());
+    assert!(parse_rewrite("1.1.1.1").is_err());
+    assert!(parse_rewrite("1.1.1.1=example").is_err());
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use common::{exchange, query, temp_file, MockResolver};
+use dns_starter_rust::blocklist::Blocklist;
+use dns_starter_rust::rpz::{Action, Policy};
+use dns_starter_rust::server::Config;
+use dns_starter_rust::upstream::Upstreams;
+use dns_starter_rust::{Name, ResourceType, ResponseCode};
+
+const POLICY: &str = "\
+# sinkholed, with every subdomain
+tracker.test        A     0.0.0.0
+tracker.test        AAAA  ::
+*.tracker.test      A     0.0.0.0
+# except this one
+ok.tracker.test     CNAME rpz-passthru.
+Ads.Example.com.    CNAME .
+*.ads.example.com   CNAME .
+";
+
+fn load(contents: &str) -> std::io::Result<Policy> {
+    let path = temp_file(contents);
+    let policy = Policy::load(path.to_str().unwrap());
+    fs::remove_file(&path).unwrap();
+    policy
+}
+
+fn policy_config(upstream: &MockResolver) -> Config {
+    Config {
+        upstreams: Some(Box::new(Upstreams::new(vec![upstream.addr.clone()]))),
+        policy: Some(load(POLICY).unwrap()),
+        ..Config::default()
+    }
+}
+
+#[test]
+fn most_specific_rule_wins() {
+    let policy = load(POLICY).unwrap();
+
+    assert_eq!(policy.action(&Name::new("ok.tracker.test")), Some(&Action::Passthru));
+    assert_eq!(policy.action(&Name::new("cdn.TRACKER.test")), Some(&Action::Sinkhole(vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)])));
+    assert_eq!(
+        policy.action(&Name::new("tracker.test")),
+        Some(&Action::Sinkhole(vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V6(Ipv6Addr::UNSPECIFIED)])),
+    );
+    assert!(policy.is_nxdomain(&Name::new("ads.example.com")));
+    assert!(policy.is_nxdomain(&Name::new("x.y.ads.example.com")));
+    assert_eq!(policy.action(&Name::new("example.com")), None);
+}
+
+#[test]
+fn sinkholed_name_is_answered_with_the_sinkhole_address() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = policy_config(&upstream);
+
+    for name in ["tracker.test", "cdn.tracker.test"] {
+        let response = exchange(&config, &query(1, &[(name, ResourceType::A)]));
+        assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].rdata, vec![0, 0, 0, 0]);
+    }
+
+    let response = exchange(&config, &query(2, &[("tracker.test", ResourceType::MX)]));
+    assert!(response.answers.is_empty());
+
+    let response = exchange(&config, &query(3, &[("ads.example.com", ResourceType::A)]));
+    assert_eq!(response.header.flags.rcode, ResponseCode::NXDomain as u8);
+
+    assert_eq!(upstream.queries(), 0);
+}
+
+#[test]
+fn passthru_name_is_forwarded_even_if_blocklisted() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = Config {
+        blocklist: Some(Blocklist::new(["tracker.test"])),
+        ..policy_config(&upstream)
+    };
+
+    let response = exchange(&config, &query(1, &[("ok.tracker.test", ResourceType::A)]));
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+    assert_eq!(upstream.queries(), 1);
+}
+
+#[test]
+fn bad_rules_are_reported_with_their_line() {
+    let error = load("ok.test CNAME rpz-passthru.\nbad.test MX mail.test\n").unwrap_err();
+    assert!(error.to_string().ends_with(":2: unsupported rule"), "{}", error);
+
+    assert!(load("a.test CNAME .\na.test CNAME rpz-passthru.\n").is_err());
+    assert!(load("a.test A ::1\n").is_err());
+}
@@ -0,0 +1,828 @@
+mod common;
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use common::{a_record, cname_record, exchange, exchange_bytes, exchange_raw, ns_record, query, reply_to, MockResolver};
+use dns_starter_rust::ratelimit::parse_rate_limit;
+use dns_starter_rust::server::{bind, forward_query_on, handle_connection, Config};
+use dns_starter_rust::upstream::{Upstream, Upstreams};
+use dns_starter_rust::{Answer, Flags, Message, MessageType, Name, QClass, QType, Question, ResourceClass, ResourceType, ResponseCode};
+
+fn forwarding_config(upstream: &MockResolver) -> Config {
+    Config {
+        upstreams: Some(Box::new(Upstreams::new(vec![upstream.addr.clone()]))),
+        ..Config::default()
+    }
+}
+
+#[test]
+fn corrupt_ancount_skews_only_the_header_count() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let query = query(1, &[("example.com", ResourceType::A)]);
+
+    let honest = exchange_bytes(&forwarding_config(&upstream), &query);
+    let corrupt = exchange_bytes(&Config { corrupt_ancount: Some(2), ..forwarding_config(&upstream) }, &query);
+
+    assert_eq!(u16::from_be_bytes([honest[6], honest[7]]), 1);
+    assert_eq!(u16::from_be_bytes([corrupt[6], corrupt[7]]), 3);
+    assert_eq!(honest[8..], corrupt[8..]);
+}
+
+#[test]
+fn reply_for_a_different_question_is_rejected() {
+    let upstream = MockResolver::start(|query| {
+        let mut reply = reply_to(query);
+        reply.questions[0].name = Name::new("attacker.example");
+        reply.add_answer(a_record(&Name::new("attacker.example"), Ipv4Addr::new(6, 6, 6, 6)));
+        Some(reply)
+    });
+    let config = Config {
+        upstreams: Some(Box::new(Upstreams::new(vec![upstream.addr.clone()]).with_timeout(Duration::from_millis(200)))),
+        ..Config::default()
+    };
+
+    let response = exchange(&config, &query(1, &[("example.com", ResourceType::A)]));
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::ServFail as u8);
+    assert!(response.answers.is_empty());
+}
+
+/// A local address nothing is listening on.
+fn closed_port() -> SocketAddr {
+    UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+#[test]
+fn resolver_on_a_closed_port_gets_servfail_within_the_timeout() {
+    let config = Config {
+        upstreams: Some(Box::new(Upstreams::new(vec![closed_port().to_string()]).with_timeout(Duration::from_millis(200)).with_retries(0))),
+        ..Config::default()
+    };
+
+    let started = Instant::now();
+    let response = exchange(&config, &query(1, &[("example.com", ResourceType::A)]));
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::ServFail as u8);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn port_unreachable_fails_the_forward_without_waiting() {
+    let closed = closed_port();
+    // a connected socket hears about the ICMP error
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.connect(closed).unwrap();
+
+    let started = Instant::now();
+    let error = forward_query_on(&socket, &query(1, &[("example.com", ResourceType::A)]), &closed.to_string(), Duration::from_secs(5)).unwrap_err();
+
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionRefused);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn cname_chain_is_chased_to_the_final_record() {
+    let upstream = MockResolver::start(|query| {
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.clone();
+        let answer = match &name.canonical()[..] {
+            "start.example" => cname_record(&name, "middle.example"),
+            "middle.example" => cname_record(&name, "end.example"),
+            _ => a_record(&name, Ipv4Addr::new(10, 0, 0, 3)),
+        };
+        reply.add_answer(answer);
+        Some(reply)
+    });
+    let config = Config { cname_chase_depth: Some(8), ..forwarding_config(&upstream) };
+
+    let response = exchange(&config, &query(1, &[("start.example", ResourceType::A)]));
+
+    let chain: Vec<(String, ResourceType)> = response.answers.iter()
+        .map(|answer| (answer.name.name.clone(), answer.rtype))
+        .collect();
+    assert_eq!(chain, vec![
+        ("start.example".to_string(), ResourceType::CNAME),
+        ("middle.example".to_string(), ResourceType::CNAME),
+        ("end.example".to_string(), ResourceType::A),
+    ]);
+    assert_eq!(response.header.ancount, 3);
+    assert_eq!(response.answers[2].rdata, vec![10, 0, 0, 3]);
+}
+
+#[test]
+fn any_query_is_not_implemented_without_a_resolver() {
+    let response = exchange(&Config::default(), &query(1, &[("example.com", QType::ANY)]));
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::NotImp as u8);
+    assert_eq!(response.questions[0].rtype, QType::ANY);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn any_query_is_forwarded_as_is() {
+    let upstream = MockResolver::start(|query| {
+        assert_eq!(query.questions[0].rtype, QType::ANY);
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.clone();
+        reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 1)));
+        reply.add_answer(cname_record(&name, "alias.example"));
+        Some(reply)
+    });
+
+    let response = exchange(&forwarding_config(&upstream), &query(1, &[("example.com", QType::ANY)]));
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.answers.len(), 2);
+}
+
+#[test]
+fn client_over_its_rate_limit_is_refused() {
+    let config = Config { rate_limiter: Some(parse_rate_limit("1:3").unwrap()), ..Config::default() };
+    let query = query(1, &[("example.com", ResourceType::A)]);
+
+    for _ in 0..3 {
+        assert_eq!(exchange(&config, &query).header.flags.rcode, ResponseCode::NoError as u8);
+    }
+    let response = exchange(&config, &query);
+    assert_eq!(response.header.flags.rcode, ResponseCode::Refused as u8);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn default_mode_refuses_other_classes() {
+    let mut query = query(1, &[("example.com", ResourceType::TXT)]);
+    query.questions[0].class = ResourceClass::CH.into();
+
+    let response = exchange(&Config::default(), &query);
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::Refused as u8);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn default_mode_never_claims_recursion_or_authority() {
+    for rd in [1, 0] {
+        let mut query = query(1, &[("example.com", ResourceType::A)]);
+        query.header.flags.rd = rd;
+
+        let response = exchange(&Config::default(), &query);
+
+        assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8, "RD={}", rd);
+        assert_eq!(response.header.flags.rd, rd);
+        assert_eq!(response.header.flags.ra, 0, "RD={}", rd);
+        assert_eq!(response.header.flags.aa, 0, "RD={}", rd);
+        assert_eq!(response.answers.len(), 1);
+    }
+}
+
+#[test]
+fn default_mode_does_not_implement_other_types() {
+    let response = exchange(&Config::default(), &query(1, &[("example.com", ResourceType::MX)]));
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::NotImp as u8);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn default_mode_answers_each_question_with_its_own_type() {
+    let response = exchange(&Config::default(), &query(1, &[("v4.example", ResourceType::A), ("v6.example", ResourceType::AAAA)]));
+
+    assert_eq!(response.header.ancount, 2);
+    let answers: Vec<(&str, ResourceType, usize)> = response.answers.iter()
+        .map(|answer| (&answer.name.name[..], answer.rtype, answer.rdata.len()))
+        .collect();
+    assert_eq!(answers, vec![("v4.example", ResourceType::A, 4), ("v6.example", ResourceType::AAAA, 16)]);
+}
+
+#[test]
+fn default_mode_header_counts_every_answer_sent() {
+    let response = exchange_bytes(&Config::default(), &query(1, &[("a.example", ResourceType::A), ("b.example", ResourceType::A)]));
+
+    assert_eq!(u16::from_be_bytes([response[6], response[7]]), 2);
+}
+
+#[test]
+fn synthesized_answers_use_the_configured_ttl() {
+    let default = exchange(&Config::default(), &query(1, &[("example.com", ResourceType::A)]));
+    assert_eq!(default.answers[0].ttl, 60);
+
+    let config = Config { ttl: 3600, ..Config::default() };
+    let response = exchange(&config, &query(1, &[("example.com", ResourceType::A)]));
+    assert_eq!(response.answers[0].ttl, 3600);
+}
+
+#[test]
+fn synthesized_addresses_are_configurable() {
+    let config = Config {
+        default_a: Ipv4Addr::new(1, 2, 3, 4),
+        default_aaaa: "2001:db8::1".parse().unwrap(),
+        ..Config::default()
+    };
+    let response = exchange(&config, &query(1, &[("example.com", ResourceType::A), ("example.com", ResourceType::AAAA)]));
+
+    assert_eq!(response.answers[0].rdata, vec![1, 2, 3, 4]);
+    assert_eq!(response.answers[1].rdata, Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets().to_vec());
+}
+
+#[test]
+fn reply_flags_keep_what_the_client_sent_and_reset_the_rest() {
+    // every bit but QR, which would make it a reply we drop
+    let mut every_bit = query(0x1234, &[("example.com", ResourceType::A)]);
+    every_bit.header.flags = Flags {
+        qr: MessageType::Query,
+        opcode: 0xf,
+        aa: 1,
+        tc: 1,
+        rd: 1,
+        ra: 1,
+        z: 0x7,
+        rcode: 0xf,
+    };
+    assert_eq!(&every_bit.to_bytes()[2..4], &[0x7f, 0xff]);
+
+    let response = exchange_bytes(&Config::default(), &every_bit);
+
+    assert_eq!(&response[..2], &[0x12, 0x34]);
+    // QR and RD set, opcode echoed; AA, TC, RA, Z and RCODE all zero
+    assert_eq!(&response[2..4], &[0b1111_1001, 0b0000_0000]);
+}
+
+#[test]
+fn query_for_an_unknown_type_gets_notimp_instead_of_a_crash() {
+    let mut bytes = query(0x4242, &[("example.com", ResourceType::A)]).to_bytes();
+    // turn the question's type into 99, which nothing knows about
+    let qtype = bytes.len() - 4;
+    bytes[qtype..qtype + 2].copy_from_slice(&99u16.to_be_bytes());
+
+    let response = Message::parse(&exchange_raw(&Config::default(), &bytes)).unwrap();
+    assert_eq!(response.header.id, 0x4242);
+    assert_eq!(response.header.flags.rcode, ResponseCode::NotImp as u8);
+    assert_eq!(response.header.qdcount, 0);
+}
+
+#[test]
+fn binding_port_zero_reports_the_port_picked() {
+    let config = Config { port: 0, ..Config::default() };
+
+    let (socket, addr) = bind(&config).unwrap();
+
+    assert_ne!(addr.port(), 0);
+    assert_eq!(socket.local_addr().unwrap(), addr);
+}
+
+#[test]
+fn header_only_query_gets_an_empty_noerror_reply() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let no_questions: [(&str, ResourceType); 0] = [];
+
+    for config in [Config::default(), forwarding_config(&upstream)] {
+        let bytes = exchange_bytes(&config, &query(0x0bad, &no_questions));
+
+        assert_eq!(bytes.len(), 12);
+        let response = Message::parse(&bytes).unwrap();
+        assert_eq!(response.header.id, 0x0bad);
+        assert_eq!(response.header.flags.qr, MessageType::Reply);
+        assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+        assert_eq!(response.header.qdcount, 0);
+        assert_eq!(response.header.ancount, 0);
+    }
+    assert_eq!(upstream.queries(), 0);
+}
+
+#[test]
+fn query_with_an_edns_opt_record_is_answered() {
+    let mut edns_query = query(1, &[("example.com", ResourceType::A)]);
+    edns_query.add_additional(Answer {
+        name: Name::new(""),
+        rtype: ResourceType::OPT,
+        class: ResourceClass::Unknown(4096),
+        ttl: 0,
+        rdlength: 0,
+        rdata: Vec::new(),
+    });
+
+    let response = exchange(&Config::default(), &edns_query);
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.answers.len(), 1);
+}
+
+/// An OPT record advertising a 4096-byte payload, with nothing set.
+fn empty_opt() -> Answer {
+    Answer { name: Name::new(""), rtype: ResourceType::OPT, class: ResourceClass::Unknown(4096), ttl: 0, rdlength: 0, rdata: Vec::new() }
+}
+
+#[test]
+fn reply_carries_our_own_opt_and_nothing_else_the_client_sent() {
+    // a cookie option, DO set, and records that have no business coming back
+    let cookie = [0, 10, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8];
+    let opt = Answer { ttl: 0x8000, rdlength: cookie.len() as u16, rdata: cookie.to_vec(), ..empty_opt() };
+    let mut edns_query = query(1, &[("example.com", ResourceType::A)])
+        .with_additional(a_record(&Name::new("extra.example"), Ipv4Addr::new(10, 9, 9, 9)))
+        .with_additional(opt);
+    edns_query.add_name_server(ns_record(&Name::new("example.com"), "ns.example.com"));
+
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let configs = [Config { buffer_size: 1232, ..Config::default() }, Config { buffer_size: 1232, ..forwarding_config(&upstream) }];
+    for config in configs {
+        let response = exchange(&config, &edns_query);
+
+        assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+        assert!(response.name_servers.is_empty());
+        assert_eq!(response.additional.len(), 1);
+        let opt = &response.additional[0];
+        assert_eq!(opt.rtype, ResourceType::OPT);
+        assert_eq!(opt.class, ResourceClass::Unknown(1232));
+        assert!(opt.rdata.is_empty(), "client options echoed: {:?}", opt.rdata);
+        // no DNSSEC records, so no DO either
+        assert_eq!(opt.ttl, 0);
+    }
+
+    // a query without EDNS gets no OPT at all
+    let response = exchange(&Config::default(), &query(2, &[("example.com", ResourceType::A)]));
+    assert!(response.additional.is_empty());
+}
+
+#[test]
+fn opt_record_outside_the_additional_section_is_only_tolerated_when_lenient() {
+    let mut misplaced = query(1, &[("example.com", ResourceType::A)]);
+    misplaced.add_answer(empty_opt());
+
+    let response = exchange(&Config::default(), &misplaced);
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+
+    let response = exchange(&Config { strict: true, ..Config::default() }, &misplaced);
+    assert_eq!(response.header.flags.rcode, ResponseCode::FormErr as u8);
+    assert!(response.answers.is_empty());
+
+    // in its place it's fine either way
+    let placed = query(2, &[("example.com", ResourceType::A)]).with_additional(empty_opt());
+    let response = exchange(&Config { strict: true, ..Config::default() }, &placed);
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+}
+
+#[test]
+fn query_with_two_opt_records_gets_formerr() {
+    let mut doubled = query(1, &[("example.com", ResourceType::A)]).with_additional(empty_opt());
+    doubled.add_name_server(empty_opt());
+
+    for strict in [false, true] {
+        let response = exchange(&Config { strict, ..Config::default() }, &doubled);
+        assert_eq!(response.header.flags.rcode, ResponseCode::FormErr as u8, "strict={}", strict);
+    }
+
+    let doubled = query(2, &[("example.com", ResourceType::A)]).with_additional(empty_opt()).with_additional(empty_opt());
+    assert_eq!(exchange(&Config::default(), &doubled).header.flags.rcode, ResponseCode::FormErr as u8);
+}
+
+#[test]
+fn strip_edns_removes_opt_records_from_forwarded_responses() {
+    let opt = |ttl| Answer {
+        name: Name::new(""),
+        rtype: ResourceType::OPT,
+        class: ResourceClass::Unknown(4096),
+        ttl,
+        rdlength: 0,
+        rdata: Vec::new(),
+    };
+    let asked_with = Arc::new(Mutex::new(Vec::new()));
+    let seen = asked_with.clone();
+    let upstream = MockResolver::start(move |query| {
+        seen.lock().unwrap().push(query.additional[0].ttl);
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.clone();
+        reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 1)));
+        Some(reply)
+    });
+    // asks for DNSSEC records (DO set)
+    let edns_query = query(1, &[("example.com", ResourceType::A)]).with_additional(opt(0x8000));
+
+    // our own OPT comes back, without DO as no DNSSEC records did
+    let response = exchange(&forwarding_config(&upstream), &edns_query);
+    assert_eq!(response.additional, vec![Answer { class: ResourceClass::Unknown(512), ..opt(0) }]);
+
+    let bytes = exchange_bytes(&Config { strip_edns: true, ..forwarding_config(&upstream) }, &edns_query);
+    let response = Message::parse(&bytes).unwrap();
+    assert_eq!(u16::from_be_bytes([bytes[10], bytes[11]]), 0);
+    assert!(response.additional.is_empty());
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(*asked_with.lock().unwrap(), vec![0x8000, 0]);
+}
+
+#[test]
+fn no_compression_writes_repeated_names_out_in_full() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]);
+    let query = query(1, &[("www.example.com", ResourceType::A)]);
+
+    // every answer repeats the question's name, which a pointer stands in for
+    let compressed = exchange_bytes(&forwarding_config(&upstream), &query);
+    assert!(compressed.contains(&0xc0));
+
+    let expanded = exchange_bytes(&Config { no_compression: true, ..forwarding_config(&upstream) }, &query);
+    assert!(!expanded.contains(&0xc0));
+    assert!(expanded.len() > compressed.len());
+    assert_eq!(Message::parse(&expanded).unwrap(), Message::parse(&compressed).unwrap());
+}
+
+#[test]
+fn extended_rcode_from_the_resolver_is_passed_on() {
+    // BADVERS is 16, all zeros in the header's 4 bits
+    let upstream = MockResolver::start(|query| {
+        let mut reply = reply_to(query);
+        reply.set_extended_rcode(16);
+        Some(reply)
+    });
+    let opt = Answer { name: Name::new(""), rtype: ResourceType::OPT, class: ResourceClass::Unknown(4096), ttl: 0, rdlength: 0, rdata: Vec::new() };
+
+    let response = exchange(&forwarding_config(&upstream), &query(1, &[("example.com", ResourceType::A)]).with_additional(opt));
+
+    assert_eq!(response.header.flags.rcode, 0);
+    assert_eq!(response.extended_rcode(), 16);
+}
+
+#[test]
+fn answer_section_only_drops_authority_and_additional_records() {
+    let upstream = MockResolver::start(|query| {
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.clone();
+        reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 1)));
+        reply.add_name_server(ns_record(&Name::new("example.com"), "ns.example.com"));
+        reply.add_additional(a_record(&Name::new("ns.example.com"), Ipv4Addr::new(10, 0, 0, 53)));
+        Some(reply)
+    });
+    let opt = Answer { name: Name::new(""), rtype: ResourceType::OPT, class: ResourceClass::Unknown(4096), ttl: 0, rdlength: 0, rdata: Vec::new() };
+    let edns_query = query(1, &[("example.com", ResourceType::A)]).with_additional(opt);
+
+    let response = exchange(&forwarding_config(&upstream), &edns_query);
+    assert_eq!(response.name_servers.len(), 1);
+    assert_eq!(response.additional.len(), 2);
+
+    let bytes = exchange_bytes(&Config { answer_section_only: true, ..forwarding_config(&upstream) }, &edns_query);
+    let response = Message::parse(&bytes).unwrap();
+    assert_eq!(u16::from_be_bytes([bytes[8], bytes[9]]), 0);
+    assert_eq!(u16::from_be_bytes([bytes[10], bytes[11]]), 0);
+    assert!(response.name_servers.is_empty());
+    assert!(response.additional.is_empty());
+    assert_eq!(response.answers.len(), 1);
+}
+
+#[test]
+fn datagram_longer_than_the_buffer_is_not_parsed() {
+    let config = Config { buffer_size: 512, ..Config::default() };
+    let mut oversized = query(9, &[("example.com", ResourceType::A)]).to_bytes();
+    oversized.resize(700, 0);
+
+    let response = Message::parse(&exchange_raw(&config, &oversized)).unwrap();
+    assert_eq!(response.header.id, 9);
+    assert_eq!(response.header.flags.qr, MessageType::Reply);
+    assert_eq!(response.header.flags.rcode, ResponseCode::FormErr as u8);
+    assert!(response.questions.is_empty());
+    assert!(response.answers.is_empty());
+
+    // the same query fits a larger buffer
+    let response = Message::parse(&exchange_raw(&Config { buffer_size: 1232, ..config }, &oversized)).unwrap();
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+}
+
+#[test]
+fn reply_echoes_the_query_opcode() {
+    let upstream = MockResolver::start(|query| {
+        // a resolver that doesn't echo the opcode must not change ours
+        let mut reply = reply_to(query);
+        reply.header.flags.opcode = 0;
+        Some(reply)
+    });
+
+    for opcode in [0, 2] {
+        let mut status = query(1, &[("example.com", ResourceType::A)]);
+        status.header.flags.opcode = opcode;
+
+        assert_eq!(exchange(&Config::default(), &status).header.flags.opcode, opcode);
+        assert_eq!(exchange(&forwarding_config(&upstream), &status).header.flags.opcode, opcode);
+    }
+}
+
+#[test]
+fn shuffled_answers_vary_in_order() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 3)]);
+    let orders = |config: &Config| -> Vec<Vec<u8>> {
+        (0..30)
+            .map(|id| exchange(config, &query(id, &[("pool.example", ResourceType::A)])))
+            .map(|response| response.answers.iter().map(|answer| answer.rdata[3]).collect())
+            .collect()
+    };
+
+    let stable = orders(&forwarding_config(&upstream));
+    assert!(stable.iter().all(|order| *order == vec![1, 2, 3]));
+
+    let shuffled = orders(&Config { shuffle_answers: true, ..forwarding_config(&upstream) });
+    assert!(shuffled.iter().all(|order| order.len() == 3));
+    assert!(shuffled.iter().any(|order| *order != vec![1, 2, 3]));
+}
+
+#[test]
+fn failing_to_send_a_reply_does_not_stop_the_server() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let query = query(1, &[("example.com", ResourceType::A)]).to_bytes();
+
+    // an IPv4 socket can't send to an IPv6 address
+    let unreachable = SocketAddr::from((Ipv6Addr::LOCALHOST, 53));
+    handle_connection(&server, &unreachable, &query, &Config::default());
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    handle_connection(&server, &client.local_addr().unwrap(), &query, &Config::default());
+
+    let mut buf = [0; 512];
+    let (size, _) = client.recv_from(&mut buf).unwrap();
+    assert_eq!(Message::parse(&buf[..size]).unwrap().answers.len(), 1);
+}
+
+#[test]
+fn packet_with_qr_set_is_not_answered() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+
+    let mut reply = query(1, &[("example.com", ResourceType::A)]);
+    reply.header.flags.qr = MessageType::Reply;
+    handle_connection(&server, &client.local_addr().unwrap(), &reply.to_bytes(), &Config::default());
+
+    let mut buf = [0; 512];
+    assert!(client.recv_from(&mut buf).is_err());
+}
+
+#[test]
+fn query_claiming_too_many_records_gets_formerr() {
+    let mut bytes = query(1, &[("example.com", ResourceType::A)]).to_bytes();
+    bytes[10..12].copy_from_slice(&u16::MAX.to_be_bytes());
+
+    let response = Message::parse(&exchange_raw(&Config::default(), &bytes)).unwrap();
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::FormErr as u8);
+    assert!(response.questions.is_empty());
+}
+
+#[test]
+fn split_questions_keep_their_exact_type_and_class() {
+    let asked = Arc::new(Mutex::new(Vec::new()));
+    let seen = asked.clone();
+    let upstream = MockResolver::start(move |query| {
+        let question = &query.questions[0];
+        seen.lock().unwrap().push((question.name.name.clone(), u16::from(question.rtype), u16::from(question.class)));
+        Some(reply_to(query))
+    });
+    let mut split = query(1, &[("a.example", QType::from(ResourceType::MX)), ("b.example", QType::ANY)]);
+    split.questions[1].class = QClass::ANY;
+
+    exchange(&forwarding_config(&upstream), &split);
+
+    let mut asked = asked.lock().unwrap().clone();
+    asked.sort();
+    assert_eq!(asked, vec![("a.example".to_string(), 15, 1), ("b.example".to_string(), 255, 255)]);
+}
+
+#[test]
+fn split_questions_keep_every_answer_of_each_reply() {
+    let ips = vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 3)];
+    let upstream = MockResolver::answering(ips.clone());
+
+    let response = exchange(&forwarding_config(&upstream), &query(1, &[("a.example", ResourceType::A), ("b.example", ResourceType::A)]));
+
+    assert_eq!(response.header.ancount, 6);
+    for name in ["a.example", "b.example"] {
+        let addresses: Vec<Vec<u8>> = response.answers.iter()
+            .filter(|answer| answer.name.name == name)
+            .map(|answer| answer.rdata.clone())
+            .collect();
+        assert_eq!(addresses, ips.iter().map(|ip| ip.octets().to_vec()).collect::<Vec<_>>(), "{}", name);
+    }
+}
+
+#[test]
+fn split_questions_succeed_if_any_of_them_resolves() {
+    let upstream = MockResolver::start(|query| {
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.clone();
+        if name.name.starts_with("missing") {
+            reply.header.flags.rcode = ResponseCode::NXDomain as u8;
+        } else {
+            reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 1)));
+        }
+        Some(reply)
+    });
+    let config = forwarding_config(&upstream);
+
+    let response = exchange(&config, &query(1, &[("missing.example", ResourceType::A), ("found.example", ResourceType::A)]));
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.header.ancount, 1);
+    assert_eq!(response.answers[0].name.name, "found.example");
+    assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+
+    let response = exchange(&config, &query(2, &[("missing.example", ResourceType::A), ("missing2.example", ResourceType::A)]));
+    assert_eq!(response.header.flags.rcode, ResponseCode::NXDomain as u8);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn question_name_is_echoed_in_the_case_it_was_asked() {
+    // a resolver that lowercases the question it echoes
+    let upstream = MockResolver::start(|query| {
+        let mut reply = reply_to(query);
+        let name = Name::new(&reply.questions[0].name.name.to_ascii_lowercase());
+        reply.questions[0].name = name.clone();
+        reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 1)));
+        Some(reply)
+    });
+    let asked = query(1, &[("wWw.ExAmPlE.CoM", ResourceType::A)]);
+
+    let response = exchange_bytes(&forwarding_config(&upstream), &asked);
+    assert_eq!(response[12..12 + 17], asked.to_bytes()[12..12 + 17]);
+
+    let response = exchange_bytes(&Config::default(), &asked);
+    assert_eq!(response[12..12 + 17], asked.to_bytes()[12..12 + 17]);
+    assert_eq!(Message::parse(&response).unwrap().answers[0].name.name, "wWw.ExAmPlE.CoM");
+}
+
+#[test]
+fn health_check_name_is_answered_without_the_resolver() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+
+    let response = exchange(&forwarding_config(&upstream), &query(1, &[("health.check", ResourceType::TXT)]));
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].rtype, ResourceType::TXT);
+    assert_eq!(response.answers[0].rdata, b"\x02ok");
+
+    let config = Config { health_name: "Ready.Internal.".to_string(), ..forwarding_config(&upstream) };
+    let response = exchange(&config, &query(2, &[("ready.internal", ResourceType::TXT)]));
+    assert_eq!(response.answers[0].rdata, b"\x02ok");
+
+    assert_eq!(upstream.queries(), 0);
+}
+
+#[test]
+fn deny_recursion_refuses_what_we_cannot_answer_locally() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let config = Config { deny_recursion: true, ..forwarding_config(&upstream) };
+
+    let response = exchange(&config, &query(1, &[("example.com", ResourceType::A)]));
+    assert_eq!(response.header.flags.rcode, ResponseCode::Refused as u8);
+    assert!(response.answers.is_empty());
+    // a resolver we won't use is no recursion to advertise
+    assert_eq!(response.header.flags.ra, 0);
+
+    // local data is still served
+    let response = exchange(&config, &query(2, &[("health.check", ResourceType::TXT)]));
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.header.flags.ra, 0);
+
+    // and without a resolver nothing is synthesized for RD=1 either
+    let config = Config { deny_recursion: true, ..Config::default() };
+    let response = exchange(&config, &query(3, &[("example.com", ResourceType::A)]));
+    assert_eq!(response.header.flags.rcode, ResponseCode::Refused as u8);
+
+    assert_eq!(upstream.queries(), 0);
+}
+
+#[test]
+fn reserved_bits_are_zeroed_in_responses() {
+    let upstream = MockResolver::start(|query| {
+        let mut reply = reply_to(query);
+        reply.header.flags.z = 0x7;
+        Some(reply)
+    });
+    let mut msg = query(1, &[("example.com", ResourceType::A)]);
+    msg.header.flags.z = 0x7;
+
+    let bytes = exchange_bytes(&forwarding_config(&upstream), &msg);
+    // ra stays in the top bit and rcode in the low nibble around them
+    assert_eq!(bytes[3], 0x80);
+
+    let bytes = exchange_bytes(&Config { deny_recursion: true, ..Config::default() }, &msg);
+    assert_eq!(bytes[3], ResponseCode::Refused as u8);
+}
+
+#[test]
+fn dnssec_records_are_forwarded_intact_when_do_is_set() {
+    let opt = Answer {
+        name: Name::new(""),
+        rtype: ResourceType::OPT,
+        class: ResourceClass::Unknown(4096),
+        ttl: 0x8000,
+        rdlength: 0,
+        rdata: Vec::new(),
+    };
+    let record = |rtype, rdata: Vec<u8>| Answer {
+        name: Name::new("example.com"),
+        rtype,
+        class: ResourceClass::IN,
+        ttl: 300,
+        rdlength: rdata.len() as u16,
+        rdata,
+    };
+
+    // covers A, ECDSAP256SHA256, 2 labels, TTL 300, expiration, inception,
+    // key tag, then the signer's name and the signature
+    let mut rrsig = vec![0, 1, 13, 2, 0, 0, 1, 0x2c, 0x67, 0, 0, 0, 0x66, 0, 0, 0, 0x9a, 0x4c];
+    Name::new("example.com").write_to(&mut rrsig);
+    rrsig.extend((0..64).map(|i| i as u8));
+    let mut nsec = Vec::new();
+    Name::new("www.example.com").write_to(&mut nsec);
+    nsec.extend_from_slice(&[0, 6, 0x40, 0, 0, 0, 0, 0x03]);
+    let mut dnskey = vec![1, 1, 3, 13];
+    dnskey.extend((0..64).map(|i| 255 - i as u8));
+
+    let dnssec = vec![
+        record(ResourceType::A, vec![93, 184, 216, 34]),
+        record(ResourceType::RRSIG, rrsig),
+        record(ResourceType::NSEC, nsec),
+        record(ResourceType::DNSKEY, dnskey),
+    ];
+    let answers = dnssec.clone();
+    let upstream = MockResolver::start(move |query| {
+        // only a client asking for DNSSEC gets the signatures
+        let dnssec_ok = query.additional.first().is_some_and(|opt| opt.ttl & 0x8000 != 0);
+        let mut reply = reply_to(query);
+        for answer in &answers[..if dnssec_ok { answers.len() } else { 1 }] {
+            reply.add_answer(answer.clone());
+        }
+        Some(reply)
+    });
+
+    let dnssec_query = query(1, &[("example.com", ResourceType::A)]).with_additional(opt);
+    let response = exchange(&forwarding_config(&upstream), &dnssec_query);
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.answers, dnssec);
+    assert_eq!(response.additional[0].ttl & 0x8000, 0x8000);
+}
+
+#[test]
+fn version_bind_is_answered_with_the_configured_text() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let chaos_query = |id, name: &str| {
+        Message::query(id, Question { name: Name::new(name), rtype: ResourceType::TXT.into(), class: ResourceClass::CH.into() })
+    };
+
+    let response = exchange(&forwarding_config(&upstream), &chaos_query(1, "VERSION.bind"));
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(response.answers[0].class, ResourceClass::CH);
+    assert_eq!(response.answers[0].rdata, b"\x0dnot disclosed");
+
+    let config = Config { chaos_txt: Some("ns1".to_string()), ..forwarding_config(&upstream) };
+    let response = exchange(&config, &chaos_query(2, "Hostname.Bind"));
+    assert_eq!(response.answers[0].rtype, ResourceType::TXT);
+    assert_eq!(response.answers[0].rdata, b"\x03ns1");
+
+    let config = Config { chaos_txt: None, ..forwarding_config(&upstream) };
+    let response = exchange(&config, &chaos_query(3, "version.bind"));
+    assert_eq!(response.header.flags.rcode, ResponseCode::Refused as u8);
+    assert!(response.answers.is_empty());
+
+    assert_eq!(upstream.queries(), 0);
+}
+
+#[test]
+fn question_in_an_unknown_class_gets_notimp() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let mut bytes = query(1, &[("example.com", ResourceType::A)]).to_bytes();
+    let len = bytes.len();
+    bytes[len - 2..].copy_from_slice(&42u16.to_be_bytes());
+
+    let response = Message::parse(&exchange_raw(&forwarding_config(&upstream), &bytes)).unwrap();
+
+    assert_eq!(response.header.flags.rcode, ResponseCode::NotImp as u8);
+    assert_eq!(response.questions[0].class, QClass::Record(ResourceClass::Unknown(42)));
+    assert!(response.answers.is_empty());
+    assert_eq!(upstream.queries(), 0);
+}
+
+/// An upstream that answers every query with the same address, without
+/// any network involved.
+#[derive(Debug)]
+struct Canned(Ipv4Addr);
+
+impl Upstream for Canned {
+    fn query(&self, msg: &Message) -> std::io::Result<Message> {
+        let mut reply = reply_to(msg.clone());
+        let name = reply.questions[0].name.clone();
+        reply.add_answer(a_record(&name, self.0));
+        Ok(reply)
+    }
+}
+
+#[test]
+fn queries_are_forwarded_to_any_upstream() {
+    let config = Config { upstreams: Some(Box::new(Canned(Ipv4Addr::new(192, 0, 2, 9)))), ..Config::default() };
+
+    let response = exchange(&config, &query(4, &[("example.com", ResourceType::A), ("example.org", ResourceType::A)]));
+
+    assert_eq!(response.header.id, 4);
+    assert_eq!(response.header.flags.ra, 1);
+    assert_eq!(response.answers.len(), 2);
+    assert!(response.answers.iter().all(|answer| answer.rdata == [192, 0, 2, 9]));
+}
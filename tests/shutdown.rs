@@ -0,0 +1,49 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{query, ServerProcess};
+use dns_starter_rust::ResourceType;
+
+#[test]
+fn sigterm_stops_the_server_cleanly() {
+    let mut server = ServerProcess::start(&[]);
+
+    // the server is listening, but make sure it answers so the signal
+    // handler is in place
+    server.exchange(&query(1, &[("example.com", ResourceType::A)]));
+
+    let status = Command::new("kill").args(["-TERM", &server.child.id().to_string()]).status().unwrap();
+    assert!(status.success());
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    loop {
+        if let Some(status) = server.child.try_wait().unwrap() {
+            assert!(status.success(), "server exited with {}", status);
+            break;
+        }
+        if Instant::now() > deadline {
+            panic!("server didn't exit after SIGTERM");
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn sigusr1_dumps_the_cache_without_stopping_the_server() {
+    let mut server = ServerProcess::start(&["--cache"]);
+    server.exchange(&query(1, &[("example.com", ResourceType::A)]));
+
+    let status = Command::new("kill").args(["-USR1", &server.child.id().to_string()]).status().unwrap();
+    assert!(status.success());
+
+    // past a poll of the receive loop, the server is still there to answer
+    thread::sleep(Duration::from_millis(300));
+    assert!(server.child.try_wait().unwrap().is_none());
+    let response = server.exchange(&query(2, &[("example.com", ResourceType::A)]));
+    assert_eq!(response.header.id, 2);
+}
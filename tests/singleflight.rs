@@ -0,0 +1,35 @@
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns_starter_rust::singleflight::SingleFlight;
+
+#[test]
+fn waiters_start_over_when_the_leader_panics() {
+    let flights = Arc::new(SingleFlight::new());
+    let (started, leading) = mpsc::channel();
+
+    let leader = {
+        let flights = flights.clone();
+        thread::spawn(move || {
+            flights.run("example.com", || {
+                started.send(()).unwrap();
+                thread::sleep(Duration::from_millis(100));
+                panic!("resolver blew up");
+            })
+        })
+    };
+    leading.recv().unwrap();
+
+    // joins the leader's flight, then has to do the work itself
+    let waiter = {
+        let flights = flights.clone();
+        thread::spawn(move || flights.run("example.com", || 7))
+    };
+
+    assert!(leader.join().is_err());
+    assert_eq!(waiter.join().unwrap(), 7);
+    // and the key is free for whoever comes next
+    assert_eq!(flights.run("example.com", || 8), 8);
+}
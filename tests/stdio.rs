@@ -0,0 +1,52 @@
+mod common;
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use common::query;
+use dns_starter_rust::{Message, MessageType, ResourceType};
+
+fn run_stdio(args: &[&str], input: &[u8]) -> std::process::Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dns-starter-rust"))
+        .arg("--stdio")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn stdio_mode_answers_the_query_on_stdin() {
+    let output = run_stdio(&["--default-a", "192.0.2.1"], &query(7, &[("codecrafters.io", ResourceType::A)]).to_bytes());
+
+    assert!(output.status.success());
+    let response = Message::parse(&output.stdout).unwrap();
+    assert_eq!(response.header.id, 7);
+    assert_eq!(response.header.flags.qr, MessageType::Reply);
+    assert_eq!(response.answers[0].name.name, "codecrafters.io");
+    assert_eq!(response.answers[0].rdata, vec![192, 0, 2, 1]);
+}
+
+#[test]
+fn stdio_mode_fails_on_a_truncated_header() {
+    let output = run_stdio(&[], &[0x12, 0x34]);
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn json_query_log_stays_out_of_the_reply() {
+    let query = query(7, &[("codecrafters.io", ResourceType::A)]);
+    let output = run_stdio(&["--log-format", "json"], &query.to_bytes());
+
+    assert!(output.status.success());
+    // stdout is exactly one DNS message, nothing before or after it
+    let response = Message::parse(&output.stdout).unwrap();
+    assert_eq!(output.stdout, response.to_bytes_compressed());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("\"name\":\"codecrafters.io\""));
+}
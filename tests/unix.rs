@@ -0,0 +1,31 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::os::unix::net::UnixStream;
+
+use common::{query, temp_file, ServerProcess};
+use dns_starter_rust::{tcp, MessageType, ResourceType};
+
+#[test]
+fn queries_are_answered_over_a_unix_socket() {
+    // a unique path, left free for the server to bind
+    let path = temp_file("");
+    fs::remove_file(&path).unwrap();
+    let path = path.to_str().unwrap();
+    let server = ServerProcess::start(&["--unix-socket", path, "--default-a", "192.0.2.1"]);
+
+    let mut stream = UnixStream::connect(path).unwrap();
+    for id in [7, 8] {
+        tcp::send(&mut stream, &query(id, &[("codecrafters.io", ResourceType::A)])).unwrap();
+        let response = tcp::recv(&mut stream).unwrap();
+
+        assert_eq!(response.header.id, id);
+        assert_eq!(response.header.flags.qr, MessageType::Reply);
+        assert_eq!(response.answers[0].rdata, vec![192, 0, 2, 1]);
+    }
+
+    drop(server);
+    let _ = fs::remove_file(path);
+}
@@ -0,0 +1,433 @@
+mod common;
+
+use std::net::{Ipv4Addr, TcpListener, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use common::{a_record, query, reply_to, MockResolver};
+use dns_starter_rust::server::forward_query_timeout;
+use dns_starter_rust::upstream::{parse_resolver_spec, parse_resolver_spec_with_port, Protocol, Upstreams};
+use dns_starter_rust::{tcp, Answer, Message, Name, ResourceClass, ResourceType, ResponseCode};
+
+#[test]
+fn failing_resolver_is_skipped_and_restored_after_recovery() {
+    let healthy = Arc::new(AtomicBool::new(false));
+    let flaky_health = healthy.clone();
+    let flaky = MockResolver::start(move |query| {
+        let mut reply = reply_to(query);
+        if !flaky_health.load(Ordering::SeqCst) {
+            reply.header.flags.rcode = ResponseCode::ServFail as u8;
+        }
+        Some(reply)
+    });
+    let backup = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+
+    let upstreams = Upstreams::new(vec![flaky.addr.clone(), backup.addr.clone()])
+        .with_circuit_breaker(2, Duration::from_millis(200));
+
+    for _ in 0..2 {
+        let response = upstreams.forward(&query(7, &[("example.com", ResourceType::A)])).unwrap();
+        assert_eq!(response.header.ancount, 1);
+    }
+    assert_eq!(flaky.queries(), 2);
+    let health = upstreams.health(&flaky.addr).unwrap();
+    assert_eq!((health.successes, health.failures), (0, 2));
+    assert!(health.ejected());
+    assert_eq!(health.average_latency(), None);
+
+    // the circuit is open: the flaky resolver isn't even asked
+    upstreams.forward(&query(7, &[("example.com", ResourceType::A)])).unwrap();
+    assert_eq!(flaky.queries(), 2);
+    assert_eq!(backup.queries(), 3);
+    let health = upstreams.health(&backup.addr).unwrap();
+    assert_eq!((health.successes, health.failures), (3, 0));
+    assert!(health.average_latency().unwrap() > Duration::ZERO);
+
+    healthy.store(true, Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(250));
+
+    // after the cooldown a probe goes through and closes the circuit
+    upstreams.forward(&query(7, &[("example.com", ResourceType::A)])).unwrap();
+    upstreams.forward(&query(7, &[("example.com", ResourceType::A)])).unwrap();
+    assert_eq!(flaky.queries(), 4);
+    assert_eq!(backup.queries(), 3);
+    let health = upstreams.health(&flaky.addr).unwrap();
+    assert_eq!(health.consecutive_failures, 0);
+    assert!(!health.ejected());
+    assert_eq!(health.successes, 2);
+}
+
+#[test]
+fn weighted_resolvers_share_traffic_in_proportion() {
+    let light = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let heavy = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 2)]);
+
+    let upstreams = Upstreams::weighted(vec![
+        parse_resolver_spec(&format!("{}:1", light.addr)).unwrap(),
+        parse_resolver_spec(&format!("{}:3", heavy.addr)).unwrap(),
+    ]);
+
+    let total = 800;
+    for id in 0..total {
+        upstreams.forward(&query(id, &[("example.com", ResourceType::A)])).unwrap();
+    }
+
+    let heavy_share = heavy.queries() as f64 / total as f64;
+    assert_eq!(light.queries() + heavy.queries(), total as usize);
+    assert!((0.65..0.85).contains(&heavy_share), "heavy resolver got {:.2} of the traffic", heavy_share);
+}
+
+#[test]
+fn resolver_spec_weight_is_optional() {
+    assert_eq!(parse_resolver_spec("1.1.1.1:53").unwrap(), ("1.1.1.1:53".to_string(), None));
+    assert_eq!(parse_resolver_spec("1.1.1.1:53:5").unwrap(), ("1.1.1.1:53".to_string(), Some(5)));
+    assert!(parse_resolver_spec("1.1.1.1:53:0").is_err());
+}
+
+#[test]
+fn resolver_without_a_port_is_asked_on_53() {
+    assert_eq!(parse_resolver_spec("1.1.1.1").unwrap(), ("1.1.1.1:53".to_string(), None));
+    assert_eq!(parse_resolver_spec("2606:4700::1111").unwrap(), ("[2606:4700::1111]:53".to_string(), None));
+    assert_eq!(parse_resolver_spec("[::1]").unwrap(), ("[::1]:53".to_string(), None));
+    assert_eq!(parse_resolver_spec_with_port("1.1.1.1", 5353).unwrap(), ("1.1.1.1:5353".to_string(), None));
+    assert_eq!(parse_resolver_spec_with_port("1.1.1.1:53", 5353).unwrap(), ("1.1.1.1:53".to_string(), None));
+    assert!(parse_resolver_spec("resolver.example").is_err());
+}
+
+#[test]
+fn lost_packets_are_retried_under_a_fresh_id() {
+    let seen_ids = Arc::new(Mutex::new(Vec::new()));
+    let ids = seen_ids.clone();
+    let lossy = MockResolver::start(move |query| {
+        let mut ids = ids.lock().unwrap();
+        ids.push(query.header.id);
+        // drop the first packet on the floor
+        (ids.len() > 1).then(|| reply_to(query))
+    });
+
+    let upstreams = Upstreams::new(vec![lossy.addr.clone()]).with_timeout(Duration::from_millis(100));
+    let response = upstreams.forward(&query(7, &[("example.com", ResourceType::A)])).unwrap();
+
+    assert_eq!(response.header.id, 7);
+    assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8);
+    let ids = seen_ids.lock().unwrap();
+    assert_eq!(ids.len(), 2);
+    assert_ne!(ids[0], ids[1]);
+    assert_eq!(upstreams.health(&lossy.addr).unwrap().failures, 0);
+}
+
+#[test]
+fn retries_are_bounded_before_failing_over() {
+    let silent = MockResolver::start(|_| None);
+    let backup = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+
+    let upstreams = Upstreams::new(vec![silent.addr.clone(), backup.addr.clone()])
+        .with_timeout(Duration::from_millis(50))
+        .with_retries(1);
+    upstreams.forward(&query(7, &[("example.com", ResourceType::A)])).unwrap();
+
+    assert_eq!(silent.queries(), 2);
+    assert_eq!(backup.queries(), 1);
+}
+
+#[test]
+fn reply_from_another_address_is_ignored_even_with_the_right_id() {
+    let resolver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = resolver.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        let spoofer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut buf = [0; 512];
+        while let Ok((size, source)) = resolver.recv_from(&mut buf) {
+            let query = Message::parse(&buf[..size]).unwrap();
+            let name = query.questions[0].name.clone();
+
+            // an attacker who guessed the ID answers first
+            let mut forged = reply_to(query.clone());
+            forged.add_answer(a_record(&name, Ipv4Addr::new(6, 6, 6, 6)));
+            spoofer.send_to(&forged.to_bytes(), source).unwrap();
+
+            thread::sleep(Duration::from_millis(50));
+            let mut reply = reply_to(query);
+            reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 1)));
+            resolver.send_to(&reply.to_bytes(), source).unwrap();
+        }
+    });
+
+    let upstreams = Upstreams::new(vec![addr]);
+    let response = upstreams.forward(&query(7, &[("example.com", ResourceType::A)])).unwrap();
+
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+}
+
+#[test]
+fn forwarding_sockets_are_pooled_and_reused() {
+    let upstream = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let upstreams = Arc::new(Upstreams::new(vec![upstream.addr.clone()]));
+
+    for id in 0..500 {
+        let response = upstreams.forward(&query(id, &[("example.com", ResourceType::A)])).unwrap();
+        assert_eq!(response.header.id, id);
+    }
+    assert_eq!(upstreams.idle_sockets(), 1);
+
+    // concurrent queries each get their own socket, but no more than that
+    let workers: Vec<_> = (0..4).map(|worker| {
+        let upstreams = upstreams.clone();
+        thread::spawn(move || {
+            for i in 0..100 {
+                let id = worker * 1000 + i;
+                let response = upstreams.forward(&query(id, &[("example.com", ResourceType::A)])).unwrap();
+                assert_eq!(response.header.id, id);
+            }
+        })
+    }).collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    assert!((1..=4).contains(&upstreams.idle_sockets()));
+}
+
+#[test]
+fn queries_can_be_forwarded_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reply = reply_to(tcp::recv(&mut stream).unwrap());
+            let name = reply.questions[0].name.clone();
+            reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 6)));
+            tcp::send(&mut stream, &reply).unwrap();
+        }
+    });
+
+    let upstreams = Upstreams::new(vec![addr]).with_protocol(Protocol::Tcp);
+    let response = upstreams.forward(&query(7, &[("example.com", ResourceType::A)])).unwrap();
+
+    assert_eq!(response.header.id, 7);
+    assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 6]);
+    assert_eq!("tcp".parse(), Ok(Protocol::Tcp));
+    assert!("quic".parse::<Protocol>().is_err());
+}
+
+#[test]
+fn truncated_udp_reply_is_retried_over_tcp() {
+    // UDP and TCP on the same port, like a real resolver
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = udp.local_addr().unwrap();
+    let listener = TcpListener::bind(addr).unwrap();
+
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        while let Ok((size, source)) = udp.recv_from(&mut buf) {
+            let mut reply = reply_to(Message::parse(&buf[..size]).unwrap());
+            reply.header.flags.tc = 1;
+            udp.send_to(&reply.to_bytes(), source).unwrap();
+        }
+    });
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reply = reply_to(tcp::recv(&mut stream).unwrap());
+            let name = reply.questions[0].name.clone();
+            for last in 1..=40 {
+                reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, last)));
+            }
+            tcp::send(&mut stream, &reply).unwrap();
+        }
+    });
+
+    let upstreams = Upstreams::new(vec![addr.to_string()]);
+    let response = upstreams.forward(&query(8, &[("big.example", ResourceType::A)])).unwrap();
+
+    assert_eq!(response.header.id, 8);
+    assert_eq!(response.header.flags.tc, 0);
+    assert_eq!(response.answers.len(), 40);
+}
+
+#[test]
+fn only_one_probe_goes_out_after_the_cooldown() {
+    let healthy = Arc::new(AtomicBool::new(false));
+    let flaky_health = healthy.clone();
+    let flaky = MockResolver::start(move |query| {
+        let mut reply = reply_to(query);
+        if flaky_health.load(Ordering::SeqCst) {
+            // slow enough that the other queries arrive while it's out
+            thread::sleep(Duration::from_millis(300));
+        } else {
+            reply.header.flags.rcode = ResponseCode::ServFail as u8;
+        }
+        Some(reply)
+    });
+    let backup = MockResolver::answering(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    let upstreams = Arc::new(Upstreams::new(vec![flaky.addr.clone(), backup.addr.clone()])
+        .with_circuit_breaker(1, Duration::from_millis(100)));
+
+    upstreams.forward(&query(1, &[("example.com", ResourceType::A)])).unwrap();
+    assert!(upstreams.health(&flaky.addr).unwrap().ejected());
+
+    healthy.store(true, Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(150));
+
+    // distinct names, so nothing is coalesced before reaching the breaker
+    let clients: Vec<_> = (0..5).map(|id| {
+        let upstreams = upstreams.clone();
+        let name = format!("{}.example.com", id);
+        let client = thread::spawn(move || upstreams.forward(&query(id, &[(&name, ResourceType::A)])).unwrap());
+        thread::sleep(Duration::from_millis(20));
+        client
+    }).collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+
+    assert_eq!(flaky.queries(), 2);
+    assert_eq!(backup.queries(), 5);
+    assert!(!upstreams.health(&flaky.addr).unwrap().ejected());
+}
+
+#[test]
+fn latency_is_summed_over_successes_only() {
+    let calls = Arc::new(Mutex::new(0));
+    let counter = calls.clone();
+    let upstream = MockResolver::start(move |query| {
+        let mut calls = counter.lock().unwrap();
+        *calls += 1;
+        let mut reply = reply_to(query);
+        if *calls == 1 {
+            // a quick failure mustn't drag the average down
+            reply.header.flags.rcode = ResponseCode::ServFail as u8;
+        } else {
+            thread::sleep(Duration::from_millis(50));
+        }
+        Some(reply)
+    });
+    let upstreams = Upstreams::new(vec![upstream.addr.clone()]);
+
+    for id in 0..3 {
+        upstreams.forward(&query(id, &[("example.com", ResourceType::A)])).unwrap();
+    }
+
+    let health = upstreams.health(&upstream.addr).unwrap();
+    assert_eq!((health.successes, health.failures), (2, 1));
+    assert!(health.latency >= Duration::from_millis(100), "{:?}", health.latency);
+    let average = health.average_latency().unwrap();
+    assert!(average >= Duration::from_millis(50) && average < Duration::from_secs(1), "{:?}", average);
+}
+
+#[test]
+fn udp_reply_larger_than_512_bytes_arrives_whole() {
+    let upstream = MockResolver::start(|query| {
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.clone();
+        for last in 1..=40 {
+            reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, last)));
+        }
+        assert!(reply.to_bytes().len() > 512);
+        Some(reply)
+    });
+
+    let response = forward_query_timeout(&query(9, &[("big.example", ResourceType::A)]), &upstream.addr, Duration::from_secs(2)).unwrap();
+
+    assert_eq!(response.header.id, 9);
+    assert_eq!(response.answers.len(), 40);
+    assert_eq!(response.answers[39].rdata, vec![10, 0, 0, 40]);
+}
+
+#[test]
+fn identical_concurrent_queries_share_one_upstream_request() {
+    let slow = MockResolver::start(|query| {
+        thread::sleep(Duration::from_millis(200));
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.clone();
+        reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 9)));
+        Some(reply)
+    });
+    let upstreams = Arc::new(Upstreams::new(vec![slow.addr.clone()]));
+
+    let clients: Vec<_> = (0..8).map(|id| {
+        let upstreams = upstreams.clone();
+        thread::spawn(move || upstreams.forward(&query(id, &[("popular.example", ResourceType::A)])).unwrap())
+    }).collect();
+
+    for (id, client) in clients.into_iter().enumerate() {
+        let response = client.join().unwrap();
+        assert_eq!(response.header.id, id as u16);
+        assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 9]);
+    }
+    assert_eq!(slow.queries(), 1);
+}
+
+#[test]
+fn concurrent_queries_differing_in_do_are_not_coalesced() {
+    let slow = MockResolver::start(|query| {
+        thread::sleep(Duration::from_millis(200));
+        let dnssec_ok = query.dnssec_ok();
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.clone();
+        reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, dnssec_ok as u8)));
+        Some(reply)
+    });
+    let upstreams = Arc::new(Upstreams::new(vec![slow.addr.clone()]));
+
+    let clients: Vec<_> = [false, true].into_iter().map(|dnssec_ok| {
+        let upstreams = upstreams.clone();
+        thread::spawn(move || {
+            let mut msg = query(1, &[("popular.example", ResourceType::A)]);
+            if dnssec_ok {
+                msg.add_additional(Answer { name: Name::new(""), rtype: ResourceType::OPT, class: ResourceClass::Unknown(1232), ttl: 0x8000, rdlength: 0, rdata: Vec::new() });
+            }
+            upstreams.forward(&msg).unwrap()
+        })
+    }).collect();
+
+    // each got the answer fetched for its own DO bit
+    for (dnssec_ok, client) in clients.into_iter().enumerate() {
+        assert_eq!(client.join().unwrap().answers[0].rdata, vec![10, 0, 0, dnssec_ok as u8]);
+    }
+    assert_eq!(slow.queries(), 2);
+}
+
+#[test]
+fn case_randomization_is_echoed_and_hidden_from_the_client() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log = seen.clone();
+    let upstream = MockResolver::start(move |query| {
+        let name = query.questions[0].name.clone();
+        log.lock().unwrap().push(name.name.clone());
+        let mut reply = reply_to(query);
+        reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 1)));
+        Some(reply)
+    });
+    let upstreams = Upstreams::new(vec![upstream.addr.clone()]).with_case_randomization(true);
+
+    // long enough that keeping every letter's case by chance won't happen
+    let asked = "Resolver.Case.Randomization.Example.com";
+    let response = upstreams.forward(&query(7, &[(asked, ResourceType::A)])).unwrap();
+
+    let sent = seen.lock().unwrap()[0].clone();
+    assert_ne!(sent, asked);
+    assert_eq!(sent.to_ascii_lowercase(), asked.to_ascii_lowercase());
+    assert_eq!(response.questions[0].name.name, asked);
+    assert_eq!(response.answers[0].name.name, asked);
+}
+
+#[test]
+fn case_randomization_rejects_replies_that_change_the_case() {
+    let upstream = MockResolver::start(|query| {
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.name.to_ascii_lowercase();
+        reply.questions[0].name = Name::new(&name);
+        Some(reply)
+    });
+
+    let upstreams = Upstreams::new(vec![upstream.addr.clone()]);
+    assert!(upstreams.forward(&query(7, &[("Example.COM", ResourceType::A)])).is_ok());
+
+    let upstreams = upstreams.with_case_randomization(true);
+    let err = upstreams.forward(&query(7, &[("Resolver.Case.Randomization.Example.com", ResourceType::A)])).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
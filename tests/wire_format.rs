@@ -0,0 +1,282 @@
+// Golden wire-format fixtures, one per record type: a response with a
+// single answer, byte for byte as RFC 1035 (and RFC 2782, RFC 3596) lay
+// it out. Parsing each one and serializing it again must reproduce it
+// exactly. Names are written out in full, as to_bytes doesn't compress.
+
+use dns_starter_rust::{Message, ResourceType};
+
+/// A response to one question for `qname` of type `rtype`, in class IN,
+/// answered by `record`.
+fn response(qname: &[u8], rtype: ResourceType, record: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![
+        0x12, 0x34, // ID
+        0x81, 0x80, // QR=1, opcode QUERY, RD=1, RA=1, RCODE NOERROR
+        0x00, 0x01, // QDCOUNT
+        0x00, 0x01, // ANCOUNT
+        0x00, 0x00, // NSCOUNT
+        0x00, 0x00, // ARCOUNT
+    ];
+    bytes.extend_from_slice(qname);
+    bytes.extend_from_slice(&(rtype as u16).to_be_bytes());
+    bytes.extend_from_slice(&[0x00, 0x01]); // IN
+    bytes.extend_from_slice(record);
+    bytes
+}
+
+/// Asserts that `bytes` survive a parse and serialize unchanged, and that
+/// the answer's RDLENGTH covers exactly the RDATA that ends the packet.
+fn assert_round_trip(bytes: &[u8], rtype: ResourceType) -> Message {
+    let msg = Message::parse(bytes).unwrap();
+    assert_eq!(msg.to_bytes(), bytes);
+
+    let answer = &msg.answers[0];
+    assert_eq!(answer.rtype, rtype);
+    assert_eq!(answer.rdlength as usize, answer.rdata.len());
+    assert_eq!(answer.rdata, bytes[bytes.len() - answer.rdata.len()..]);
+    msg
+}
+
+const EXAMPLE_COM: &[u8] = b"\x07example\x03com\x00";
+
+#[test]
+fn a_record() {
+    let record = [
+        EXAMPLE_COM,
+        &[0x00, 0x01],             // TYPE A
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x00, 0x0e, 0x10], // TTL 3600
+        &[0x00, 0x04],             // RDLENGTH
+        &[93, 184, 216, 34],       // 93.184.216.34
+    ].concat();
+    assert_round_trip(&response(EXAMPLE_COM, ResourceType::A, &record), ResourceType::A);
+}
+
+#[test]
+fn aaaa_record() {
+    let record = [
+        EXAMPLE_COM,
+        &[0x00, 0x1c],             // TYPE AAAA
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x00, 0x0e, 0x10], // TTL 3600
+        &[0x00, 0x10],             // RDLENGTH
+        // 2606:2800:220:1:248:1893:25c8:1946
+        &[0x26, 0x06, 0x28, 0x00, 0x02, 0x20, 0x00, 0x01, 0x02, 0x48, 0x18, 0x93, 0x25, 0xc8, 0x19, 0x46],
+    ].concat();
+    assert_round_trip(&response(EXAMPLE_COM, ResourceType::AAAA, &record), ResourceType::AAAA);
+}
+
+#[test]
+fn cname_record() {
+    let www = b"\x03www\x07example\x03com\x00";
+    let record = [
+        &www[..],
+        &[0x00, 0x05],             // TYPE CNAME
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x00, 0x01, 0x2c], // TTL 300
+        &[0x00, 0x13],             // RDLENGTH 19
+        b"\x05alias\x07example\x03net\x00",
+    ].concat();
+    let msg = assert_round_trip(&response(www, ResourceType::CNAME, &record), ResourceType::CNAME);
+    assert_eq!(msg.answers[0].cname().unwrap().name, "alias.example.net");
+}
+
+#[test]
+fn mx_record() {
+    let record = [
+        EXAMPLE_COM,
+        &[0x00, 0x0f],             // TYPE MX
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x00, 0x0e, 0x10], // TTL 3600
+        &[0x00, 0x14],             // RDLENGTH 20
+        &[0x00, 0x0a],             // PREFERENCE 10
+        b"\x04mail\x07example\x03com\x00",
+    ].concat();
+    assert_round_trip(&response(EXAMPLE_COM, ResourceType::MX, &record), ResourceType::MX);
+}
+
+#[test]
+fn ns_record() {
+    let record = [
+        EXAMPLE_COM,
+        &[0x00, 0x02],             // TYPE NS
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x01, 0x51, 0x80], // TTL 86400
+        &[0x00, 0x14],             // RDLENGTH 20
+        b"\x01a\x0ciana-servers\x03net\x00",
+    ].concat();
+    let msg = assert_round_trip(&response(EXAMPLE_COM, ResourceType::NS, &record), ResourceType::NS);
+    assert_eq!(msg.answers[0].ns().unwrap().name, "a.iana-servers.net");
+}
+
+#[test]
+fn soa_record() {
+    let record = [
+        EXAMPLE_COM,
+        &[0x00, 0x06],             // TYPE SOA
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x00, 0x0e, 0x10], // TTL 3600
+        &[0x00, 0x3c],             // RDLENGTH 60
+        b"\x02ns\x07example\x03com\x00",         // MNAME
+        b"\x0ahostmaster\x07example\x03com\x00", // RNAME
+        &[0x78, 0xa3, 0xf1, 0x75], // SERIAL 2024010101
+        &[0x00, 0x00, 0x1c, 0x20], // REFRESH 7200
+        &[0x00, 0x00, 0x0e, 0x10], // RETRY 3600
+        &[0x00, 0x12, 0x75, 0x00], // EXPIRE 1209600
+        &[0x00, 0x00, 0x0e, 0x10], // MINIMUM 3600
+    ].concat();
+    assert_round_trip(&response(EXAMPLE_COM, ResourceType::SOA, &record), ResourceType::SOA);
+}
+
+#[test]
+fn txt_record() {
+    let record = [
+        EXAMPLE_COM,
+        &[0x00, 0x10],             // TYPE TXT
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x00, 0x0e, 0x10], // TTL 3600
+        &[0x00, 0x12],             // RDLENGTH 18
+        b"\x0bv=spf1 -all",        // one <character-string> after another
+        b"\x05hello",
+    ].concat();
+    assert_round_trip(&response(EXAMPLE_COM, ResourceType::TXT, &record), ResourceType::TXT);
+}
+
+#[test]
+fn ptr_record() {
+    let reverse = b"\x0234\x03216\x03184\x0293\x07in-addr\x04arpa\x00";
+    let record = [
+        &reverse[..],
+        &[0x00, 0x0c],             // TYPE PTR
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x00, 0x0e, 0x10], // TTL 3600
+        &[0x00, 0x0d],             // RDLENGTH 13
+        EXAMPLE_COM,
+    ].concat();
+    assert_round_trip(&response(reverse, ResourceType::PTR, &record), ResourceType::PTR);
+}
+
+#[test]
+fn srv_record() {
+    let service = b"\x04_sip\x04_tcp\x07example\x03com\x00";
+    let record = [
+        &service[..],
+        &[0x00, 0x21],             // TYPE SRV
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x00, 0x0e, 0x10], // TTL 3600
+        &[0x00, 0x17],             // RDLENGTH 23
+        &[0x00, 0x0a],             // PRIORITY 10
+        &[0x00, 0x3c],             // WEIGHT 60
+        &[0x13, 0xc4],             // PORT 5060
+        b"\x03sip\x07example\x03com\x00",
+    ].concat();
+    assert_round_trip(&response(service, ResourceType::SRV, &record), ResourceType::SRV);
+}
+
+#[test]
+fn compressed_names_in_rdata_are_written_out_in_full() {
+    let www = b"\x03www\x07example\x03com\x00";
+    // the CNAME target points back at "example.com" in the question
+    let compressed = [
+        &[0xc0, 0x0c][..],         // NAME: pointer to the question's name
+        &[0x00, 0x05],             // TYPE CNAME
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x00, 0x01, 0x2c], // TTL 300
+        &[0x00, 0x07],             // RDLENGTH 7
+        b"\x04edge\xc0\x10",       // "edge" + pointer to "example.com"
+    ].concat();
+    let expanded = [
+        &www[..],
+        &[0x00, 0x05],
+        &[0x00, 0x01],
+        &[0x00, 0x00, 0x01, 0x2c],
+        &[0x00, 0x12],             // RDLENGTH 18, the target written out
+        b"\x04edge\x07example\x03com\x00",
+    ].concat();
+
+    let msg = Message::parse(&response(www, ResourceType::CNAME, &compressed)).unwrap();
+    assert_eq!(msg.to_bytes(), response(www, ResourceType::CNAME, &expanded));
+}
+
+#[test]
+fn compressed_mx_exchange_is_written_out_in_full() {
+    let compressed = [
+        &[0xc0, 0x0c][..],         // NAME: pointer to the question's name
+        &[0x00, 0x0f],             // TYPE MX
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x00, 0x0e, 0x10], // TTL 3600
+        &[0x00, 0x09],             // RDLENGTH 9
+        &[0x00, 0x0a],             // PREFERENCE 10
+        b"\x04mail\xc0\x0c",       // "mail" + pointer to "example.com"
+    ].concat();
+    let expanded = [
+        EXAMPLE_COM,
+        &[0x00, 0x0f],
+        &[0x00, 0x01],
+        &[0x00, 0x00, 0x0e, 0x10],
+        &[0x00, 0x14],             // RDLENGTH 20, the exchange written out
+        &[0x00, 0x0a],
+        b"\x04mail\x07example\x03com\x00",
+    ].concat();
+
+    let msg = Message::parse(&response(EXAMPLE_COM, ResourceType::MX, &compressed)).unwrap();
+    let bytes = msg.to_bytes();
+    assert_eq!(bytes, response(EXAMPLE_COM, ResourceType::MX, &expanded));
+    // and what comes out stands on its own
+    assert_round_trip(&bytes, ResourceType::MX);
+}
+
+#[test]
+fn compressed_soa_names_are_written_out_in_full() {
+    let numbers = [
+        &[0x78, 0xa3, 0xf1, 0x75][..], // SERIAL 2024010101
+        &[0x00, 0x00, 0x1c, 0x20],     // REFRESH 7200
+        &[0x00, 0x00, 0x0e, 0x10],     // RETRY 3600
+        &[0x00, 0x12, 0x75, 0x00],     // EXPIRE 1209600
+        &[0x00, 0x00, 0x0e, 0x10],     // MINIMUM 3600
+    ].concat();
+    let compressed = [
+        &[0xc0, 0x0c][..],         // NAME: pointer to the question's name
+        &[0x00, 0x06],             // TYPE SOA
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x00, 0x0e, 0x10], // TTL 3600
+        &[0x00, 0x26],             // RDLENGTH 38
+        b"\x02ns\xc0\x0c",         // MNAME: "ns" + pointer to "example.com"
+        b"\x0ahostmaster\xc0\x0c", // RNAME: "hostmaster" + the same pointer
+        &numbers,
+    ].concat();
+    let expanded = [
+        EXAMPLE_COM,
+        &[0x00, 0x06],
+        &[0x00, 0x01],
+        &[0x00, 0x00, 0x0e, 0x10],
+        &[0x00, 0x3c],             // RDLENGTH 60, both names written out
+        b"\x02ns\x07example\x03com\x00",
+        b"\x0ahostmaster\x07example\x03com\x00",
+        &numbers,
+    ].concat();
+
+    let msg = Message::parse(&response(EXAMPLE_COM, ResourceType::SOA, &compressed)).unwrap();
+    let bytes = msg.to_bytes();
+    assert_eq!(bytes, response(EXAMPLE_COM, ResourceType::SOA, &expanded));
+    assert_round_trip(&bytes, ResourceType::SOA);
+}
+
+#[test]
+fn compressed_output_points_repeated_names_at_their_first_occurrence() {
+    let www = b"\x03www\x07example\x03com\x00";
+    let record = [
+        &www[..],
+        &[0x00, 0x01],             // TYPE A
+        &[0x00, 0x01],             // CLASS IN
+        &[0x00, 0x00, 0x0e, 0x10], // TTL 3600
+        &[0x00, 0x04],             // RDLENGTH
+        &[192, 0, 2, 1],
+    ].concat();
+    let msg = Message::parse(&response(www, ResourceType::A, &record)).unwrap();
+
+    let compressed = [
+        &[0xc0, 0x0c][..],         // NAME: pointer to the question's name
+        &record[www.len()..],
+    ].concat();
+    assert_eq!(msg.to_bytes_compressed(), response(www, ResourceType::A, &compressed));
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use std::net::Ipv4Addr;
+
+use common::{a_record, exchange, query, reply_to, temp_file, MockResolver};
+use dns_starter_rust::server::Config;
+use dns_starter_rust::upstream::Upstreams;
+use dns_starter_rust::zone::Zone;
+use dns_starter_rust::{ResourceType, ResponseCode};
+
+fn zone_config(upstream: &MockResolver) -> Config {
+    let path = temp_file("www.example.com 300 A 192.0.2.10\n");
+    Config {
+        zone: Some(Zone::load(path.to_str().unwrap()).unwrap()),
+        upstreams: Some(Box::new(Upstreams::new(vec![upstream.addr.clone()]))),
+        ..Config::default()
+    }
+}
+
+/// A resolver that (wrongly, for a forwarder's purposes) claims authority.
+fn authoritative_resolver() -> MockResolver {
+    MockResolver::start(|query| {
+        let mut reply = reply_to(query);
+        let name = reply.questions[0].name.clone();
+        reply.header.flags.aa = 1;
+        reply.add_answer(a_record(&name, Ipv4Addr::new(10, 0, 0, 1)));
+        Some(reply)
+    })
+}
+
+#[test]
+fn zone_answers_are_authoritative() {
+    let upstream = authoritative_resolver();
+    let config = zone_config(&upstream);
+
+    let response = exchange(&config, &query(1, &[("WWW.example.com", ResourceType::A)]));
+
+    assert_eq!(response.header.flags.aa, 1);
+    assert_eq!(response.answers[0].rdata, vec![192, 0, 2, 10]);
+    assert_eq!(response.answers[0].ttl, 300);
+    assert_eq!(upstream.queries(), 0);
+}
+
+#[test]
+fn rd_decides_between_forwarding_and_the_zone_alone() {
+    let upstream = authoritative_resolver();
+    let config = zone_config(&upstream);
+    let ask = |id, name, rd| {
+        let mut query = query(id, &[(name, ResourceType::A)]);
+        query.header.flags.rd = rd;
+        exchange(&config, &query)
+    };
+
+    // names in the zone are answered from it, RD or not
+    for rd in [1, 0] {
+        let response = ask(1, "www.example.com", rd);
+        assert_eq!(response.header.flags.rcode, ResponseCode::NoError as u8, "RD={}", rd);
+        assert_eq!(response.answers[0].rdata, vec![192, 0, 2, 10]);
+    }
+    assert_eq!(upstream.queries(), 0);
+
+    // anything else is forwarded with RD=1 and refused with RD=0
+    let forwarded = ask(2, "other.example.com", 1);
+    assert_eq!(forwarded.header.flags.rcode, ResponseCode::NoError as u8);
+    assert_eq!(forwarded.answers[0].rdata, vec![10, 0, 0, 1]);
+    assert_eq!(upstream.queries(), 1);
+
+    let refused = ask(3, "other.example.com", 0);
+    assert_eq!(refused.header.flags.rcode, ResponseCode::Refused as u8);
+    assert!(refused.answers.is_empty());
+    assert_eq!(upstream.queries(), 1);
+}
+
+#[test]
+fn forwarded_answers_are_not_authoritative() {
+    let upstream = authoritative_resolver();
+    let config = zone_config(&upstream);
+
+    let forwarded = exchange(&config, &query(1, &[("other.example.com", ResourceType::A)]));
+    assert_eq!(forwarded.header.flags.aa, 0);
+    assert_eq!(forwarded.answers[0].rdata, vec![10, 0, 0, 1]);
+
+    // one forwarded question is enough to lose authority over the reply
+    let mixed = exchange(&config, &query(2, &[("www.example.com", ResourceType::A), ("other.example.com", ResourceType::A)]));
+    assert_eq!(mixed.header.flags.aa, 0);
+    assert_eq!(mixed.answers.len(), 2);
+}
+
+#[test]
+fn synthesized_answers_are_not_authoritative() {
+    let response = exchange(&Config::default(), &query(1, &[("www.example.com", ResourceType::A)]));
+
+    assert_eq!(response.header.flags.aa, 0);
+}
+
+#[test]
+fn zone_answers_without_a_resolver_are_authoritative_but_not_recursive() {
+    let path = temp_file("www.example.com 300 A 192.0.2.10\n");
+    let config = Config { zone: Some(Zone::load(path.to_str().unwrap()).unwrap()), ..Config::default() };
+
+    for rd in [1, 0] {
+        let mut query = query(1, &[("www.example.com", ResourceType::A)]);
+        query.header.flags.rd = rd;
+
+        let response = exchange(&config, &query);
+
+        assert_eq!(response.header.flags.aa, 1, "RD={}", rd);
+        assert_eq!(response.header.flags.ra, 0, "RD={}", rd);
+        assert_eq!(response.answers[0].rdata, vec![192, 0, 2, 10]);
+    }
+}
+
+#[test]
+fn records_of_one_name_are_rotated_per_query() {
+    let path = temp_file("pool.example.com A 192.0.2.1,192.0.2.2\npool.example.com A 192.0.2.3\n");
+    let config = Config { zone: Some(Zone::load(path.to_str().unwrap()).unwrap()), ..Config::default() };
+
+    let last_octets: Vec<Vec<u8>> = (0..4)
+        .map(|id| exchange(&config, &query(id, &[("pool.example.com", ResourceType::A)])))
+        .map(|response| response.answers.iter().map(|answer| answer.rdata[3]).collect())
+        .collect();
+
+    assert_eq!(last_octets, vec![vec![1, 2, 3], vec![2, 3, 1], vec![3, 1, 2], vec![1, 2, 3]]);
+}